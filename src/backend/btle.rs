@@ -0,0 +1,152 @@
+//! Backend talking to the platform bluetooth stack through btleplug.
+
+use std::collections::BTreeSet;
+
+use async_trait::async_trait;
+use btleplug::api::{
+    BDAddr, Central, CentralEvent, Characteristic, Descriptor, Manager as _,
+    Peripheral as _, PeripheralProperties, ScanFilter, Service, WriteType,
+};
+use btleplug::platform::{Adapter as PlatformAdapter, Manager, Peripheral as PlatformPeripheral};
+use btleplug::Result;
+use tokio_stream::StreamExt;
+
+use super::{Adapter, AdapterEvent, Backend, EventStream, Peripheral};
+
+pub struct BtleplugBackend {
+    manager: Manager,
+}
+
+impl BtleplugBackend {
+    pub async fn new() -> Result<BtleplugBackend> {
+        Ok(BtleplugBackend { manager: Manager::new().await? })
+    }
+}
+
+#[async_trait]
+impl Backend for BtleplugBackend {
+    async fn adapters(&self) -> Result<Vec<Box<dyn Adapter>>> {
+        Ok(self.manager.adapters().await?
+            .into_iter()
+            .map(|a| Box::new(BtleplugAdapter(a)) as Box<dyn Adapter>)
+            .collect())
+    }
+}
+
+struct BtleplugAdapter(PlatformAdapter);
+
+#[async_trait]
+impl Adapter for BtleplugAdapter {
+    async fn info(&self) -> Result<String> {
+        self.0.adapter_info().await
+    }
+
+    async fn start_scan(&self, filter: ScanFilter) -> Result<()> {
+        self.0.start_scan(filter).await
+    }
+
+    async fn stop_scan(&self) -> Result<()> {
+        self.0.stop_scan().await
+    }
+
+    async fn events(&self) -> Result<EventStream<AdapterEvent>> {
+        let central = self.0.clone();
+        let events = self.0.events().await?.filter_map(|e| match e {
+            CentralEvent::DeviceDiscovered(id) => {
+                Some((id, AdapterEvent::DeviceDiscovered as fn(BDAddr) -> AdapterEvent))
+            }
+            CentralEvent::DeviceUpdated(id)
+            | CentralEvent::ManufacturerDataAdvertisement { id, .. }
+            | CentralEvent::ServiceDataAdvertisement { id, .. }
+            | CentralEvent::ServicesAdvertisement { id, .. } => {
+                Some((id, AdapterEvent::DeviceUpdated))
+            }
+            CentralEvent::DeviceConnected(id) => Some((id, AdapterEvent::DeviceConnected)),
+            CentralEvent::DeviceDisconnected(id) => Some((id, AdapterEvent::DeviceDisconnected)),
+            CentralEvent::StateUpdate(_) => None,
+        });
+
+        // btleplug reports peripheral ids, resolve them to addresses
+        let events = events.then(move |(id, kind)| {
+            let central = central.clone();
+            async move {
+                kind(central.peripheral(&id).await.map(|p| p.address()).unwrap_or_default())
+            }
+        });
+
+        Ok(Box::pin(events))
+    }
+
+    async fn peripherals(&self) -> Result<Vec<Box<dyn Peripheral>>> {
+        Ok(self.0.peripherals().await?
+            .into_iter()
+            .map(|p| Box::new(BtleplugPeripheral(p)) as Box<dyn Peripheral>)
+            .collect())
+    }
+}
+
+struct BtleplugPeripheral(PlatformPeripheral);
+
+#[async_trait]
+impl Peripheral for BtleplugPeripheral {
+    fn address(&self) -> BDAddr {
+        self.0.address()
+    }
+
+    async fn properties(&self) -> Result<Option<PeripheralProperties>> {
+        self.0.properties().await
+    }
+
+    async fn is_connected(&self) -> Result<bool> {
+        self.0.is_connected().await
+    }
+
+    async fn connect(&self) -> Result<()> {
+        self.0.connect().await
+    }
+
+    async fn disconnect(&self) -> Result<()> {
+        self.0.disconnect().await
+    }
+
+    async fn discover_services(&self) -> Result<()> {
+        self.0.discover_services().await
+    }
+
+    fn services(&self) -> BTreeSet<Service> {
+        self.0.services()
+    }
+
+    async fn read(&self, characteristic: &Characteristic) -> Result<Vec<u8>> {
+        self.0.read(characteristic).await
+    }
+
+    async fn write(
+        &self,
+        characteristic: &Characteristic,
+        data: &[u8],
+        write_type: WriteType,
+    ) -> Result<()> {
+        self.0.write(characteristic, data, write_type).await
+    }
+
+    async fn read_descriptor(&self, descriptor: &Descriptor) -> Result<Vec<u8>> {
+        self.0.read_descriptor(descriptor).await
+    }
+
+    async fn write_descriptor(&self, descriptor: &Descriptor, data: &[u8]) -> Result<()> {
+        self.0.write_descriptor(descriptor, data).await
+    }
+
+    async fn subscribe(&self, characteristic: &Characteristic) -> Result<()> {
+        self.0.subscribe(characteristic).await
+    }
+
+    async fn unsubscribe(&self, characteristic: &Characteristic) -> Result<()> {
+        self.0.unsubscribe(characteristic).await
+    }
+
+    async fn notifications(&self) -> Result<EventStream<btleplug::api::ValueNotification>> {
+        self.0.notifications().await
+    }
+}
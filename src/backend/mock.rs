@@ -0,0 +1,812 @@
+//! In-memory backend with scriptable peripherals, for running without bluetooth hardware.
+//!
+//! Peripherals are described with a builder and then handed to a [`MockAdapter`]:
+//!
+//! ```
+//! use ble_util::backend::{MockAdapter, MockBackend, MockPeripheral};
+//! use btleplug::api::{bleuuid::uuid_from_u16, CharPropFlags};
+//!
+//! let battery = uuid_from_u16(0x180f);
+//! let level = uuid_from_u16(0x2a19);
+//!
+//! let backend = MockBackend::new().with_adapter(
+//!     MockAdapter::new("hci0").with_peripheral(
+//!         MockPeripheral::new([0xaa, 0xbb, 0xcc, 0xdd, 0xee, 0x01].into())
+//!             .name("sensor")
+//!             .rssi(-42)
+//!             .service(battery)
+//!             .characteristic(battery, level, CharPropFlags::READ | CharPropFlags::NOTIFY, &[87]),
+//!     ),
+//! );
+//! ```
+
+use std::collections::{BTreeSet, HashMap, HashSet};
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::time::Duration;
+
+use async_trait::async_trait;
+use btleplug::api::{
+    bleuuid::uuid_from_u16, AddressType, BDAddr, CharPropFlags, Characteristic, Descriptor,
+    PeripheralProperties, ScanFilter, Service, ValueNotification, WriteType,
+};
+use btleplug::{Error, Result};
+use tokio::sync::broadcast;
+use tokio::time;
+use tokio_stream::wrappers::BroadcastStream;
+use tokio_stream::StreamExt;
+use uuid::Uuid;
+
+use super::{Adapter, AdapterEvent, Backend, EventStream, Peripheral, DEFAULT_MTU};
+
+/// Longest value an attribute can hold, and so be written with a long write.
+const MAX_ATTRIBUTE_LEN: usize = 512;
+
+/// Called with the written bytes, returns the notifications the peripheral answers with.
+pub type WriteHandler = Box<dyn FnMut(&[u8]) -> Vec<ValueNotification> + Send>;
+
+/// Produces the successive values of a periodically notified characteristic.
+pub type ValueGenerator = Box<dyn FnMut() -> Vec<u8> + Send>;
+
+/// Changes the advertisement data of a peripheral before each of its advertisements.
+pub type AdvertisementUpdate = Box<dyn FnMut(&mut PeripheralProperties) + Send>;
+
+#[derive(Default)]
+pub struct MockBackend {
+    adapters: Vec<MockAdapter>,
+}
+
+impl MockBackend {
+    pub fn new() -> MockBackend {
+        MockBackend::default()
+    }
+
+    pub fn with_adapter(mut self, adapter: MockAdapter) -> MockBackend {
+        self.adapters.push(adapter);
+        self
+    }
+
+    /// Two adapters with a few canned devices: a Nordic UART echo device exposing the battery and
+    /// device information services, a sensor that shows up a little later, notifies its
+    /// temperature every second and is also in range of the second adapter, and a device receiving
+    /// files over the Nordic UART service: a 32-bit little endian size, then as many bytes, which
+    /// it acknowledges with their CRC32. Three beacons advertise iBeacon, Eddystone URL and
+    /// AltBeacon frames, the iBeacon every 300 ms with a varying signal strength, and the sensor, a
+    /// RuuviTag and a Xiaomi thermometer their readings.
+    pub fn demo() -> MockBackend {
+        let battery = uuid_from_u16(0x180f);
+        let battery_level = uuid_from_u16(0x2a19);
+        let device_info = uuid_from_u16(0x180a);
+        let manufacturer = uuid_from_u16(0x2a29);
+        let serial_number = uuid_from_u16(0x2a25);
+        let client_config = uuid_from_u16(0x2902);
+        let generic_access = uuid_from_u16(0x1800);
+        let appearance = uuid_from_u16(0x2a01);
+        let environment = uuid_from_u16(0x181a);
+        let temperature = uuid_from_u16(0x2a6e);
+        let nus = Uuid::from_u128(0x6e400001_b5a3_f393_e0a9_e50e24dcca9e);
+        let nus_rx = Uuid::from_u128(0x6e400002_b5a3_f393_e0a9_e50e24dcca9e);
+        let nus_tx = Uuid::from_u128(0x6e400003_b5a3_f393_e0a9_e50e24dcca9e);
+
+        let uart = MockPeripheral::new([0xaa, 0xbb, 0xcc, 0xdd, 0xee, 0x01].into())
+            .name("ble-util mock")
+            .rssi(-48)
+            .tx_power(-4)
+            .advertised_service(nus)
+            .service(battery)
+            .characteristic(battery, battery_level, CharPropFlags::READ | CharPropFlags::NOTIFY, &[100])
+            .descriptor(battery_level, client_config, &[0, 0])
+            .service(device_info)
+            .characteristic(device_info, manufacturer, CharPropFlags::READ, b"ble-util")
+            .characteristic(device_info, serial_number, CharPropFlags::READ, b"0001")
+            .fail(serial_number, "insufficient authentication")
+            .service(nus)
+            .characteristic(nus, nus_rx, CharPropFlags::WRITE | CharPropFlags::WRITE_WITHOUT_RESPONSE, &[])
+            .characteristic(nus, nus_tx, CharPropFlags::READ | CharPropFlags::NOTIFY, &[])
+            .descriptor(nus_tx, client_config, &[0, 0])
+            .on_write(nus_rx, move |data| {
+                vec![ValueNotification { uuid: nus_tx, value: data.to_vec() }]
+            });
+
+        let sensor = MockPeripheral::new([0xaa, 0xbb, 0xcc, 0xdd, 0xee, 0x02].into())
+            .name("ble-util sensor")
+            .address_type(AddressType::Random)
+            .rssi(-71)
+            .manufacturer_data(0x0059, &[0x01, 0x02, 0x03, 0x04])
+            // BTHome v2: battery 87 %, 23.45 °C, 50.02 % humidity
+            .service_data(uuid_from_u16(0xfcd2), &[0x40, 0x01, 87, 0x02, 0x29, 0x09, 0x03, 0x8a, 0x13])
+            .appear_after(Duration::from_millis(500))
+            .service(generic_access)
+            // Thermometer
+            .characteristic(generic_access, appearance, CharPropFlags::READ, &768u16.to_le_bytes())
+            .service(battery)
+            .characteristic(battery, battery_level, CharPropFlags::READ, &[64])
+            .service(environment)
+            .characteristic(environment, temperature, CharPropFlags::READ | CharPropFlags::NOTIFY, &2345i16.to_le_bytes())
+            // sint16, exponent -2, degrees Celsius
+            .descriptor(temperature, uuid_from_u16(0x2904), &[0x0e, 0xfe, 0x2f, 0x27, 0x01, 0x00, 0x00])
+            .periodic(temperature, Duration::from_secs(1), {
+                let mut tick = 0i16;
+                move || {
+                    tick += 1;
+                    (2345 + (tick % 10) * 5).to_le_bytes().to_vec()
+                }
+            });
+
+        let config = MockPeripheral::new([0xaa, 0xbb, 0xcc, 0xdd, 0xee, 0x03].into())
+            .name("ble-util config")
+            .rssi(-60)
+            .mtu(247)
+            .advertised_service(nus)
+            .service(nus)
+            .characteristic(nus, nus_rx, CharPropFlags::WRITE | CharPropFlags::WRITE_WITHOUT_RESPONSE, &[])
+            .characteristic(nus, nus_tx, CharPropFlags::NOTIFY, &[])
+            .descriptor(nus_tx, client_config, &[0, 0])
+            .on_write(nus_rx, {
+                let mut received = Vec::new();
+                move |data| {
+                    received.extend_from_slice(data);
+                    let size = match received.get(..4) {
+                        Some(header) => u32::from_le_bytes(header.try_into().unwrap()) as usize,
+                        None => return Vec::new(),
+                    };
+                    if received.len() < 4 + size {
+                        return Vec::new();
+                    }
+
+                    let crc = crc32fast::hash(&received[4..4 + size]);
+                    received.clear();
+                    vec![ValueNotification { uuid: nus_tx, value: crc.to_le_bytes().to_vec() }]
+                }
+            });
+
+        let beacon_id = Uuid::from_u128(0xe2c56db5_dffb_48d2_b060_d0f5a71096e0);
+        let ibeacon = MockPeripheral::new([0xaa, 0xbb, 0xcc, 0xdd, 0xee, 0x04].into())
+            .address_type(AddressType::Random)
+            .rssi(-65)
+            // Major 1, minor 2, -59 dBm at 1 m
+            .manufacturer_data(0x004c, &[&[0x02, 0x15][..], beacon_id.as_bytes(), &[0, 1, 0, 2, 0xc5]].concat())
+            // Noisy like a real signal, to exercise smoothing
+            .advertise_every(Duration::from_millis(300), {
+                let mut tick = 0;
+                move |properties| {
+                    const NOISE: [i16; 10] = [0, -4, 3, -7, 2, 5, -2, -6, 4, 1];
+                    tick += 1;
+                    properties.rssi = Some(-65 + NOISE[tick % NOISE.len()]);
+                }
+            });
+
+        let eddystone = MockPeripheral::new([0xaa, 0xbb, 0xcc, 0xdd, 0xee, 0x05].into())
+            .address_type(AddressType::Random)
+            .rssi(-75)
+            .advertised_service(uuid_from_u16(0xfeaa))
+            // https://example.com, -18 dBm at 0 m
+            .service_data(uuid_from_u16(0xfeaa), &[&[0x10, 0xee, 0x03][..], b"example", &[0x07]].concat());
+
+        let altbeacon = MockPeripheral::new([0xaa, 0xbb, 0xcc, 0xdd, 0xee, 0x06].into())
+            .address_type(AddressType::Random)
+            .rssi(-80)
+            // Same id as the iBeacon, -59 dBm at 1 m
+            .manufacturer_data(0x0118, &[&[0xbe, 0xac][..], beacon_id.as_bytes(), &[0, 1, 0, 2, 0xc5, 0x00]].concat());
+
+        // Example from the data format 5 specification: 24.3 °C, 53.49 %, 1000.44 hPa...
+        let ruuvi = MockPeripheral::new([0xcb, 0xb8, 0x33, 0x4c, 0x88, 0x4f].into())
+            .name("Ruuvi 884F")
+            .address_type(AddressType::Random)
+            .rssi(-70)
+            .manufacturer_data(0x0499, &[
+                0x05, 0x12, 0xfc, 0x53, 0x94, 0xc3, 0x7c, 0x00, 0x04, 0xff, 0xfc, 0x04, 0x0c, 0xac, 0x36,
+                0x42, 0x00, 0xcd, 0xcb, 0xb8, 0x33, 0x4c, 0x88, 0x4f,
+            ]);
+
+        // pvvx format: 21.5 °C, 45.2 %, 2.95 V, 81 %
+        let thermometer = MockPeripheral::new([0xa4, 0xc1, 0x38, 0x12, 0x34, 0x56].into())
+            .name("ATC_123456")
+            .rssi(-68)
+            .service_data(uuid_from_u16(0x181a), &[
+                0x56, 0x34, 0x12, 0x38, 0xc1, 0xa4, 0x66, 0x08, 0xa8, 0x11, 0x86, 0x0b, 81, 0x01, 0x04,
+            ]);
+
+        MockBackend::new()
+            .with_adapter(
+                MockAdapter::new("mock0 (ble-util mock adapter)")
+                    .with_peripheral(uart)
+                    .with_peripheral(sensor.clone())
+                    .with_peripheral(config)
+                    .with_peripheral(ibeacon)
+                    .with_peripheral(eddystone)
+                    .with_peripheral(altbeacon)
+                    .with_peripheral(ruuvi)
+                    .with_peripheral(thermometer),
+            )
+            .with_adapter(MockAdapter::new("mock1 (ble-util mock dongle)").with_peripheral(sensor))
+    }
+}
+
+#[async_trait]
+impl Backend for MockBackend {
+    async fn adapters(&self) -> Result<Vec<Box<dyn Adapter>>> {
+        Ok(self.adapters.iter()
+            .map(|a| Box::new(a.clone()) as Box<dyn Adapter>)
+            .collect())
+    }
+}
+
+/// A fake controller. Clones share the same state.
+#[derive(Clone)]
+pub struct MockAdapter {
+    inner: Arc<AdapterState>,
+}
+
+struct AdapterState {
+    info: String,
+    peripherals: Mutex<Vec<MockPeripheral>>,
+    /// Addresses that were advertised since the scan started
+    discovered: Mutex<HashSet<BDAddr>>,
+    scanning: Mutex<Option<ScanFilter>>,
+    /// Bumped on each scan, so the advertising tasks of a previous one know to stop
+    scan: Mutex<u64>,
+    events: broadcast::Sender<AdapterEvent>,
+}
+
+impl MockAdapter {
+    pub fn new(info: &str) -> MockAdapter {
+        MockAdapter {
+            inner: Arc::new(AdapterState {
+                info: info.into(),
+                peripherals: Mutex::new(Vec::new()),
+                discovered: Mutex::new(HashSet::new()),
+                scanning: Mutex::new(None),
+                scan: Mutex::new(0),
+                events: broadcast::channel(256).0,
+            }),
+        }
+    }
+
+    pub fn with_peripheral(self, peripheral: MockPeripheral) -> MockAdapter {
+        self.add_peripheral(peripheral);
+        self
+    }
+
+    /// Adds a peripheral, which starts advertising right away if a scan is running.
+    pub fn add_peripheral(&self, peripheral: MockPeripheral) {
+        lock(&self.inner.peripherals).push(peripheral.clone());
+
+        if let Some(filter) = lock(&self.inner.scanning).clone() {
+            self.advertise(peripheral, filter);
+        }
+    }
+
+    /// Updates the advertisement data of a peripheral and reports it to scanners.
+    pub fn update_properties(&self, addr: BDAddr, f: impl FnOnce(&mut PeripheralProperties)) {
+        let peripherals = lock(&self.inner.peripherals);
+        if let Some(p) = peripherals.iter().find(|p| p.address() == addr) {
+            f(&mut p.state().properties);
+
+            if lock(&self.inner.discovered).contains(&addr) {
+                let _ = self.inner.events.send(AdapterEvent::DeviceUpdated(addr));
+            }
+        }
+    }
+
+    fn advertise(&self, peripheral: MockPeripheral, filter: ScanFilter) {
+        let (delay, services) = {
+            let state = peripheral.state();
+            (state.delay, state.properties.services.clone())
+        };
+
+        if !filter.services.is_empty() && !filter.services.iter().any(|s| services.contains(s)) {
+            return;
+        }
+
+        let inner = self.inner.clone();
+        let scan = *lock(&inner.scan);
+        tokio::spawn(async move {
+            time::sleep(delay).await;
+
+            let scanning = || lock(&inner.scanning).is_some() && *lock(&inner.scan) == scan;
+            if !scanning() {
+                return;
+            }
+
+            let addr = peripheral.address();
+            if lock(&inner.discovered).insert(addr) {
+                let _ = inner.events.send(AdapterEvent::DeviceDiscovered(addr));
+            }
+
+            let interval = match &peripheral.state().readvertising {
+                Some(r) => r.interval,
+                None => return,
+            };
+            loop {
+                time::sleep(interval).await;
+                if !scanning() {
+                    return;
+                }
+
+                {
+                    let mut state = peripheral.state();
+                    let state = &mut *state;
+                    if let Some(r) = state.readvertising.as_mut() {
+                        (r.update)(&mut state.properties);
+                    }
+                }
+                let _ = inner.events.send(AdapterEvent::DeviceUpdated(addr));
+            }
+        });
+    }
+}
+
+#[async_trait]
+impl Adapter for MockAdapter {
+    async fn info(&self) -> Result<String> {
+        Ok(self.inner.info.clone())
+    }
+
+    async fn start_scan(&self, filter: ScanFilter) -> Result<()> {
+        *lock(&self.inner.scanning) = Some(filter.clone());
+        *lock(&self.inner.scan) += 1;
+
+        let peripherals = lock(&self.inner.peripherals).clone();
+        for p in peripherals {
+            self.advertise(p, filter.clone());
+        }
+
+        Ok(())
+    }
+
+    async fn stop_scan(&self) -> Result<()> {
+        *lock(&self.inner.scanning) = None;
+        Ok(())
+    }
+
+    async fn events(&self) -> Result<EventStream<AdapterEvent>> {
+        let rx = self.inner.events.subscribe();
+        Ok(Box::pin(BroadcastStream::new(rx).filter_map(|e| e.ok())))
+    }
+
+    async fn peripherals(&self) -> Result<Vec<Box<dyn Peripheral>>> {
+        let discovered = lock(&self.inner.discovered);
+        Ok(lock(&self.inner.peripherals).iter()
+            .filter(|p| discovered.contains(&p.address()))
+            .map(|p| Box::new(p.clone()) as Box<dyn Peripheral>)
+            .collect())
+    }
+}
+
+/// A fake remote device with an in-memory GATT database. Clones share the same state, so a test
+/// can keep a handle to inspect writes or push notifications.
+#[derive(Clone)]
+pub struct MockPeripheral {
+    inner: Arc<Mutex<PeripheralState>>,
+    notifications: broadcast::Sender<ValueNotification>,
+}
+
+struct PeripheralState {
+    properties: PeripheralProperties,
+    delay: Duration,
+    readvertising: Option<Readvertising>,
+    services: Vec<Service>,
+    connected: bool,
+    mtu: u16,
+    discovered: bool,
+    values: HashMap<Uuid, Vec<u8>>,
+    descriptor_values: HashMap<(Uuid, Uuid), Vec<u8>>,
+    subscribed: HashSet<Uuid>,
+    writes: Vec<(Uuid, Vec<u8>)>,
+    handlers: HashMap<Uuid, WriteHandler>,
+    periodic: HashMap<Uuid, Periodic>,
+    failures: HashMap<Uuid, String>,
+}
+
+struct Readvertising {
+    interval: Duration,
+    update: AdvertisementUpdate,
+}
+
+struct Periodic {
+    interval: Duration,
+    generator: ValueGenerator,
+    /// Bumped on each subscription, so the task of a previous one knows to stop
+    subscription: u64,
+}
+
+impl MockPeripheral {
+    pub fn new(address: BDAddr) -> MockPeripheral {
+        MockPeripheral {
+            inner: Arc::new(Mutex::new(PeripheralState {
+                properties: PeripheralProperties {
+                    address,
+                    address_type: Some(AddressType::Public),
+                    ..Default::default()
+                },
+                delay: Duration::ZERO,
+                readvertising: None,
+                services: Vec::new(),
+                connected: false,
+                mtu: DEFAULT_MTU,
+                discovered: false,
+                values: HashMap::new(),
+                descriptor_values: HashMap::new(),
+                subscribed: HashSet::new(),
+                writes: Vec::new(),
+                handlers: HashMap::new(),
+                periodic: HashMap::new(),
+                failures: HashMap::new(),
+            })),
+            notifications: broadcast::channel(256).0,
+        }
+    }
+
+    pub fn name(self, name: &str) -> MockPeripheral {
+        self.state().properties.local_name = Some(name.into());
+        self
+    }
+
+    pub fn rssi(self, rssi: i16) -> MockPeripheral {
+        self.state().properties.rssi = Some(rssi);
+        self
+    }
+
+    pub fn address_type(self, address_type: AddressType) -> MockPeripheral {
+        self.state().properties.address_type = Some(address_type);
+        self
+    }
+
+    pub fn tx_power(self, tx_power: i16) -> MockPeripheral {
+        self.state().properties.tx_power_level = Some(tx_power);
+        self
+    }
+
+    pub fn advertised_service(self, uuid: Uuid) -> MockPeripheral {
+        self.state().properties.services.push(uuid);
+        self
+    }
+
+    pub fn manufacturer_data(self, company: u16, data: &[u8]) -> MockPeripheral {
+        self.state().properties.manufacturer_data.insert(company, data.to_vec());
+        self
+    }
+
+    pub fn service_data(self, uuid: Uuid, data: &[u8]) -> MockPeripheral {
+        self.state().properties.service_data.insert(uuid, data.to_vec());
+        self
+    }
+
+    /// Delay between the start of a scan and the first advertisement of the device.
+    pub fn appear_after(self, delay: Duration) -> MockPeripheral {
+        self.state().delay = delay;
+        self
+    }
+
+    /// Keeps advertising every `interval` once discovered, updating the advertisement data with
+    /// `update` each time, such as to vary the signal strength.
+    pub fn advertise_every(
+        self,
+        interval: Duration,
+        update: impl FnMut(&mut PeripheralProperties) + Send + 'static,
+    ) -> MockPeripheral {
+        self.state().readvertising = Some(Readvertising { interval, update: Box::new(update) });
+        self
+    }
+
+    /// ATT MTU negotiated on connection, bounding the length of writes without response.
+    pub fn mtu(self, mtu: u16) -> MockPeripheral {
+        self.state().mtu = mtu;
+        self
+    }
+
+    pub fn service(self, uuid: Uuid) -> MockPeripheral {
+        self.state().services.push(Service {
+            uuid,
+            primary: true,
+            characteristics: BTreeSet::new(),
+        });
+        self
+    }
+
+    /// Adds a characteristic to a service previously added with [`MockPeripheral::service`].
+    pub fn characteristic(
+        self,
+        service: Uuid,
+        uuid: Uuid,
+        properties: CharPropFlags,
+        value: &[u8],
+    ) -> MockPeripheral {
+        {
+            let mut state = self.state();
+            let s = state.services.iter_mut()
+                .find(|s| s.uuid == service)
+                .expect("characteristic added to unknown service");
+
+            s.characteristics.insert(Characteristic {
+                uuid,
+                service_uuid: service,
+                properties,
+                descriptors: BTreeSet::new(),
+            });
+            state.values.insert(uuid, value.to_vec());
+        }
+        self
+    }
+
+    /// Adds a descriptor to a characteristic previously added with
+    /// [`MockPeripheral::characteristic`].
+    pub fn descriptor(self, characteristic: Uuid, uuid: Uuid, value: &[u8]) -> MockPeripheral {
+        {
+            let mut state = self.state();
+            let s = state.services.iter_mut()
+                .find(|s| s.characteristics.iter().any(|c| c.uuid == characteristic))
+                .expect("descriptor added to unknown characteristic");
+
+            let mut c = s.characteristics.iter()
+                .find(|c| c.uuid == characteristic)
+                .cloned()
+                .unwrap();
+            s.characteristics.remove(&c);
+            c.descriptors.insert(Descriptor {
+                uuid,
+                service_uuid: c.service_uuid,
+                characteristic_uuid: characteristic,
+            });
+            s.characteristics.insert(c);
+
+            state.descriptor_values.insert((characteristic, uuid), value.to_vec());
+        }
+        self
+    }
+
+    /// Scripts the response of the peripheral to writes on a characteristic. Without a handler
+    /// written bytes simply become the new value of the characteristic.
+    pub fn on_write(
+        self,
+        characteristic: Uuid,
+        handler: impl FnMut(&[u8]) -> Vec<ValueNotification> + Send + 'static,
+    ) -> MockPeripheral {
+        self.state().handlers.insert(characteristic, Box::new(handler));
+        self
+    }
+
+    /// Notifies a new value of a characteristic every `interval` while it is subscribed to.
+    pub fn periodic(
+        self,
+        characteristic: Uuid,
+        interval: Duration,
+        generator: impl FnMut() -> Vec<u8> + Send + 'static,
+    ) -> MockPeripheral {
+        self.state().periodic.insert(characteristic, Periodic {
+            interval,
+            generator: Box::new(generator),
+            subscription: 0,
+        });
+        self
+    }
+
+    /// Makes every operation on the given characteristic or descriptor fail.
+    pub fn fail(self, uuid: Uuid, message: &str) -> MockPeripheral {
+        self.state().failures.insert(uuid, message.into());
+        self
+    }
+
+    /// Changes the value of a characteristic, notifying subscribers.
+    pub fn notify(&self, characteristic: Uuid, value: &[u8]) {
+        let mut state = self.state();
+        state.values.insert(characteristic, value.to_vec());
+
+        if state.connected && state.subscribed.contains(&characteristic) {
+            let _ = self.notifications.send(ValueNotification {
+                uuid: characteristic,
+                value: value.to_vec(),
+            });
+        }
+    }
+
+    /// Everything written to a characteristic so far, in order.
+    pub fn writes(&self, characteristic: Uuid) -> Vec<Vec<u8>> {
+        self.state().writes.iter()
+            .filter(|(uuid, _)| *uuid == characteristic)
+            .map(|(_, data)| data.clone())
+            .collect()
+    }
+
+    /// Simulates the link dropping on the peripheral side.
+    pub fn drop_link(&self) {
+        let mut state = self.state();
+        state.connected = false;
+        state.subscribed.clear();
+    }
+
+    fn state(&self) -> MutexGuard<'_, PeripheralState> {
+        lock(&self.inner)
+    }
+}
+
+impl PeripheralState {
+    fn check(&self, uuid: Uuid) -> Result<()> {
+        if !self.connected {
+            return Err(Error::NotConnected);
+        }
+
+        match self.failures.get(&uuid) {
+            Some(msg) => Err(Error::RuntimeError(msg.clone())),
+            None => Ok(()),
+        }
+    }
+}
+
+#[async_trait]
+impl Peripheral for MockPeripheral {
+    fn address(&self) -> BDAddr {
+        self.state().properties.address
+    }
+
+    async fn properties(&self) -> Result<Option<PeripheralProperties>> {
+        Ok(Some(self.state().properties.clone()))
+    }
+
+    async fn is_connected(&self) -> Result<bool> {
+        Ok(self.state().connected)
+    }
+
+    fn mtu(&self) -> Option<u16> {
+        Some(self.state().mtu)
+    }
+
+    async fn connect(&self) -> Result<()> {
+        self.state().connected = true;
+        Ok(())
+    }
+
+    async fn disconnect(&self) -> Result<()> {
+        let mut state = self.state();
+        state.connected = false;
+        state.subscribed.clear();
+        Ok(())
+    }
+
+    async fn discover_services(&self) -> Result<()> {
+        let mut state = self.state();
+        if !state.connected {
+            return Err(Error::NotConnected);
+        }
+
+        state.discovered = true;
+        Ok(())
+    }
+
+    fn services(&self) -> BTreeSet<Service> {
+        let state = self.state();
+        if !state.discovered {
+            return BTreeSet::new();
+        }
+
+        state.services.iter().cloned().collect()
+    }
+
+    async fn read(&self, characteristic: &Characteristic) -> Result<Vec<u8>> {
+        let state = self.state();
+        state.check(characteristic.uuid)?;
+
+        if !characteristic.properties.contains(CharPropFlags::READ) {
+            return Err(Error::NotSupported("characteristic is not readable".into()));
+        }
+
+        Ok(state.values.get(&characteristic.uuid).cloned().unwrap_or_default())
+    }
+
+    async fn write(
+        &self,
+        characteristic: &Characteristic,
+        data: &[u8],
+        write_type: WriteType,
+    ) -> Result<()> {
+        let responses = {
+            let mut state = self.state();
+            state.check(characteristic.uuid)?;
+
+            let required = match write_type {
+                WriteType::WithResponse => CharPropFlags::WRITE,
+                WriteType::WithoutResponse => CharPropFlags::WRITE_WITHOUT_RESPONSE,
+            };
+            if !characteristic.properties.contains(required) {
+                return Err(Error::NotSupported(format!("characteristic does not support {:?}", write_type)));
+            }
+
+            // Writes with response longer than a packet become long writes, up to the longest
+            // attribute value allowed
+            let max = match write_type {
+                WriteType::WithResponse => MAX_ATTRIBUTE_LEN,
+                WriteType::WithoutResponse => state.mtu.saturating_sub(3) as usize,
+            };
+            if data.len() > max {
+                return Err(Error::RuntimeError(format!("invalid attribute value length {}", data.len())));
+            }
+
+            state.writes.push((characteristic.uuid, data.to_vec()));
+            match state.handlers.get_mut(&characteristic.uuid) {
+                Some(handler) => handler(data),
+                None => {
+                    state.values.insert(characteristic.uuid, data.to_vec());
+                    Vec::new()
+                }
+            }
+        };
+
+        for n in responses {
+            self.notify(n.uuid, &n.value);
+        }
+
+        Ok(())
+    }
+
+    async fn read_descriptor(&self, descriptor: &Descriptor) -> Result<Vec<u8>> {
+        let state = self.state();
+        state.check(descriptor.uuid)?;
+
+        Ok(state.descriptor_values
+            .get(&(descriptor.characteristic_uuid, descriptor.uuid))
+            .cloned()
+            .unwrap_or_default())
+    }
+
+    async fn write_descriptor(&self, descriptor: &Descriptor, data: &[u8]) -> Result<()> {
+        let mut state = self.state();
+        state.check(descriptor.uuid)?;
+
+        state.descriptor_values.insert((descriptor.characteristic_uuid, descriptor.uuid), data.to_vec());
+        Ok(())
+    }
+
+    async fn subscribe(&self, characteristic: &Characteristic) -> Result<()> {
+        let mut state = self.state();
+        state.check(characteristic.uuid)?;
+
+        if !characteristic.properties.intersects(CharPropFlags::NOTIFY | CharPropFlags::INDICATE) {
+            return Err(Error::NotSupported("characteristic does not support notifications".into()));
+        }
+
+        if !state.subscribed.insert(characteristic.uuid) {
+            return Ok(());
+        }
+
+        if let Some(periodic) = state.periodic.get_mut(&characteristic.uuid) {
+            periodic.subscription += 1;
+            let (uuid, interval, subscription) = (characteristic.uuid, periodic.interval, periodic.subscription);
+
+            let peripheral = self.clone();
+            tokio::spawn(async move {
+                loop {
+                    time::sleep(interval).await;
+
+                    let value = {
+                        let mut state = peripheral.state();
+                        let still_subscribed = state.subscribed.contains(&uuid);
+                        match state.periodic.get_mut(&uuid) {
+                            Some(p) if still_subscribed && p.subscription == subscription => (p.generator)(),
+                            _ => return,
+                        }
+                    };
+                    peripheral.notify(uuid, &value);
+                }
+            });
+        }
+
+        Ok(())
+    }
+
+    async fn unsubscribe(&self, characteristic: &Characteristic) -> Result<()> {
+        let mut state = self.state();
+        state.check(characteristic.uuid)?;
+
+        state.subscribed.remove(&characteristic.uuid);
+        Ok(())
+    }
+
+    async fn notifications(&self) -> Result<EventStream<ValueNotification>> {
+        let rx = self.notifications.subscribe();
+        Ok(Box::pin(BroadcastStream::new(rx).filter_map(|n| n.ok())))
+    }
+}
+
+fn lock<T>(m: &Mutex<T>) -> MutexGuard<'_, T> {
+    m.lock().unwrap_or_else(|e| e.into_inner())
+}
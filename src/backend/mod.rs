@@ -0,0 +1,117 @@
+//! Abstraction over the bluetooth stack.
+//!
+//! Everything ble-util does goes through the [`Backend`], [`Adapter`] and [`Peripheral`] traits so
+//! that the same code can run against a real radio (via btleplug) or against the in-memory
+//! [`mock`] backend.
+
+use std::collections::BTreeSet;
+use std::pin::Pin;
+
+use async_trait::async_trait;
+use btleplug::api::{
+    BDAddr, Characteristic, Descriptor, PeripheralProperties, ScanFilter, Service,
+    ValueNotification, WriteType,
+};
+use btleplug::Result;
+use tokio_stream::Stream;
+
+pub mod btle;
+pub mod mock;
+
+pub use btle::BtleplugBackend;
+pub use mock::{MockAdapter, MockBackend, MockPeripheral};
+
+/// ATT MTU every connection starts with, assumed when the stack doesn't tell the negotiated one.
+pub const DEFAULT_MTU: u16 = 23;
+
+/// A boxed stream of events produced by a backend.
+pub type EventStream<T> = Pin<Box<dyn Stream<Item = T> + Send>>;
+
+/// Events emitted by an adapter while scanning.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AdapterEvent {
+    DeviceDiscovered(BDAddr),
+    DeviceUpdated(BDAddr),
+    DeviceConnected(BDAddr),
+    DeviceDisconnected(BDAddr),
+}
+
+impl AdapterEvent {
+    /// Address of the device the event refers to.
+    pub fn address(&self) -> BDAddr {
+        match *self {
+            AdapterEvent::DeviceDiscovered(addr)
+            | AdapterEvent::DeviceUpdated(addr)
+            | AdapterEvent::DeviceConnected(addr)
+            | AdapterEvent::DeviceDisconnected(addr) => addr,
+        }
+    }
+}
+
+/// Entry point of a bluetooth stack, used to enumerate the local adapters.
+#[async_trait]
+pub trait Backend: Send + Sync {
+    async fn adapters(&self) -> Result<Vec<Box<dyn Adapter>>>;
+}
+
+/// A local bluetooth controller, able to scan for and hand out peripherals.
+#[async_trait]
+pub trait Adapter: Send + Sync {
+    /// Human readable description of the adapter.
+    async fn info(&self) -> Result<String>;
+
+    async fn start_scan(&self, filter: ScanFilter) -> Result<()>;
+    async fn stop_scan(&self) -> Result<()>;
+
+    /// Stream of discovery events, starting from the moment it is requested.
+    async fn events(&self) -> Result<EventStream<AdapterEvent>>;
+
+    /// Peripherals seen so far.
+    async fn peripherals(&self) -> Result<Vec<Box<dyn Peripheral>>>;
+}
+
+/// A remote device, along with the GATT operations that can be performed on it.
+#[async_trait]
+pub trait Peripheral: Send + Sync {
+    fn address(&self) -> BDAddr;
+
+    /// Latest advertisement data for the device, if any was received.
+    async fn properties(&self) -> Result<Option<PeripheralProperties>>;
+
+    async fn is_connected(&self) -> Result<bool>;
+    async fn connect(&self) -> Result<()>;
+    async fn disconnect(&self) -> Result<()>;
+
+    /// ATT MTU negotiated for the connection, if the stack tells.
+    fn mtu(&self) -> Option<u16> {
+        None
+    }
+
+    /// Populates [`Peripheral::services`]. Must be called after connecting.
+    async fn discover_services(&self) -> Result<()>;
+    fn services(&self) -> BTreeSet<Service>;
+
+    fn characteristics(&self) -> BTreeSet<Characteristic> {
+        self.services()
+            .into_iter()
+            .flat_map(|s| s.characteristics)
+            .collect()
+    }
+
+    async fn read(&self, characteristic: &Characteristic) -> Result<Vec<u8>>;
+    async fn write(
+        &self,
+        characteristic: &Characteristic,
+        data: &[u8],
+        write_type: WriteType,
+    ) -> Result<()>;
+
+    async fn read_descriptor(&self, descriptor: &Descriptor) -> Result<Vec<u8>>;
+    async fn write_descriptor(&self, descriptor: &Descriptor, data: &[u8]) -> Result<()>;
+
+    async fn subscribe(&self, characteristic: &Characteristic) -> Result<()>;
+    async fn unsubscribe(&self, characteristic: &Characteristic) -> Result<()>;
+
+    /// Stream of values notified or indicated by subscribed characteristics.
+    async fn notifications(&self) -> Result<EventStream<ValueNotification>>;
+}
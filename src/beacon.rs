@@ -0,0 +1,395 @@
+//! Decoding of beacon advertisements: Apple iBeacon, AltBeacon and Google Eddystone.
+
+use std::fmt;
+use std::time::Duration;
+
+use btleplug::api::bleuuid::uuid_from_u16;
+use uuid::Uuid;
+
+use crate::distance::LOSS_AT_1M;
+use crate::session::DeviceInfo;
+use crate::value::hex;
+
+/// Company id of Apple, whose manufacturer data carries iBeacon frames.
+const APPLE: u16 = 0x004c;
+
+/// Service whose service data carries Eddystone frames.
+pub const EDDYSTONE: Uuid = uuid_from_u16(0xfeaa);
+
+/// A beacon frame found in an advertisement.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Beacon {
+    IBeacon {
+        uuid: Uuid,
+        major: u16,
+        minor: u16,
+        /// Received signal strength at 1 m, in dBm
+        measured_power: i8,
+    },
+    AltBeacon {
+        /// Company id of the manufacturer data
+        manufacturer: u16,
+        /// Beacon id, usually a UUID followed by two 16-bit numbers
+        id: [u8; 20],
+        /// Received signal strength at 1 m, in dBm
+        reference_rssi: i8,
+        reserved: u8,
+    },
+    EddystoneUid {
+        /// Transmission power at 0 m, in dBm
+        tx_power: i8,
+        namespace: [u8; 10],
+        instance: [u8; 6],
+    },
+    EddystoneUrl {
+        /// Transmission power at 0 m, in dBm
+        tx_power: i8,
+        url: String,
+    },
+    /// Telemetry, in clear
+    EddystoneTlm {
+        /// Battery voltage, in mV; `None` if unknown
+        battery: Option<u16>,
+        /// Beacon temperature, in °C; `None` if unknown
+        temperature: Option<f32>,
+        /// Number of advertisements sent since power-up
+        advertisements: u32,
+        uptime: Duration,
+    },
+    EddystoneEid {
+        /// Transmission power at 0 m, in dBm
+        tx_power: i8,
+        eid: [u8; 8],
+    },
+}
+
+impl Beacon {
+    /// Decodes the manufacturer data of a company, if it holds an iBeacon or AltBeacon frame.
+    pub fn from_manufacturer_data(company: u16, data: &[u8]) -> Option<Beacon> {
+        match data {
+            [0x02, 0x15, rest @ ..] if company == APPLE && rest.len() == 21 => Some(Beacon::IBeacon {
+                uuid: Uuid::from_slice(&rest[..16]).ok()?,
+                major: u16::from_be_bytes([rest[16], rest[17]]),
+                minor: u16::from_be_bytes([rest[18], rest[19]]),
+                measured_power: rest[20] as i8,
+            }),
+            [0xbe, 0xac, rest @ ..] if rest.len() == 22 => Some(Beacon::AltBeacon {
+                manufacturer: company,
+                id: rest[..20].try_into().ok()?,
+                reference_rssi: rest[20] as i8,
+                reserved: rest[21],
+            }),
+            _ => None,
+        }
+    }
+
+    /// Decodes the data of a service, if it holds an Eddystone frame.
+    pub fn from_service_data(uuid: Uuid, data: &[u8]) -> Option<Beacon> {
+        if uuid != EDDYSTONE {
+            return None;
+        }
+
+        match data {
+            // Two reserved bytes may follow
+            [0x00, tx_power, rest @ ..] if rest.len() >= 16 => Some(Beacon::EddystoneUid {
+                tx_power: *tx_power as i8,
+                namespace: rest[..10].try_into().ok()?,
+                instance: rest[10..16].try_into().ok()?,
+            }),
+            [0x10, tx_power, scheme, rest @ ..] => Some(Beacon::EddystoneUrl {
+                tx_power: *tx_power as i8,
+                url: decode_url(*scheme, rest)?,
+            }),
+            // Version 0 is unencrypted
+            [0x20, 0x00, rest @ ..] if rest.len() == 12 => {
+                let battery = u16::from_be_bytes([rest[0], rest[1]]);
+                let temperature = i16::from_be_bytes([rest[2], rest[3]]);
+                Some(Beacon::EddystoneTlm {
+                    battery: (battery != 0).then_some(battery),
+                    // 8.8 fixed point, the lowest value meaning unsupported
+                    temperature: (temperature != i16::MIN).then(|| temperature as f32 / 256.0),
+                    advertisements: u32::from_be_bytes(rest[4..8].try_into().ok()?),
+                    uptime: Duration::from_millis(u32::from_be_bytes(rest[8..12].try_into().ok()?) as u64 * 100),
+                })
+            }
+            [0x30, tx_power, rest @ ..] if rest.len() == 8 => Some(Beacon::EddystoneEid {
+                tx_power: *tx_power as i8,
+                eid: rest.try_into().ok()?,
+            }),
+            _ => None,
+        }
+    }
+
+    /// Short name of the kind of frame, such as `ibeacon` or `eddystone-url`.
+    pub fn kind(&self) -> &'static str {
+        match self {
+            Beacon::IBeacon { .. } => "ibeacon",
+            Beacon::AltBeacon { .. } => "altbeacon",
+            Beacon::EddystoneUid { .. } => "eddystone-uid",
+            Beacon::EddystoneUrl { .. } => "eddystone-url",
+            Beacon::EddystoneTlm { .. } => "eddystone-tlm",
+            Beacon::EddystoneEid { .. } => "eddystone-eid",
+        }
+    }
+
+    /// Signal strength the frame says it is received with at 1 m, in dBm, deduced from the power
+    /// at 0 m for Eddystone. `None` for telemetry.
+    pub fn measured_power(&self) -> Option<i16> {
+        match *self {
+            Beacon::IBeacon { measured_power, .. } => Some(measured_power as i16),
+            Beacon::AltBeacon { reference_rssi, .. } => Some(reference_rssi as i16),
+            Beacon::EddystoneUid { tx_power, .. }
+            | Beacon::EddystoneUrl { tx_power, .. }
+            | Beacon::EddystoneEid { tx_power, .. } => Some(tx_power as i16 - LOSS_AT_1M),
+            Beacon::EddystoneTlm { .. } => None,
+        }
+    }
+}
+
+impl fmt::Display for Beacon {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Beacon::IBeacon { uuid, major, minor, measured_power } => {
+                write!(f, "iBeacon {} major {} minor {}, {} dBm at 1 m", uuid, major, minor, measured_power)
+            }
+            Beacon::AltBeacon { manufacturer, id, reference_rssi, .. } => {
+                write!(f, "AltBeacon {} from {:#06x}, {} dBm at 1 m", hex(id), manufacturer, reference_rssi)
+            }
+            Beacon::EddystoneUid { tx_power, namespace, instance } => {
+                write!(f, "Eddystone UID {} instance {}, {} dBm at 0 m", hex(namespace), hex(instance), tx_power)
+            }
+            Beacon::EddystoneUrl { tx_power, url } => write!(f, "Eddystone URL {}, {} dBm at 0 m", url, tx_power),
+            Beacon::EddystoneTlm { battery, temperature, advertisements, uptime } => {
+                write!(f, "Eddystone TLM")?;
+                if let Some(battery) = battery {
+                    write!(f, " battery {} mV", battery)?;
+                }
+                if let Some(temperature) = temperature {
+                    write!(f, " temperature {:.2} °C", temperature)?;
+                }
+                write!(f, " {} advertisements in {}s", advertisements, uptime.as_secs())
+            }
+            Beacon::EddystoneEid { tx_power, eid } => write!(f, "Eddystone EID {}, {} dBm at 0 m", hex(eid), tx_power),
+        }
+    }
+}
+
+/// Beacon frames advertised by a device.
+pub fn beacons(device: &DeviceInfo) -> Vec<Beacon> {
+    let manufacturer = device.manufacturer_data.iter().filter_map(|(c, d)| Beacon::from_manufacturer_data(*c, d));
+    let service = device.service_data.iter().filter_map(|(u, d)| Beacon::from_service_data(*u, d));
+    manufacturer.chain(service).collect()
+}
+
+fn decode_url(scheme: u8, encoded: &[u8]) -> Option<String> {
+    let mut url = String::from(match scheme {
+        0x00 => "http://www.",
+        0x01 => "https://www.",
+        0x02 => "http://",
+        0x03 => "https://",
+        _ => return None,
+    });
+
+    for &b in encoded {
+        match b {
+            0x00 => url.push_str(".com/"),
+            0x01 => url.push_str(".org/"),
+            0x02 => url.push_str(".edu/"),
+            0x03 => url.push_str(".net/"),
+            0x04 => url.push_str(".info/"),
+            0x05 => url.push_str(".biz/"),
+            0x06 => url.push_str(".gov/"),
+            0x07 => url.push_str(".com"),
+            0x08 => url.push_str(".org"),
+            0x09 => url.push_str(".edu"),
+            0x0a => url.push_str(".net"),
+            0x0b => url.push_str(".info"),
+            0x0c => url.push_str(".biz"),
+            0x0d => url.push_str(".gov"),
+            0x21..=0x7e => url.push(b as char),
+            _ => return None,
+        }
+    }
+
+    Some(url)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PROXIMITY_UUID: Uuid = Uuid::from_u128(0xe2c56db5_dffb_48d2_b060_d0f5a71096e0);
+
+    fn ibeacon() -> Vec<u8> {
+        // Major 1, minor 2, -59 dBm at 1 m
+        [&[0x02, 0x15][..], PROXIMITY_UUID.as_bytes(), &[0x00, 0x01, 0x00, 0x02, 0xc5]].concat()
+    }
+
+    fn altbeacon() -> Vec<u8> {
+        [&[0xbe, 0xac][..], PROXIMITY_UUID.as_bytes(), &[0x00, 0x03, 0x00, 0x04, 0xbc, 0x2a]].concat()
+    }
+
+    fn eddystone_uid() -> Vec<u8> {
+        let mut frame = vec![0x00, 0xec];
+        frame.extend(0..10);
+        frame.extend([0xa1, 0xa2, 0xa3, 0xa4, 0xa5, 0xa6]);
+        frame
+    }
+
+    fn eddystone_tlm() -> Vec<u8> {
+        // 3000 mV, 23.5 °C, 1000 advertisements, 60 s
+        vec![0x20, 0x00, 0x0b, 0xb8, 0x17, 0x80, 0x00, 0x00, 0x03, 0xe8, 0x00, 0x00, 0x02, 0x58]
+    }
+
+    fn url(scheme: u8, encoded: &[u8]) -> Option<String> {
+        match Beacon::from_service_data(EDDYSTONE, &[&[0x10, 0xf4, scheme][..], encoded].concat())? {
+            Beacon::EddystoneUrl { url, .. } => Some(url),
+            b => panic!("not a URL frame: {:?}", b),
+        }
+    }
+
+    #[test]
+    fn decodes_ibeacons() {
+        let beacon = Beacon::from_manufacturer_data(APPLE, &ibeacon()).unwrap();
+        assert_eq!(beacon, Beacon::IBeacon { uuid: PROXIMITY_UUID, major: 1, minor: 2, measured_power: -59 });
+        assert_eq!(beacon.kind(), "ibeacon");
+        assert_eq!(beacon.measured_power(), Some(-59));
+        assert_eq!(
+            beacon.to_string(),
+            "iBeacon e2c56db5-dffb-48d2-b060-d0f5a71096e0 major 1 minor 2, -59 dBm at 1 m"
+        );
+
+        // Only from Apple
+        assert_eq!(Beacon::from_manufacturer_data(0x0059, &ibeacon()), None);
+    }
+
+    #[test]
+    fn decodes_altbeacons() {
+        let beacon = Beacon::from_manufacturer_data(0x0118, &altbeacon()).unwrap();
+        let mut id = [0; 20];
+        id[..16].copy_from_slice(PROXIMITY_UUID.as_bytes());
+        id[16..].copy_from_slice(&[0x00, 0x03, 0x00, 0x04]);
+        assert_eq!(beacon, Beacon::AltBeacon { manufacturer: 0x0118, id, reference_rssi: -68, reserved: 0x2a });
+        assert_eq!(beacon.measured_power(), Some(-68));
+    }
+
+    #[test]
+    fn decodes_eddystone_uids() {
+        let beacon = Beacon::from_service_data(EDDYSTONE, &eddystone_uid()).unwrap();
+        assert_eq!(beacon, Beacon::EddystoneUid {
+            tx_power: -20,
+            namespace: [0, 1, 2, 3, 4, 5, 6, 7, 8, 9],
+            instance: [0xa1, 0xa2, 0xa3, 0xa4, 0xa5, 0xa6],
+        });
+        // Power at 0 m less the loss over the first meter
+        assert_eq!(beacon.measured_power(), Some(-61));
+        assert_eq!(beacon.to_string(), "Eddystone UID 00010203040506070809 instance a1a2a3a4a5a6, -20 dBm at 0 m");
+
+        // With the reserved bytes
+        let reserved = [eddystone_uid(), vec![0, 0]].concat();
+        assert_eq!(Beacon::from_service_data(EDDYSTONE, &reserved), Some(beacon));
+        // Only in Eddystone service data
+        assert_eq!(Beacon::from_service_data(uuid_from_u16(0xfeab), &eddystone_uid()), None);
+    }
+
+    #[test]
+    fn decodes_eddystone_urls() {
+        let beacon = Beacon::from_service_data(EDDYSTONE, &[0x10, 0xf4, 0x03, b'g', b'o', b'o', b'.', b'g', b'l', 0x00, b'x']);
+        assert_eq!(beacon, Some(Beacon::EddystoneUrl { tx_power: -12, url: "https://goo.gl.com/x".into() }));
+    }
+
+    #[test]
+    fn expands_url_schemes() {
+        assert_eq!(url(0x00, b"example").as_deref(), Some("http://www.example"));
+        assert_eq!(url(0x01, b"example").as_deref(), Some("https://www.example"));
+        assert_eq!(url(0x02, b"example").as_deref(), Some("http://example"));
+        assert_eq!(url(0x03, b"example").as_deref(), Some("https://example"));
+        assert_eq!(url(0x04, b"example"), None);
+    }
+
+    #[test]
+    fn expands_url_suffixes() {
+        let suffixes = [
+            ".com/", ".org/", ".edu/", ".net/", ".info/", ".biz/", ".gov/",
+            ".com", ".org", ".edu", ".net", ".info", ".biz", ".gov",
+        ];
+        for (code, suffix) in suffixes.iter().enumerate() {
+            assert_eq!(url(0x02, &[b'a', code as u8]), Some(format!("http://a{}", suffix)));
+        }
+
+        // Neither a suffix nor printable
+        for b in [0x0e, 0x20, 0x7f, 0xff] {
+            assert_eq!(url(0x02, &[b'a', b]), None, "{:#04x}", b);
+        }
+    }
+
+    #[test]
+    fn decodes_eddystone_telemetry() {
+        let beacon = Beacon::from_service_data(EDDYSTONE, &eddystone_tlm()).unwrap();
+        assert_eq!(beacon, Beacon::EddystoneTlm {
+            battery: Some(3000),
+            temperature: Some(23.5),
+            advertisements: 1000,
+            uptime: Duration::from_secs(60),
+        });
+        assert_eq!(beacon.measured_power(), None);
+        assert_eq!(
+            beacon.to_string(),
+            "Eddystone TLM battery 3000 mV temperature 23.50 °C 1000 advertisements in 60s"
+        );
+
+        // Unknown battery voltage and temperature
+        let mut frame = eddystone_tlm();
+        frame[2..6].copy_from_slice(&[0x00, 0x00, 0x80, 0x00]);
+        let beacon = Beacon::from_service_data(EDDYSTONE, &frame).unwrap();
+        assert!(matches!(beacon, Beacon::EddystoneTlm { battery: None, temperature: None, .. }));
+
+        // Encrypted telemetry isn't decoded
+        frame[1] = 0x01;
+        assert_eq!(Beacon::from_service_data(EDDYSTONE, &frame), None);
+    }
+
+    #[test]
+    fn truncated_frames_are_ignored() {
+        let manufacturer = [(APPLE, ibeacon()), (0x0118, altbeacon())];
+        for (company, frame) in manufacturer {
+            for len in 0..frame.len() {
+                assert_eq!(Beacon::from_manufacturer_data(company, &frame[..len]), None, "{}", hex(&frame[..len]));
+            }
+        }
+
+        for frame in [eddystone_uid(), eddystone_tlm()] {
+            for len in 0..frame.len() {
+                assert_eq!(Beacon::from_service_data(EDDYSTONE, &frame[..len]), None, "{}", hex(&frame[..len]));
+            }
+        }
+        assert_eq!(Beacon::from_service_data(EDDYSTONE, &[0x10, 0xf4]), None);
+    }
+
+    #[test]
+    fn garbage_does_not_panic() {
+        // A simple deterministic generator is enough to cover odd lengths and values
+        let mut state = 0x2545_f491_4f6c_dd1du64;
+        let mut next = move || {
+            state ^= state << 13;
+            state ^= state >> 7;
+            state ^= state << 17;
+            state
+        };
+
+        for _ in 0..2000 {
+            let len = (next() % 40) as usize;
+            let mut data: Vec<u8> = (0..len).map(|_| next() as u8).collect();
+            // Make known frame types likely
+            if let Some(first) = data.first_mut() {
+                *first = [0x00, 0x02, 0x10, 0x20, 0x30, 0xbe][*first as usize % 6];
+            }
+
+            Beacon::from_manufacturer_data(APPLE, &data);
+            Beacon::from_manufacturer_data(0x0118, &data);
+            if let Some(b) = Beacon::from_service_data(EDDYSTONE, &data) {
+                b.to_string();
+            }
+        }
+    }
+}
@@ -0,0 +1,15 @@
+use std::error::Error;
+
+use ble_util::backend::Backend;
+
+use super::output::{AdapterRecord, Output};
+
+pub async fn list_adapters(backend: &dyn Backend, out: Output) -> Result<(), Box<dyn Error>> {
+    let adapters: Vec<AdapterRecord> = ble_util::adapters(backend).await?
+        .into_iter()
+        .map(AdapterRecord::from)
+        .collect();
+
+    out.list(&adapters, |a| println!("{}: {}", a.index, a.info));
+    Ok(())
+}
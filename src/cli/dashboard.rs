@@ -0,0 +1,893 @@
+//! Full-screen dashboard: a live table of the devices around, from which to browse the GATT
+//! database of one.
+//!
+//! The table is updated as advertisements arrive and can be sorted and filtered. Opening a device
+//! stops the scan, connects to it and shows its services, characteristics and descriptors as a
+//! tree, whose values can be read, written and subscribed to. Leaving the tree disconnects and
+//! resumes the scan.
+
+use std::cmp::Reverse;
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::error::Error;
+use std::future;
+use std::io::{self, stdout, IsTerminal};
+use std::thread;
+use std::time::{Duration, SystemTime};
+
+use ble_util::backend::EventStream;
+use ble_util::lookup::DEFAULT_TIMEOUT;
+use ble_util::{
+    hex, BDAddr, CharPropFlags, Chunking, Decoders, Device, DeviceFilter, Names, ScanEvent, Scanner, ServiceInfo,
+    Session, Target, ValueNotification, WriteType,
+};
+use crossterm::event::{self, Event, KeyCode, KeyEvent, KeyEventKind, KeyModifiers};
+use ratatui::layout::{Constraint, Layout};
+use ratatui::style::{Style, Stylize};
+use ratatui::text::{Line, Span};
+use ratatui::widgets::{Block, List, ListState, Paragraph, Row, Table, TableState};
+use ratatui::{DefaultTerminal, Frame};
+use tokio::sync::mpsc;
+use tokio::time;
+use tokio_stream::StreamExt;
+
+use super::gatt::{gatt_record, printable};
+use super::output::{named, property_names, DeviceRecord, GattRecord, Output};
+use super::{parse_hex, DashboardArgs, SortOrder};
+
+/// Number of signal strengths kept per device for its sparkline.
+const HISTORY: usize = 30;
+
+/// Signal strengths spanned by the sparkline, in dBm.
+const RSSI_RANGE: (i16, i16) = (-100, -30);
+
+const BARS: [char; 8] = ['▁', '▂', '▃', '▄', '▅', '▆', '▇', '█'];
+
+/// How often to redraw when nothing happens, to keep the ages of advertisements current.
+const REFRESH: Duration = Duration::from_secs(1);
+
+pub async fn dashboard(
+    session: &Session,
+    args: &DashboardArgs,
+    names: &Names,
+    out: Output,
+) -> Result<(), Box<dyn Error>> {
+    if !out.is_text() || !stdout().is_terminal() {
+        return Err("the dashboard needs a terminal and text output".into());
+    }
+
+    let mut screen = Screen::new()?;
+    let mut table = DeviceTable::new(args);
+    let decoders = Decoders::builtin();
+
+    loop {
+        let mut scanner = session.watch(DeviceFilter::default()).await?;
+        let opened = table.run(&mut screen, &mut scanner, names, &decoders).await;
+        scanner.stop().await?;
+
+        let address = match opened? {
+            Some(address) => address,
+            None => return Ok(()),
+        };
+        table.status = match browse(session, address, args.timeout.unwrap_or(DEFAULT_TIMEOUT), &mut screen, names).await {
+            Ok(()) => String::new(),
+            Err(e) => format!("{}: {}", address, e),
+        };
+    }
+}
+
+/// The terminal, in the alternate screen and raw mode until dropped, and its events.
+struct Screen {
+    terminal: DefaultTerminal,
+    events: mpsc::UnboundedReceiver<Event>,
+}
+
+impl Screen {
+    fn new() -> io::Result<Screen> {
+        Ok(Screen {
+            terminal: ratatui::try_init()?,
+            events: read_events(),
+        })
+    }
+
+    fn draw(&mut self, render: impl FnOnce(&mut Frame)) -> io::Result<()> {
+        self.terminal.draw(render)?;
+        Ok(())
+    }
+
+    /// Waits for a key press or a resize.
+    async fn next_event(&mut self) -> Event {
+        match self.events.recv().await {
+            Some(event) => event,
+            // Input is gone, but the screen can still be watched
+            None => future::pending().await,
+        }
+    }
+
+    /// Waits for the user to ask to go back.
+    async fn cancelled(&mut self) {
+        loop {
+            if let Event::Key(key) = self.next_event().await {
+                if is_back(key) {
+                    return;
+                }
+            }
+        }
+    }
+}
+
+impl Drop for Screen {
+    fn drop(&mut self) {
+        ratatui::restore();
+    }
+}
+
+/// Reads terminal events on a thread of its own, as crossterm's reads block.
+fn read_events() -> mpsc::UnboundedReceiver<Event> {
+    let (tx, rx) = mpsc::unbounded_channel();
+
+    thread::spawn(move || {
+        while let Ok(event) = event::read() {
+            let wanted = match &event {
+                // Some platforms report releases too
+                Event::Key(key) => key.kind != KeyEventKind::Release,
+                Event::Resize(..) => true,
+                _ => false,
+            };
+            if wanted && tx.send(event).is_err() {
+                break;
+            }
+        }
+    });
+
+    rx
+}
+
+/// Esc, `q` or Ctrl-C, which comes as a key in raw mode.
+fn is_back(key: KeyEvent) -> bool {
+    match key.code {
+        KeyCode::Esc | KeyCode::Char('q') => true,
+        KeyCode::Char('c') => key.modifiers.contains(KeyModifiers::CONTROL),
+        _ => false,
+    }
+}
+
+/// A device seen during the scan.
+struct Seen {
+    record: DeviceRecord,
+    /// Latest signal strengths, oldest first
+    history: VecDeque<i16>,
+    last_seen: SystemTime,
+    /// Beacon frames and sensor readings, on one line
+    decoded: String,
+}
+
+struct DeviceTable {
+    devices: HashMap<BDAddr, Seen>,
+    sort: SortOrder,
+    /// Only devices whose address, name or decoded advertisement contain this are shown
+    filter: String,
+    /// Whether the filter is being typed
+    editing: bool,
+    selected: Option<BDAddr>,
+    /// Outcome of the last device opened, if it failed
+    status: String,
+}
+
+impl DeviceTable {
+    fn new(args: &DashboardArgs) -> DeviceTable {
+        DeviceTable {
+            devices: HashMap::new(),
+            sort: args.sort,
+            filter: args.filter.clone().unwrap_or_default(),
+            editing: false,
+            selected: None,
+            status: String::new(),
+        }
+    }
+
+    /// Shows the table until a device is opened, returning its address, or `None` to quit.
+    async fn run(
+        &mut self,
+        screen: &mut Screen,
+        scanner: &mut Scanner<'_>,
+        names: &Names,
+        decoders: &Decoders,
+    ) -> Result<Option<BDAddr>, Box<dyn Error>> {
+        let mut refresh = time::interval(REFRESH);
+        let mut scanning = true;
+
+        loop {
+            screen.draw(|f| self.render(f, scanning))?;
+
+            tokio::select! {
+                event = scanner.next(), if scanning => match event? {
+                    Some(event) => self.update(event, names, decoders),
+                    None => scanning = false,
+                },
+                event = screen.next_event() => {
+                    if let Event::Key(key) = event {
+                        match self.key(key) {
+                            TableAction::None => {}
+                            TableAction::Quit => return Ok(None),
+                            TableAction::Open(address) => return Ok(Some(address)),
+                        }
+                    }
+                }
+                _ = refresh.tick() => {}
+            }
+        }
+    }
+
+    fn update(&mut self, event: ScanEvent, names: &Names, decoders: &Decoders) {
+        let address = event.device.address;
+        let mut history = self.devices.remove(&address).map(|s| s.history).unwrap_or_default();
+        if let Some(rssi) = event.device.rssi {
+            if history.len() == HISTORY {
+                history.pop_front();
+            }
+            history.push_back(rssi);
+        }
+
+        let record = DeviceRecord::new(event.device, names, decoders);
+        self.devices.insert(address, Seen {
+            decoded: decoded(&record),
+            record,
+            history,
+            last_seen: event.seen,
+        });
+    }
+
+    fn key(&mut self, key: KeyEvent) -> TableAction {
+        if self.editing {
+            match key.code {
+                KeyCode::Esc => {
+                    self.filter.clear();
+                    self.editing = false;
+                }
+                KeyCode::Enter => self.editing = false,
+                KeyCode::Backspace => {
+                    self.filter.pop();
+                }
+                KeyCode::Char(c) => self.filter.push(c),
+                _ => {}
+            }
+            return TableAction::None;
+        }
+
+        if is_back(key) {
+            return TableAction::Quit;
+        }
+        match key.code {
+            KeyCode::Up | KeyCode::Char('k') => self.move_selection(-1),
+            KeyCode::Down | KeyCode::Char('j') => self.move_selection(1),
+            KeyCode::Char('s') => {
+                self.sort = match self.sort {
+                    SortOrder::Address => SortOrder::Rssi,
+                    SortOrder::Rssi => SortOrder::Name,
+                    SortOrder::Name => SortOrder::Address,
+                }
+            }
+            KeyCode::Char('/') => self.editing = true,
+            KeyCode::Enter | KeyCode::Char('p') => {
+                let visible = self.visible();
+                if let Some(i) = self.selected_index(&visible) {
+                    return TableAction::Open(visible[i].0);
+                }
+            }
+            _ => {}
+        }
+        TableAction::None
+    }
+
+    fn move_selection(&mut self, by: isize) {
+        let visible = self.visible();
+        if let Some(i) = self.selected_index(&visible) {
+            let index = i.saturating_add_signed(by).min(visible.len() - 1);
+            self.selected = Some(visible[index].0);
+        }
+    }
+
+    /// Row of the selected device, the first one if it's filtered out or none was selected yet.
+    fn selected_index(&self, visible: &[(BDAddr, &Seen)]) -> Option<usize> {
+        let index = visible.iter().position(|(a, _)| Some(*a) == self.selected);
+        index.or((!visible.is_empty()).then_some(0))
+    }
+
+    /// The devices passing the filter, in the chosen order.
+    fn visible(&self) -> Vec<(BDAddr, &Seen)> {
+        let filter = self.filter.to_lowercase();
+        let mut visible: Vec<(BDAddr, &Seen)> = self.devices.iter()
+            .filter(|(_, s)| {
+                let r = &s.record;
+                filter.is_empty()
+                    || r.address.to_lowercase().contains(&filter)
+                    || r.name.as_deref().is_some_and(|n| n.to_lowercase().contains(&filter))
+                    || s.decoded.to_lowercase().contains(&filter)
+            })
+            .map(|(a, s)| (*a, s))
+            .collect();
+
+        // Devices without a value go last, ties by address so rows don't shuffle
+        match self.sort {
+            SortOrder::Address => visible.sort_by_key(|(a, _)| *a),
+            SortOrder::Rssi => visible.sort_by_key(|(a, s)| (s.record.rssi.is_none(), Reverse(s.record.rssi), *a)),
+            SortOrder::Name => visible.sort_by(|(a, s), (b, t)| {
+                let (n, m) = (&s.record.name, &t.record.name);
+                (n.is_none(), n, a).cmp(&(m.is_none(), m, b))
+            }),
+        }
+        visible
+    }
+
+    fn render(&self, f: &mut Frame, scanning: bool) {
+        let [header, body, details, footer] = Layout::vertical([
+            Constraint::Length(1),
+            Constraint::Min(3),
+            Constraint::Length(8),
+            Constraint::Length(1),
+        ])
+        .areas(f.area());
+
+        let visible = self.visible();
+        let sort = match self.sort {
+            SortOrder::Address => "address",
+            SortOrder::Rssi => "signal strength",
+            SortOrder::Name => "name",
+        };
+        let mut title = vec![Span::raw(format!(
+            "{}: {} devices, {} shown, by {}",
+            if scanning { "Scanning" } else { "Scan stopped" },
+            self.devices.len(),
+            visible.len(),
+            sort,
+        ))];
+        if !self.status.is_empty() {
+            title.push(Span::raw("  "));
+            title.push(Span::styled(self.status.as_str(), Style::new().red()));
+        }
+        f.render_widget(Line::from(title), header);
+
+        let rows = visible.iter().map(|(_, s)| {
+            Row::new([
+                s.record.address.clone(),
+                s.record.name.clone().unwrap_or_default(),
+                s.record.rssi.map(|r| format!("{} dBm", r)).unwrap_or_default(),
+                sparkline(&s.history),
+                age(s.last_seen.elapsed().unwrap_or_default()),
+                s.decoded.clone(),
+            ])
+        });
+        let table = Table::new(rows, [
+            Constraint::Length(17),
+            Constraint::Length(20),
+            Constraint::Length(8),
+            Constraint::Length(HISTORY as u16),
+            Constraint::Length(4),
+            Constraint::Fill(1),
+        ])
+        .header(Row::new(["Address", "Name", "RSSI", "Signal", "Seen", "Decoded"]).bold())
+        .row_highlight_style(Style::new().reversed());
+        let selected = self.selected_index(&visible);
+        let mut state = TableState::new().with_selected(selected);
+        f.render_stateful_widget(table, body, &mut state);
+
+        if let Some((_, s)) = selected.map(|i| visible[i]) {
+            let block = Block::bordered().title(s.record.name.as_deref().unwrap_or(&s.record.address).to_string());
+            f.render_widget(Paragraph::new(device_details(&s.record)).block(block), details);
+        }
+
+        let help = if self.editing {
+            Line::from(format!("Filter: {}_  (Enter to apply, Esc to clear)", self.filter))
+        } else {
+            let filter = if self.filter.is_empty() { String::new() } else { format!("  filter: {}", self.filter) };
+            Line::from(format!("↑↓ select  Enter open  s sort  / filter  q quit{}", filter))
+        };
+        f.render_widget(help.reversed(), footer);
+    }
+}
+
+enum TableAction {
+    None,
+    Quit,
+    Open(BDAddr),
+}
+
+/// Beacon frames and sensor readings of a device, on one line.
+fn decoded(record: &DeviceRecord) -> String {
+    let beacons = record.beacons.iter().map(ToString::to_string);
+    let sensors = record.sensors.iter().map(|s| {
+        let readings: Vec<String> = s.readings.iter().map(ToString::to_string).collect();
+        format!("{}: {}", s.format, readings.join(", "))
+    });
+    beacons.chain(sensors).collect::<Vec<String>>().join("; ")
+}
+
+/// Advertisement data of a device, one line per field.
+fn device_details(r: &DeviceRecord) -> Vec<Line<'static>> {
+    let mut lines = Vec::new();
+    let signal = [
+        r.address_type.map(|t| format!("address type: {}", t)),
+        r.rssi.map(|rssi| format!("rssi: {} dBm", rssi)),
+        r.tx_power.map(|tx_power| format!("tx power: {} dBm", tx_power)),
+    ];
+    lines.push(Line::from(signal.into_iter().flatten().collect::<Vec<String>>().join("  ")));
+
+    let name = |id: &String| named(id, r.names.get(id).map(String::as_str));
+    lines.extend(r.services.iter().map(|s| Line::from(format!("service: {}", name(s)))));
+    lines.extend(r.manufacturer_data.iter()
+        .map(|(company, data)| Line::from(format!("manufacturer data {}: {}", name(company), data))));
+    lines.extend(r.service_data.iter()
+        .map(|(uuid, data)| Line::from(format!("service data {}: {}", name(uuid), data))));
+    lines
+}
+
+/// Signal strengths as bars, from the weakest to the strongest in [`RSSI_RANGE`].
+fn sparkline(history: &VecDeque<i16>) -> String {
+    let (min, max) = RSSI_RANGE;
+    history.iter()
+        .map(|rssi| BARS[(rssi.clamp(&min, &max) - min) as usize * (BARS.len() - 1) / (max - min) as usize])
+        .collect()
+}
+
+fn age(d: Duration) -> String {
+    match d.as_secs() {
+        0 => "now".into(),
+        s if s < 60 => format!("{}s", s),
+        s if s < 3600 => format!("{}m", s / 60),
+        s => format!("{}h", s / 3600),
+    }
+}
+
+/// Connects to a device and shows its GATT database until the user goes back.
+async fn browse(
+    session: &Session,
+    address: BDAddr,
+    timeout: Duration,
+    screen: &mut Screen,
+    names: &Names,
+) -> Result<(), Box<dyn Error>> {
+    screen.draw(|f| f.render_widget(Line::from(format!("Connecting to {}... (Esc to cancel)", address)), f.area()))?;
+    let target = Target::Address(address);
+    let dev = tokio::select! {
+        dev = session.connect(&target, timeout) => dev?,
+        _ = screen.cancelled() => return Ok(()),
+    };
+
+    let notifications = dev.notifications().await?;
+    let mut tree = GattTree::new(dev.services(), gatt_record(&dev, false, names).await);
+    let result = tree.run(&dev, screen, notifications).await;
+
+    // The link may be gone already, nothing left to clean up then
+    for (s, c) in tree.subscribed.iter() {
+        dev.unsubscribe(tree.services[*s].characteristics[*c].uuid).await.ok();
+    }
+    dev.disconnect().await.ok();
+    result
+}
+
+/// A row of the GATT tree.
+#[derive(Clone, Copy, PartialEq, Eq, Hash)]
+enum Node {
+    Service(usize),
+    Characteristic(usize, usize),
+    Descriptor(usize, usize, usize),
+}
+
+/// The services of a connected device, as a tree of attributes with the values read so far.
+struct GattTree {
+    services: Vec<ServiceInfo>,
+    /// Names of the attributes, and their values once read
+    record: GattRecord,
+    /// Services and characteristics whose children are shown
+    expanded: HashSet<Node>,
+    selected: usize,
+    /// Characteristics subscribed to, by service and characteristic index
+    subscribed: HashSet<(usize, usize)>,
+    /// Value being typed to write to the selected characteristic
+    input: Option<String>,
+    status: String,
+}
+
+impl GattTree {
+    fn new(services: Vec<ServiceInfo>, record: GattRecord) -> GattTree {
+        GattTree {
+            expanded: (0..services.len()).map(Node::Service).collect(),
+            services,
+            record,
+            selected: 0,
+            subscribed: HashSet::new(),
+            input: None,
+            status: String::new(),
+        }
+    }
+
+    async fn run(
+        &mut self,
+        dev: &Device,
+        screen: &mut Screen,
+        mut notifications: EventStream<ValueNotification>,
+    ) -> Result<(), Box<dyn Error>> {
+        loop {
+            screen.draw(|f| self.render(f))?;
+
+            tokio::select! {
+                event = screen.next_event() => {
+                    if let Event::Key(key) = event {
+                        if !self.key(key, dev).await {
+                            return Ok(());
+                        }
+                    }
+                }
+                Some(n) = notifications.next() => self.notified(n),
+            }
+        }
+    }
+
+    /// The rows shown, services being followed by their characteristics when expanded.
+    fn rows(&self) -> Vec<Node> {
+        let mut rows = Vec::new();
+        for (s, service) in self.services.iter().enumerate() {
+            rows.push(Node::Service(s));
+            if !self.expanded.contains(&Node::Service(s)) {
+                continue;
+            }
+
+            for (c, characteristic) in service.characteristics.iter().enumerate() {
+                rows.push(Node::Characteristic(s, c));
+                if self.expanded.contains(&Node::Characteristic(s, c)) {
+                    rows.extend((0..characteristic.descriptors.len()).map(|d| Node::Descriptor(s, c, d)));
+                }
+            }
+        }
+        rows
+    }
+
+    /// Handles a key, returning false to go back to the device table.
+    async fn key(&mut self, key: KeyEvent, dev: &Device) -> bool {
+        if let Some(input) = self.input.as_mut() {
+            match key.code {
+                KeyCode::Esc => self.input = None,
+                KeyCode::Enter => {
+                    let input = self.input.take().unwrap_or_default();
+                    self.write(dev, &input).await;
+                }
+                KeyCode::Backspace => {
+                    input.pop();
+                }
+                KeyCode::Char(c) => input.push(c),
+                _ => {}
+            }
+            return true;
+        }
+
+        if is_back(key) {
+            return false;
+        }
+        self.status.clear();
+
+        let rows = self.rows();
+        let node = match rows.get(self.selected) {
+            Some(node) => *node,
+            None => return true,
+        };
+        match key.code {
+            KeyCode::Up | KeyCode::Char('k') => self.selected = self.selected.saturating_sub(1),
+            KeyCode::Down | KeyCode::Char('j') => self.selected = (self.selected + 1).min(rows.len() - 1),
+            KeyCode::Enter | KeyCode::Char(' ') => {
+                let expanded = self.expanded.contains(&node);
+                self.expand(node, !expanded);
+            }
+            KeyCode::Right | KeyCode::Char('l') => self.expand(node, true),
+            // Collapse, or go up to the parent
+            KeyCode::Left | KeyCode::Char('h') if self.expanded.contains(&node) => self.expand(node, false),
+            KeyCode::Left | KeyCode::Char('h') => {
+                let parent = match node {
+                    Node::Service(_) => node,
+                    Node::Characteristic(s, _) => Node::Service(s),
+                    Node::Descriptor(s, c, _) => Node::Characteristic(s, c),
+                };
+                self.selected = rows.iter().position(|n| *n == parent).unwrap_or(self.selected);
+            }
+            KeyCode::Char('r') => self.read(dev, node).await,
+            KeyCode::Char('w') => match node {
+                Node::Characteristic(s, c) => {
+                    let properties = self.services[s].characteristics[c].properties;
+                    if properties.intersects(CharPropFlags::WRITE | CharPropFlags::WRITE_WITHOUT_RESPONSE) {
+                        self.input = Some(String::new());
+                    } else {
+                        self.status = "Characteristic not writable".into();
+                    }
+                }
+                _ => self.status = "Only characteristics can be written".into(),
+            },
+            KeyCode::Char('n') => match node {
+                Node::Characteristic(s, c) => self.toggle_notifications(dev, s, c).await,
+                _ => self.status = "Only characteristics notify".into(),
+            },
+            _ => {}
+        }
+        true
+    }
+
+    fn expand(&mut self, node: Node, expanded: bool) {
+        if expanded {
+            self.expanded.insert(node);
+        } else {
+            self.expanded.remove(&node);
+        }
+    }
+
+    async fn read(&mut self, dev: &Device, node: Node) {
+        let (result, record) = match node {
+            Node::Service(_) => return,
+            Node::Characteristic(s, c) => {
+                let uuid = self.services[s].characteristics[c].uuid;
+                let record = &mut self.record.services[s].characteristics[c];
+                (dev.read(uuid).await, (&mut record.value, &mut record.error))
+            }
+            Node::Descriptor(s, c, d) => {
+                let characteristic = &self.services[s].characteristics[c];
+                let record = &mut self.record.services[s].characteristics[c].descriptors[d];
+                let result = dev.read_descriptor(characteristic.uuid, characteristic.descriptors[d]).await;
+                (result, (&mut record.value, &mut record.error))
+            }
+        };
+
+        let (value, error) = record;
+        match result {
+            Ok(data) => {
+                *value = Some(hex(&data));
+                *error = None;
+            }
+            Err(e) => *error = Some(e.to_string()),
+        }
+    }
+
+    /// Writes the value typed, as hex or as text in double quotes, to the selected characteristic.
+    async fn write(&mut self, dev: &Device, input: &str) {
+        let Some(Node::Characteristic(s, c)) = self.rows().get(self.selected).copied() else {
+            return;
+        };
+        let characteristic = &self.services[s].characteristics[c];
+
+        let data = match input.strip_prefix('"') {
+            Some(text) => text.strip_suffix('"').unwrap_or(text).as_bytes().to_vec(),
+            None => match parse_hex(input) {
+                Ok(data) => data,
+                Err(e) => {
+                    self.status = e;
+                    return;
+                }
+            },
+        };
+        let write_type = if characteristic.properties.contains(CharPropFlags::WRITE) {
+            WriteType::WithResponse
+        } else {
+            WriteType::WithoutResponse
+        };
+
+        self.status = match dev.write_chunked(characteristic.uuid, &data, write_type, &Chunking::default()).await {
+            Ok(_) => format!("Wrote {} bytes", data.len()),
+            Err(e) => format!("Write failed: {}", e),
+        };
+    }
+
+    async fn toggle_notifications(&mut self, dev: &Device, s: usize, c: usize) {
+        let characteristic = &self.services[s].characteristics[c];
+        if !characteristic.properties.intersects(CharPropFlags::NOTIFY | CharPropFlags::INDICATE) {
+            self.status = "Characteristic doesn't notify".into();
+            return;
+        }
+
+        let result = if self.subscribed.remove(&(s, c)) {
+            dev.unsubscribe(characteristic.uuid).await
+        } else {
+            self.subscribed.insert((s, c));
+            dev.subscribe(characteristic.uuid).await
+        };
+        if let Err(e) = result {
+            self.subscribed.remove(&(s, c));
+            self.status = e.to_string();
+        }
+    }
+
+    fn notified(&mut self, n: ValueNotification) {
+        for &(s, c) in self.subscribed.iter() {
+            if self.services[s].characteristics[c].uuid == n.uuid {
+                let record = &mut self.record.services[s].characteristics[c];
+                record.value = Some(hex(&n.value));
+                record.error = None;
+            }
+        }
+    }
+
+    fn render(&self, f: &mut Frame) {
+        let [header, body, status, footer] = Layout::vertical([
+            Constraint::Length(1),
+            Constraint::Min(3),
+            Constraint::Length(1),
+            Constraint::Length(1),
+        ])
+        .areas(f.area());
+
+        f.render_widget(Line::from(format!("{}: {} services", self.record.address, self.services.len())), header);
+
+        let rows = self.rows();
+        let items = rows.iter().map(|node| self.row(*node));
+        let list = List::new(items).highlight_style(Style::new().reversed());
+        let mut state = ListState::default().with_selected(Some(self.selected.min(rows.len().saturating_sub(1))));
+        f.render_stateful_widget(list, body, &mut state);
+
+        let status_line = match &self.input {
+            Some(input) => Line::from(format!("Write (hex, or \"text\"): {}_", input)),
+            None => Line::from(Span::styled(self.status.as_str(), Style::new().red())),
+        };
+        f.render_widget(status_line, status);
+
+        let help = if self.input.is_some() {
+            "Enter write  Esc cancel"
+        } else {
+            "↑↓ select  ←→ collapse/expand  r read  w write  n notifications  q back"
+        };
+        f.render_widget(Line::from(help).reversed(), footer);
+    }
+
+    fn row(&self, node: Node) -> Line<'static> {
+        let marker = |expandable: bool| match (expandable, self.expanded.contains(&node)) {
+            (false, _) => "  ",
+            (true, true) => "▾ ",
+            (true, false) => "▸ ",
+        };
+
+        match node {
+            Node::Service(s) => {
+                let r = &self.record.services[s];
+                Line::from(format!("{}{}", marker(true), named(&r.uuid, r.name.as_deref()))).bold()
+            }
+            Node::Characteristic(s, c) => {
+                let info = &self.services[s].characteristics[c];
+                let r = &self.record.services[s].characteristics[c];
+                let subscribed = if self.subscribed.contains(&(s, c)) { ", subscribed" } else { "" };
+                let mut spans = vec![Span::raw(format!(
+                    "    {}{} [{}{}]",
+                    marker(!info.descriptors.is_empty()),
+                    named(&r.uuid, r.name.as_deref()),
+                    property_names(info.properties).join(", "),
+                    subscribed,
+                ))];
+                spans.extend(value_span(&r.value, &r.error));
+                Line::from(spans)
+            }
+            Node::Descriptor(s, c, d) => {
+                let r = &self.record.services[s].characteristics[c].descriptors[d];
+                let mut spans = vec![Span::raw(format!("          {}", named(&r.uuid, r.name.as_deref())))];
+                spans.extend(value_span(&r.value, &r.error));
+                Line::from(spans)
+            }
+        }
+    }
+}
+
+/// The value of an attribute once read, as hex followed by the text it holds if any.
+fn value_span(value: &Option<String>, error: &Option<String>) -> Option<Span<'static>> {
+    match (value, error) {
+        (_, Some(error)) => Some(Span::styled(format!(" error: {}", error), Style::new().red())),
+        (Some(value), None) if value.is_empty() => Some(Span::raw(" = (empty)").cyan()),
+        (Some(value), None) => match printable(value) {
+            Some(text) => Some(Span::raw(format!(" = {} {:?}", value, text)).cyan()),
+            None => Some(Span::raw(format!(" = {}", value)).cyan()),
+        },
+        (None, None) => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use ble_util::{ScanEventKind, Uuid};
+    use btleplug::api::bleuuid::uuid_from_u16;
+    use btleplug::api::PeripheralProperties;
+
+    use super::*;
+    use crate::cli::{parse_command, Command};
+
+    #[test]
+    fn draws_signal_strengths() {
+        assert_eq!(sparkline(&VecDeque::new()), "");
+        assert_eq!(sparkline(&VecDeque::from([-100, -65, -30])), "▁▄█");
+        // Out of range values are clamped
+        assert_eq!(sparkline(&VecDeque::from([-127, 0, 20])), "▁██");
+    }
+
+    #[test]
+    fn shortens_ages() {
+        assert_eq!(age(Duration::from_millis(999)), "now");
+        assert_eq!(age(Duration::from_secs(59)), "59s");
+        assert_eq!(age(Duration::from_secs(60)), "1m");
+        assert_eq!(age(Duration::from_secs(3599)), "59m");
+        assert_eq!(age(Duration::from_secs(7200)), "2h");
+    }
+
+    fn table(args: &[&str]) -> DeviceTable {
+        let args: Vec<&str> = ["dashboard"].iter().chain(args).copied().collect();
+        match parse_command(&args) {
+            Ok(Command::Dashboard(args)) => DeviceTable::new(&args),
+            _ => unreachable!("parsed dashboard"),
+        }
+    }
+
+    /// Feeds the table an advertisement from `aa:bb:cc:dd:ee:<last>`.
+    fn see(table: &mut DeviceTable, last: u8, name: Option<&str>, rssi: Option<i16>, data: &[(Uuid, &[u8])]) {
+        let props = PeripheralProperties {
+            address: [0xaa, 0xbb, 0xcc, 0xdd, 0xee, last].into(),
+            local_name: name.map(String::from),
+            rssi,
+            service_data: data.iter().map(|(uuid, data)| (*uuid, data.to_vec())).collect(),
+            ..Default::default()
+        };
+        let event = ScanEvent { kind: ScanEventKind::Discovered, device: props.into(), seen: SystemTime::now() };
+        table.update(event, &Names::new(), &Decoders::builtin());
+    }
+
+    fn visible(table: &DeviceTable) -> Vec<u8> {
+        table.visible().iter().map(|(a, _)| a.into_inner()[5]).collect()
+    }
+
+    /// Devices with and without names and signal strengths, one of them a BTHome sensor.
+    fn seen(args: &[&str]) -> DeviceTable {
+        let mut table = table(args);
+        see(&mut table, 3, Some("beta"), Some(-70), &[]);
+        see(&mut table, 1, None, Some(-50), &[]);
+        see(&mut table, 4, Some("Alpha"), None, &[]);
+        see(&mut table, 2, None, None, &[(uuid_from_u16(0xfcd2), &[0x40, 0x01, 87])]);
+        table
+    }
+
+    #[test]
+    fn lists_no_devices() {
+        let table = table(&[]);
+        assert!(table.visible().is_empty());
+        assert_eq!(table.selected_index(&table.visible()), None);
+    }
+
+    #[test]
+    fn sorts_devices() {
+        let mut table = seen(&["--sort", "address"]);
+        assert_eq!(visible(&table), [1, 2, 3, 4]);
+        // Strongest first, then those without a signal strength by address
+        table.sort = SortOrder::Rssi;
+        assert_eq!(visible(&table), [1, 3, 2, 4]);
+        // Names compare as they are, then the unnamed ones by address
+        table.sort = SortOrder::Name;
+        assert_eq!(visible(&table), [4, 3, 1, 2]);
+    }
+
+    #[test]
+    fn filters_devices() {
+        let mut table = seen(&["--sort", "address", "--filter", "ALPHA"]);
+        assert_eq!(visible(&table), [4]);
+        table.filter = "ee:01".into();
+        assert_eq!(visible(&table), [1]);
+        // On what the advertisement decodes to
+        table.filter = "bthome".into();
+        assert_eq!(visible(&table), [2]);
+        table.filter = "a".into();
+        assert_eq!(visible(&table), [1, 2, 3, 4]);
+        table.filter = "gamma".into();
+        assert!(visible(&table).is_empty());
+    }
+
+    #[test]
+    fn keeps_signal_history() {
+        let mut table = table(&[]);
+        for rssi in 0..HISTORY as i16 + 5 {
+            see(&mut table, 1, None, Some(-100 + rssi), &[]);
+        }
+        // No strength in the latest advertisement keeps the history as it was
+        see(&mut table, 1, None, None, &[]);
+
+        let history = &table.visible()[0].1.history;
+        assert_eq!(history.len(), HISTORY);
+        assert_eq!(history.front(), Some(&-95));
+        assert_eq!(history.back(), Some(&(-100 + HISTORY as i16 + 4)));
+    }
+}
@@ -0,0 +1,393 @@
+use std::error::Error;
+use std::fs;
+use std::path::Path;
+
+use ble_util::backend::Backend;
+use ble_util::{AdapterSelector, BleUtilError, Names, Session, Target};
+use serde_json::json;
+
+use super::gatt::{connect, gatt_record};
+use super::output::{
+    named, ChangeRecord, CharacteristicRecord, DescriptorRecord, GattRecord, Output, ServiceRecord,
+};
+use super::{DeviceArgs, GattDiffArgs};
+
+pub async fn gatt_diff(
+    backend: Box<dyn Backend>,
+    adapter: &AdapterSelector,
+    args: &GattDiffArgs,
+    names: &Names,
+    out: Output,
+) -> Result<(), Box<dyn Error>> {
+    let old = load(&args.old)?;
+
+    let new = if Path::new(&args.new).is_file() {
+        load(Path::new(&args.new))?
+    } else {
+        let device = DeviceArgs {
+            target: args.new.parse::<Target>()?,
+            timeout: args.timeout,
+        };
+        let session = Session::with_adapter(backend, adapter).await?;
+        let dev = connect(&session, &device, out).await?;
+
+        // Only compare values if the snapshot has some
+        let values = old.services.iter()
+            .flat_map(|s| s.characteristics.iter())
+            .any(|c| c.value.is_some() || c.descriptors.iter().any(|d| d.value.is_some()));
+        gatt_record(&dev, values, names).await
+    };
+
+    let changes = diff(&old, &new);
+    out.list(&changes, |c| {
+        let mark = match c.change {
+            "added" => '+',
+            "removed" => '-',
+            _ => '~',
+        };
+
+        let uuid = c.descriptor.as_ref().or(c.characteristic.as_ref()).unwrap_or(&c.service);
+        let mut line = format!("{} {} {}", mark, c.attribute, named(uuid, c.name.as_deref()));
+        if let Some(characteristic) = c.descriptor.as_ref().and(c.characteristic.as_ref()) {
+            line += &format!(" of characteristic {}", characteristic);
+        }
+        if c.characteristic.is_some() {
+            line += &format!(" in service {}", c.service);
+        }
+        if let Some(field) = c.field {
+            line += &format!(": {} {} -> {}", field, text(&c.old), text(&c.new));
+        }
+        println!("{}", line);
+    });
+
+    if changes.is_empty() {
+        out.status("No differences");
+        Ok(())
+    } else {
+        Err(BleUtilError::Mismatch(changes.len()).into())
+    }
+}
+
+fn load(path: &Path) -> Result<GattRecord, Box<dyn Error>> {
+    let json = fs::read_to_string(path).map_err(|e| format!("{}: {}", path.display(), e))?;
+    Ok(serde_json::from_str(&json).map_err(|e| format!("{}: {}", path.display(), e))?)
+}
+
+fn text(value: &Option<serde_json::Value>) -> String {
+    match value {
+        Some(serde_json::Value::String(s)) => s.clone(),
+        Some(serde_json::Value::Array(items)) => {
+            let items: Vec<String> = items.iter().map(|i| text(&Some(i.clone()))).collect();
+            format!("[{}]", items.join(", "))
+        }
+        Some(v) => v.to_string(),
+        None => "null".into(),
+    }
+}
+
+/// Lists the differences from `old` to `new`. Attributes are matched by UUID, in order when the
+/// same UUID appears several times.
+fn diff(old: &GattRecord, new: &GattRecord) -> Vec<ChangeRecord> {
+    let mut changes = Vec::new();
+
+    for pair in pair(&old.services, &new.services, |s| &s.uuid) {
+        match pair {
+            (Some(s), None) => changes.push(service_change("removed", s)),
+            (None, Some(s)) => changes.push(service_change("added", s)),
+            (Some(a), Some(b)) => {
+                if a.primary != b.primary {
+                    let mut c = service_change("changed", b);
+                    c.field = Some("primary");
+                    c.old = Some(json!(a.primary));
+                    c.new = Some(json!(b.primary));
+                    changes.push(c);
+                }
+                diff_characteristics(a, b, &mut changes);
+            }
+            (None, None) => unreachable!(),
+        }
+    }
+
+    changes
+}
+
+fn diff_characteristics(old: &ServiceRecord, new: &ServiceRecord, changes: &mut Vec<ChangeRecord>) {
+    for pair in pair(&old.characteristics, &new.characteristics, |c| &c.uuid) {
+        match pair {
+            (Some(c), None) => changes.push(characteristic_change("removed", new, c)),
+            (None, Some(c)) => changes.push(characteristic_change("added", new, c)),
+            (Some(a), Some(b)) => {
+                if a.properties != b.properties {
+                    let mut c = characteristic_change("changed", new, b);
+                    c.field = Some("properties");
+                    c.old = Some(json!(a.properties));
+                    c.new = Some(json!(b.properties));
+                    changes.push(c);
+                }
+                // Values are only compared when both sides were read
+                if let (Some(old_value), Some(new_value)) = (&a.value, &b.value) {
+                    if old_value != new_value {
+                        let mut c = characteristic_change("changed", new, b);
+                        c.field = Some("value");
+                        c.old = Some(json!(old_value));
+                        c.new = Some(json!(new_value));
+                        changes.push(c);
+                    }
+                }
+                diff_descriptors(new, a, b, changes);
+            }
+            (None, None) => unreachable!(),
+        }
+    }
+}
+
+fn diff_descriptors(
+    service: &ServiceRecord,
+    old: &CharacteristicRecord,
+    new: &CharacteristicRecord,
+    changes: &mut Vec<ChangeRecord>,
+) {
+    for pair in pair(&old.descriptors, &new.descriptors, |d| &d.uuid) {
+        match pair {
+            (Some(d), None) => changes.push(descriptor_change("removed", service, new, d)),
+            (None, Some(d)) => changes.push(descriptor_change("added", service, new, d)),
+            (Some(a), Some(b)) => {
+                if let (Some(old_value), Some(new_value)) = (&a.value, &b.value) {
+                    if old_value != new_value {
+                        let mut c = descriptor_change("changed", service, new, b);
+                        c.field = Some("value");
+                        c.old = Some(json!(old_value));
+                        c.new = Some(json!(new_value));
+                        changes.push(c);
+                    }
+                }
+            }
+            (None, None) => unreachable!(),
+        }
+    }
+}
+
+/// Matches the elements of `old` and `new` with the same UUID. Unmatched ones are paired with
+/// `None`, removed ones coming first.
+fn pair<'a, T>(old: &'a [T], new: &'a [T], uuid: impl Fn(&T) -> &String) -> Vec<(Option<&'a T>, Option<&'a T>)> {
+    let mut used = vec![false; new.len()];
+    let mut pairs = Vec::new();
+
+    for a in old {
+        let matching = (0..new.len()).find(|&i| !used[i] && uuid(a) == uuid(&new[i]));
+        match matching {
+            Some(i) => {
+                used[i] = true;
+                pairs.push((Some(a), Some(&new[i])));
+            }
+            None => pairs.push((Some(a), None)),
+        }
+    }
+
+    for (b, used) in new.iter().zip(used) {
+        if !used {
+            pairs.push((None, Some(b)));
+        }
+    }
+
+    pairs
+}
+
+fn service_change(change: &'static str, s: &ServiceRecord) -> ChangeRecord {
+    ChangeRecord {
+        change,
+        attribute: "service",
+        service: s.uuid.clone(),
+        characteristic: None,
+        descriptor: None,
+        name: s.name.clone(),
+        field: None,
+        old: None,
+        new: None,
+    }
+}
+
+fn characteristic_change(change: &'static str, s: &ServiceRecord, c: &CharacteristicRecord) -> ChangeRecord {
+    ChangeRecord {
+        attribute: "characteristic",
+        characteristic: Some(c.uuid.clone()),
+        name: c.name.clone(),
+        ..service_change(change, s)
+    }
+}
+
+fn descriptor_change(
+    change: &'static str,
+    s: &ServiceRecord,
+    c: &CharacteristicRecord,
+    d: &DescriptorRecord,
+) -> ChangeRecord {
+    ChangeRecord {
+        attribute: "descriptor",
+        descriptor: Some(d.uuid.clone()),
+        name: d.name.clone(),
+        ..characteristic_change(change, s, c)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const BATTERY: &str = "0000180f-0000-1000-8000-00805f9b34fb";
+    const LEVEL: &str = "00002a19-0000-1000-8000-00805f9b34fb";
+    const DEVICE_INFO: &str = "0000180a-0000-1000-8000-00805f9b34fb";
+    const CCCD: &str = "00002902-0000-1000-8000-00805f9b34fb";
+    const FORMAT: &str = "00002904-0000-1000-8000-00805f9b34fb";
+
+    fn characteristic(uuid: &str, properties: &[&str], value: Option<&str>, descriptors: serde_json::Value) -> serde_json::Value {
+        json!({
+            "uuid": uuid, "name": null, "properties": properties, "value": value, "error": null,
+            "descriptors": descriptors,
+        })
+    }
+
+    fn descriptor(uuid: &str, value: Option<&str>) -> serde_json::Value {
+        json!({"uuid": uuid, "name": null, "value": value, "error": null})
+    }
+
+    fn service(uuid: &str, characteristics: Vec<serde_json::Value>) -> serde_json::Value {
+        json!({"uuid": uuid, "name": null, "primary": true, "characteristics": characteristics})
+    }
+
+    fn record(services: Vec<serde_json::Value>) -> GattRecord {
+        serde_json::from_value(json!({"address": "AA:BB:CC:DD:EE:01", "services": services})).unwrap()
+    }
+
+    /// A battery service whose level has the given properties, value and descriptors.
+    fn battery(properties: &[&str], value: Option<&str>, descriptors: serde_json::Value) -> serde_json::Value {
+        service(BATTERY, vec![characteristic(LEVEL, properties, value, descriptors)])
+    }
+
+    /// Each change as its kind, the attribute changed, its UUID and the field changed.
+    fn summary(changes: &[ChangeRecord]) -> Vec<(&str, &str, &str, Option<&str>)> {
+        changes.iter()
+            .map(|c| {
+                let uuid = c.descriptor.as_ref().or(c.characteristic.as_ref()).unwrap_or(&c.service);
+                (c.change, c.attribute, uuid.as_str(), c.field)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn identical_databases_have_no_differences() {
+        let a = record(vec![battery(&["read"], Some("64"), json!([descriptor(CCCD, Some("0000"))]))]);
+        assert!(diff(&a, &a).is_empty());
+        assert!(diff(&record(vec![]), &record(vec![])).is_empty());
+    }
+
+    #[test]
+    fn lists_added_and_removed_services() {
+        let old = record(vec![battery(&["read"], None, json!([]))]);
+        let new = record(vec![service(DEVICE_INFO, vec![])]);
+        assert_eq!(
+            summary(&diff(&old, &new)),
+            [("removed", "service", BATTERY, None), ("added", "service", DEVICE_INFO, None)]
+        );
+    }
+
+    #[test]
+    fn lists_changed_services() {
+        let old = record(vec![service(BATTERY, vec![])]);
+        let mut secondary = service(BATTERY, vec![]);
+        secondary["primary"] = json!(false);
+        let changes = diff(&old, &record(vec![secondary]));
+        assert_eq!(summary(&changes), [("changed", "service", BATTERY, Some("primary"))]);
+        assert_eq!(changes[0].old, Some(json!(true)));
+        assert_eq!(changes[0].new, Some(json!(false)));
+    }
+
+    #[test]
+    fn lists_added_and_removed_characteristics() {
+        let old = record(vec![battery(&["read"], None, json!([]))]);
+        let new = record(vec![service(BATTERY, vec![])]);
+        let changes = diff(&old, &new);
+        assert_eq!(summary(&changes), [("removed", "characteristic", LEVEL, None)]);
+        assert_eq!(changes[0].service, BATTERY);
+        assert_eq!(summary(&diff(&new, &old)), [("added", "characteristic", LEVEL, None)]);
+    }
+
+    #[test]
+    fn lists_property_changes() {
+        let old = record(vec![battery(&["read"], None, json!([]))]);
+        let new = record(vec![battery(&["read", "notify"], None, json!([]))]);
+        let changes = diff(&old, &new);
+        assert_eq!(summary(&changes), [("changed", "characteristic", LEVEL, Some("properties"))]);
+        assert_eq!(changes[0].old, Some(json!(["read"])));
+        assert_eq!(changes[0].new, Some(json!(["read", "notify"])));
+    }
+
+    #[test]
+    fn lists_value_changes_when_both_have_values() {
+        let old = record(vec![battery(&["read"], Some("64"), json!([]))]);
+        let new = record(vec![battery(&["read"], Some("32"), json!([]))]);
+        let changes = diff(&old, &new);
+        assert_eq!(summary(&changes), [("changed", "characteristic", LEVEL, Some("value"))]);
+        assert_eq!(changes[0].old, Some(json!("64")));
+        assert_eq!(changes[0].new, Some(json!("32")));
+
+        // Exported without values on one side
+        let unread = record(vec![battery(&["read"], None, json!([]))]);
+        assert!(diff(&old, &unread).is_empty());
+        assert!(diff(&unread, &new).is_empty());
+    }
+
+    #[test]
+    fn lists_descriptor_changes() {
+        let old = record(vec![battery(&["read"], None, json!([descriptor(CCCD, Some("0000"))]))]);
+        let new = record(vec![battery(&["read"], None, json!([descriptor(CCCD, Some("0100")), descriptor(FORMAT, None)]))]);
+        let changes = diff(&old, &new);
+        assert_eq!(
+            summary(&changes),
+            [("changed", "descriptor", CCCD, Some("value")), ("added", "descriptor", FORMAT, None)]
+        );
+        assert_eq!(changes[0].characteristic.as_deref(), Some(LEVEL));
+        assert_eq!(summary(&diff(&new, &old))[1], ("removed", "descriptor", FORMAT, None));
+
+        // Without values on one side
+        let unread = record(vec![battery(&["read"], None, json!([descriptor(CCCD, None)]))]);
+        assert!(diff(&old, &unread).is_empty());
+    }
+
+    #[test]
+    fn matches_duplicate_uuids_in_order() {
+        let two = |first, second| {
+            record(vec![service(BATTERY, vec![
+                characteristic(LEVEL, &["read"], Some(first), json!([])),
+                characteristic(LEVEL, &["read"], Some(second), json!([])),
+            ])])
+        };
+        assert!(diff(&two("01", "02"), &two("01", "02")).is_empty());
+
+        let changes = diff(&two("01", "02"), &two("01", "03"));
+        assert_eq!(summary(&changes), [("changed", "characteristic", LEVEL, Some("value"))]);
+        assert_eq!(changes[0].old, Some(json!("02")));
+
+        // The second of two is the one gone
+        let one = record(vec![battery(&["read"], Some("01"), json!([]))]);
+        let changes = diff(&two("01", "02"), &one);
+        assert_eq!(summary(&changes), [("removed", "characteristic", LEVEL, None)]);
+    }
+
+    #[test]
+    fn pairs_in_order_with_removed_first() {
+        let old = ["a", "b", "a"].map(String::from);
+        let new = ["c", "a", "a", "a"].map(String::from);
+        let pairs: Vec<(Option<&String>, Option<&String>)> = pair(&old, &new, |s| s);
+        let indexes: Vec<(Option<usize>, Option<usize>)> = pairs.iter()
+            .map(|(a, b)| {
+                let index = |x: &String, all: &[String]| all.iter().position(|y| std::ptr::eq(x, y));
+                (a.and_then(|a| index(a, &old)), b.and_then(|b| index(b, &new)))
+            })
+            .collect();
+        assert_eq!(
+            indexes,
+            [(Some(0), Some(1)), (Some(1), None), (Some(2), Some(2)), (None, Some(0)), (None, Some(3))]
+        );
+    }
+}
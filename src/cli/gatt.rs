@@ -0,0 +1,463 @@
+use std::error::Error;
+use std::fs;
+use std::time::{Duration, SystemTime};
+
+use ble_util::{
+    hex, BleUtilError, CharPropFlags, CharacteristicInfo, Device, Names, Session, Uuid, ValueFormat, WriteType,
+};
+use btleplug::api::bleuuid::uuid_from_u16;
+use tokio::{signal, time};
+use tokio_stream::StreamExt;
+
+use super::output::{
+    local_time, named, value_json, write_json, CharacteristicRecord, GattRecord, NotificationRecord, Output, ReadRecord,
+    ServiceRecord, WriteRecord,
+};
+use super::nus::nus;
+use super::{parse_hex, DeviceArgs, LineEnding, NusArgs, PingArgs, SubscribeArgs, WriteArgs};
+
+/// Nordic UART service RX characteristic, written to.
+pub const CHAR_WRITE: Uuid = Uuid::from_u128(0x6e400002_b5a3_f393_e0a9_e50e24dcca9e);
+/// Nordic UART service TX characteristic, notifying what the device sends.
+pub const CHAR_READ: Uuid = Uuid::from_u128(0x6e400003_b5a3_f393_e0a9_e50e24dcca9e);
+/// Appearance characteristic of the Generic Access service.
+const APPEARANCE: Uuid = uuid_from_u16(0x2a01);
+
+pub async fn ping(session: &Session, args: &PingArgs, names: &Names, out: Output) -> Result<(), Box<dyn Error>> {
+    let dev = connect(session, &args.device, out).await?;
+    let values = args.values;
+
+    let record = gatt_record(&dev, values, names).await;
+    if let Some(path) = &args.export {
+        write_json(path, &record)?;
+    }
+
+    // Print out the device servers and characteristics
+    out.value(&record, |record| {
+        if let Some(appearance) = &record.appearance {
+            println!("Appearance: {}", appearance);
+        }
+        println!("Services:");
+        for s in record.services.iter() {
+            println!("{}:", named(&s.uuid, s.name.as_deref()));
+
+            for c in s.characteristics.iter() {
+                println!("\t{}: {}", named(&c.uuid, c.name.as_deref()), c.properties.join(", "));
+                if !values {
+                    continue;
+                }
+
+                print_value("value", &c.value, &c.error);
+                for d in c.descriptors.iter() {
+                    print_value(&named(&d.uuid, d.name.as_deref()), &d.value, &d.error);
+                }
+            }
+        }
+    });
+
+    Ok(())
+}
+
+/// Snapshot of the GATT database of a device, optionally with the values of its attributes.
+pub async fn gatt_record(dev: &Device, values: bool, names: &Names) -> GattRecord {
+    let mut services = Vec::new();
+    for s in dev.services() {
+        let mut record = ServiceRecord::new(s.clone(), names);
+        if values {
+            for (c, c_record) in s.characteristics.iter().zip(record.characteristics.iter_mut()) {
+                read_values(dev, c, c_record).await;
+            }
+        }
+        services.push(record);
+    }
+
+    GattRecord {
+        address: dev.address().to_string(),
+        appearance: appearance(dev, names).await,
+        services,
+    }
+}
+
+/// Name of the kind of device the Appearance characteristic describes, if the device has one.
+async fn appearance(dev: &Device, names: &Names) -> Option<String> {
+    let value = dev.read(APPEARANCE).await.ok()?;
+    let value = u16::from_le_bytes(value.get(..2)?.try_into().ok()?);
+    Some(names.appearance(value).map(String::from).unwrap_or_else(|| format!("{:#06x}", value)))
+}
+
+/// Reads the value of a characteristic, if readable, and of all its descriptors. Failures are
+/// recorded rather than returned so one protected attribute doesn't hide all the others.
+async fn read_values(dev: &Device, c: &CharacteristicInfo, record: &mut CharacteristicRecord) {
+    if c.properties.contains(CharPropFlags::READ) {
+        match dev.read(c.uuid).await {
+            Ok(value) => record.value = Some(hex(&value)),
+            Err(e) => record.error = Some(e.to_string()),
+        }
+    }
+
+    for (d, d_record) in c.descriptors.iter().zip(record.descriptors.iter_mut()) {
+        match dev.read_descriptor(c.uuid, *d).await {
+            Ok(value) => d_record.value = Some(hex(&value)),
+            Err(e) => d_record.error = Some(e.to_string()),
+        }
+    }
+}
+
+fn print_value(label: &str, value: &Option<String>, error: &Option<String>) {
+    match (value, error) {
+        (Some(value), _) if value.is_empty() => println!("\t\t{}: (empty)", label),
+        (Some(value), _) => match printable(value) {
+            Some(text) => println!("\t\t{}: {} {:?}", label, value, text),
+            None => println!("\t\t{}: {}", label, value),
+        },
+        (None, Some(error)) => println!("\t\t{}: error: {}", label, error),
+        // Not readable
+        (None, None) => {}
+    }
+}
+
+/// The text a hex encoded value holds, if it looks like text: printable UTF-8 of at least two
+/// characters, as single bytes are more likely numbers.
+pub fn printable(hex: &str) -> Option<String> {
+    let bytes = (0..hex.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(&hex[i..i + 2], 16))
+        .collect::<Result<Vec<u8>, _>>()
+        .ok()?;
+
+    String::from_utf8(bytes).ok().filter(|s| s.chars().count() >= 2 && !s.chars().any(char::is_control))
+}
+
+pub async fn read(
+    session: &Session,
+    device: &DeviceArgs,
+    char_id: Uuid,
+    format: Option<&ValueFormat>,
+    out: Output,
+) -> Result<(), Box<dyn Error>> {
+    let dev = connect(session, device, out).await?;
+
+    let res = dev.read(char_id).await?;
+    let mut record = ReadRecord::new(dev.address(), char_id, &res);
+
+    let text = if let Some(format) = format {
+        let value = format.decode(&res)?;
+        record.decoded = Some(value_json(&value));
+        value.to_string()
+    } else {
+        // Without an explicit format, use the one the device describes if any. A descriptor that
+        // can't be read or doesn't match the value only means falling back to the raw bytes.
+        let presentation = dev.presentation_format(char_id).await.ok().flatten();
+        match presentation.and_then(|p| Some((p, p.decode(&res).ok()?))) {
+            Some((p, value)) => {
+                let unit = p.unit_symbol();
+                record.decoded = Some(value_json(&value));
+                record.unit = Some(unit.map(String::from).unwrap_or_else(|| format!("{:#06x}", p.unit)));
+                match unit {
+                    Some(unit) if !unit.is_empty() => format!("{} {}", value, unit),
+                    _ => value.to_string(),
+                }
+            }
+            None => hex(&res),
+        }
+    };
+
+    out.value(&record, |_| println!("{}", text));
+    Ok(())
+}
+
+pub async fn write(session: &Session, args: &WriteArgs, out: Output) -> Result<(), Box<dyn Error>> {
+    let char_id = match args.characteristic {
+        Some(uuid) => uuid,
+        None => return write_uart(session, args, out).await,
+    };
+
+    let data = match (&args.value, &args.file) {
+        (_, Some(path)) => fs::read(path).map_err(|e| format!("{}: {}", path.display(), e))?,
+        (Some(value), None) if args.text => value.as_bytes().to_vec(),
+        (Some(value), None) => parse_hex(value)?,
+        // Enforced by the argument parser
+        (None, None) => unreachable!("write without a payload"),
+    };
+
+    let dev = connect(session, &args.device, out).await?;
+
+    let write_type = write_type(args, dev.characteristic(char_id)?.properties);
+
+    let chunks = if args.long {
+        // The stack turns it into prepared writes
+        dev.write(char_id, &data, write_type).await?;
+        1
+    } else {
+        dev.write_chunked(char_id, &data, write_type, &args.chunk.chunking()).await?
+    };
+
+    let record = WriteRecord {
+        address: dev.address().to_string(),
+        characteristic: char_id.to_string(),
+        length: data.len(),
+        chunks,
+        with_response: write_type == WriteType::WithResponse,
+    };
+    out.value(&record, |r| {
+        let ack = if r.with_response { "with response" } else { "without response" };
+        match r.chunks {
+            1 => println!("Wrote {} bytes {}", r.length, ack),
+            n => println!("Wrote {} bytes in {} chunks {}", r.length, n, ack),
+        }
+    });
+
+    Ok(())
+}
+
+/// The write type asked for, or else with response if the characteristic supports it.
+fn write_type(args: &WriteArgs, properties: CharPropFlags) -> WriteType {
+    if args.with_response || args.long {
+        WriteType::WithResponse
+    } else if args.without_response {
+        WriteType::WithoutResponse
+    } else if properties.contains(CharPropFlags::WRITE) {
+        WriteType::WithResponse
+    } else {
+        WriteType::WithoutResponse
+    }
+}
+
+pub async fn subscribe(session: &Session, args: &SubscribeArgs, out: Output) -> Result<(), Box<dyn Error>> {
+    let dev = connect(session, &args.device, out).await?;
+
+    // Listen before subscribing so the first values aren't missed
+    let mut notifications = dev.notifications().await?;
+    for (i, uuid) in args.characteristics.iter().enumerate() {
+        if let Err(e) = dev.subscribe(*uuid).await {
+            // Don't leave the device notifying the ones already subscribed to
+            for uuid in args.characteristics[..i].iter() {
+                dev.unsubscribe(*uuid).await.ok();
+            }
+            return Err(e.into());
+        }
+    }
+    out.status("Subscribed");
+
+    let stop = time::sleep(args.duration.unwrap_or(Duration::MAX));
+    tokio::pin!(stop);
+
+    let mut received = 0;
+    while args.count.is_none_or(|count| received < count) {
+        let n = tokio::select! {
+            n = notifications.next() => match n {
+                Some(n) => n,
+                None => break,
+            },
+            _ = signal::ctrl_c() => break,
+            _ = &mut stop => break,
+        };
+
+        if !args.characteristics.contains(&n.uuid) {
+            continue;
+        }
+        received += 1;
+
+        let time = local_time(SystemTime::now());
+        let decoded = args.value_format.as_ref().map(|f| f.decode(&n.value));
+        let record = NotificationRecord {
+            time: time.to_rfc3339(),
+            address: dev.address().to_string(),
+            characteristic: n.uuid.to_string(),
+            value: hex(&n.value),
+            decoded: match &decoded {
+                Some(Ok(value)) => Some(value_json(value)),
+                _ => None,
+            },
+        };
+
+        out.event(&record, |r| {
+            let value = match &decoded {
+                Some(Ok(value)) => value.to_string(),
+                Some(Err(e)) => format!("{} ({})", r.value, e),
+                None => r.value.clone(),
+            };
+            println!("{} {}: {}", time.format("%H:%M:%S%.3f"), r.characteristic, value);
+        });
+    }
+
+    // The link may be gone already, nothing left to clean up then
+    for uuid in args.characteristics.iter() {
+        dev.unsubscribe(*uuid).await.ok();
+    }
+    Ok(())
+}
+
+/// Writes each line of stdin to the Nordic UART service and prints what the device notifies, as
+/// `nus` does but without reconnecting.
+async fn write_uart(session: &Session, args: &WriteArgs, out: Output) -> Result<(), Box<dyn Error>> {
+    let args = NusArgs {
+        device: args.device.clone(),
+        eol: LineEnding::Lf,
+        raw: false,
+        no_reconnect: true,
+        chunk: args.chunk.clone(),
+    };
+    nus(session, &args, out).await
+}
+
+pub async fn connect(session: &Session, device: &DeviceArgs, out: Output) -> Result<Device, BleUtilError> {
+    let dev = session.connect(&device.target, device.timeout()).await?;
+    out.status("Connected");
+    Ok(dev)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicU8, Ordering};
+    use std::sync::Arc;
+
+    use ble_util::backend::MockPeripheral;
+
+    use super::*;
+    use crate::cli::output::Format;
+    use crate::cli::{mock_session, parse_command, Command};
+
+    const ADDRESS: &str = "aa:bb:cc:dd:ee:40";
+    const SERVICE: Uuid = uuid_from_u16(0x180f);
+    const BATTERY_LEVEL: Uuid = uuid_from_u16(0x2a19);
+    const TEMPERATURE: Uuid = uuid_from_u16(0x2a6e);
+    const TIMEOUT: Duration = Duration::from_secs(2);
+
+    /// A device notifying its battery level every 10ms, counting the values sent, and a
+    /// temperature that fails.
+    fn device(sent: Arc<AtomicU8>) -> MockPeripheral {
+        MockPeripheral::new([0xaa, 0xbb, 0xcc, 0xdd, 0xee, 0x40].into())
+            .service(SERVICE)
+            .characteristic(SERVICE, BATTERY_LEVEL, CharPropFlags::READ | CharPropFlags::NOTIFY, &[100])
+            .characteristic(SERVICE, TEMPERATURE, CharPropFlags::READ | CharPropFlags::NOTIFY, &[])
+            .fail(TEMPERATURE, "insufficient authentication")
+            .periodic(BATTERY_LEVEL, Duration::from_millis(10), move || vec![sent.fetch_add(1, Ordering::SeqCst)])
+    }
+
+    fn subscribe_args(args: &[&str]) -> SubscribeArgs {
+        let args: Vec<&str> = ["subscribe", ADDRESS].iter().chain(args).copied().collect();
+        match parse_command(&args) {
+            Ok(Command::Subscribe(args)) => args,
+            _ => unreachable!("parsed subscribe"),
+        }
+    }
+
+    #[tokio::test]
+    async fn subscribes_until_the_count() {
+        let sent = Arc::new(AtomicU8::new(0));
+        let session = mock_session(device(sent.clone())).await;
+
+        let args = subscribe_args(&["2a19", "--count", "3"]);
+        let res = time::timeout(TIMEOUT, subscribe(&session, &args, Output::new(Format::Ndjson))).await.unwrap();
+        assert!(res.is_ok(), "{:?}", res);
+        assert!(sent.load(Ordering::SeqCst) >= 3);
+
+        // Unsubscribed once done, so the device stops notifying
+        time::sleep(Duration::from_millis(50)).await;
+        let stopped = sent.load(Ordering::SeqCst);
+        time::sleep(Duration::from_millis(50)).await;
+        assert_eq!(sent.load(Ordering::SeqCst), stopped);
+    }
+
+    #[tokio::test]
+    async fn subscribes_for_the_duration() {
+        let sent = Arc::new(AtomicU8::new(0));
+        let session = mock_session(device(sent.clone())).await;
+
+        let args = subscribe_args(&["2a19", "--duration", "100ms"]);
+        let started = time::Instant::now();
+        let res = time::timeout(TIMEOUT, subscribe(&session, &args, Output::new(Format::Ndjson))).await.unwrap();
+        assert!(res.is_ok(), "{:?}", res);
+        assert!(started.elapsed() >= Duration::from_millis(100));
+        assert!(sent.load(Ordering::SeqCst) > 0);
+    }
+
+    #[tokio::test]
+    async fn unsubscribes_when_a_subscription_fails() {
+        let sent = Arc::new(AtomicU8::new(0));
+        let session = mock_session(device(sent.clone())).await;
+
+        let args = subscribe_args(&["2a19", "2a6e"]);
+        let res = time::timeout(TIMEOUT, subscribe(&session, &args, Output::new(Format::Ndjson))).await.unwrap();
+        assert!(res.is_err());
+
+        // The battery level subscription doesn't outlive the command
+        time::sleep(Duration::from_millis(50)).await;
+        let stopped = sent.load(Ordering::SeqCst);
+        time::sleep(Duration::from_millis(50)).await;
+        assert_eq!(sent.load(Ordering::SeqCst), stopped);
+    }
+
+    fn write_args(args: &[&str]) -> Result<WriteArgs, clap::Error> {
+        let args: Vec<&str> = ["write", ADDRESS].iter().chain(args).copied().collect();
+        parse_command(&args).map(|command| match command {
+            Command::Write(args) => args,
+            _ => unreachable!("parsed write"),
+        })
+    }
+
+    #[test]
+    fn chooses_write_types() {
+        let both = CharPropFlags::WRITE | CharPropFlags::WRITE_WITHOUT_RESPONSE;
+        let args = write_args(&["2a19", "01"]).unwrap();
+        assert_eq!(write_type(&args, both), WriteType::WithResponse);
+        assert_eq!(write_type(&args, CharPropFlags::WRITE_WITHOUT_RESPONSE), WriteType::WithoutResponse);
+
+        let args = write_args(&["2a19", "01", "--without-response"]).unwrap();
+        assert_eq!(write_type(&args, both), WriteType::WithoutResponse);
+        let args = write_args(&["2a19", "01", "--with-response"]).unwrap();
+        assert_eq!(write_type(&args, CharPropFlags::WRITE_WITHOUT_RESPONSE), WriteType::WithResponse);
+        let args = write_args(&["2a19", "01", "--long"]).unwrap();
+        assert_eq!(write_type(&args, both), WriteType::WithResponse);
+    }
+
+    #[test]
+    fn write_types_need_a_characteristic() {
+        // Writing lines to the Nordic UART service has no say in the write type
+        for flag in ["--with-response", "--without-response", "--long"] {
+            let err = write_args(&[flag]).err().unwrap();
+            assert_eq!(err.kind(), clap::error::ErrorKind::MissingRequiredArgument, "{}", flag);
+        }
+        assert!(write_args(&[]).is_ok());
+    }
+
+    #[tokio::test]
+    async fn refuses_unwritable_characteristics() {
+        let session = mock_session(device(Arc::new(AtomicU8::new(0)))).await;
+
+        let args = write_args(&["2a19", "01"]).unwrap();
+        let err = write(&session, &args, Output::new(Format::Ndjson)).await.unwrap_err();
+        assert!(matches!(err.downcast_ref::<BleUtilError>(), Some(BleUtilError::NotWritable(_))), "{}", err);
+    }
+
+    #[tokio::test]
+    async fn records_read_errors_per_characteristic() {
+        let info = uuid_from_u16(0x180a);
+        let (serial, manufacturer, control) = (uuid_from_u16(0x2a25), uuid_from_u16(0x2a29), uuid_from_u16(0x2a9f));
+        let peripheral = MockPeripheral::new([0xaa, 0xbb, 0xcc, 0xdd, 0xee, 0x40].into())
+            .service(info)
+            .characteristic(info, serial, CharPropFlags::READ, &[])
+            .characteristic(info, manufacturer, CharPropFlags::READ, b"ble-util")
+            .characteristic(info, control, CharPropFlags::WRITE, &[])
+            .descriptor(manufacturer, uuid_from_u16(0x2901), b"maker")
+            .fail(serial, "insufficient authentication");
+        let session = mock_session(peripheral).await;
+        let dev = session.connect(&ADDRESS.parse().unwrap(), TIMEOUT).await.unwrap();
+
+        let record = gatt_record(&dev, true, &Names::new()).await;
+        let c = &record.services[0].characteristics;
+        assert_eq!(c[0].value, None);
+        assert!(c[0].error.as_deref().unwrap().contains("insufficient authentication"), "{:?}", c[0].error);
+        // Reading goes on past the failure
+        assert_eq!(c[1].value.as_deref(), Some("626c652d7574696c"));
+        assert_eq!(c[1].error, None);
+        assert_eq!(c[1].descriptors[0].value.as_deref(), Some("6d616b6572"));
+        // Nothing to read
+        assert_eq!((&c[2].value, &c[2].error), (&None, &None));
+
+        let record = gatt_record(&dev, false, &Names::new()).await;
+        let c = &record.services[0].characteristics;
+        assert!(c.iter().all(|c| c.value.is_none() && c.error.is_none()));
+    }
+}
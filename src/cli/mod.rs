@@ -0,0 +1,562 @@
+//! Command line definition, and the implementation of each command.
+
+use std::path::PathBuf;
+use std::time::Duration;
+
+use ble_util::distance::{self, Smoothing};
+use ble_util::lookup::DEFAULT_TIMEOUT;
+use ble_util::{parse_uuid, AdapterSelector, Chunking, DeviceFilter, Target, Uuid, ValueFormat};
+use clap::{ArgGroup, Args, Parser, Subcommand, ValueEnum};
+use regex::Regex;
+
+pub mod adapters;
+pub mod dashboard;
+pub mod diff;
+pub mod gatt;
+pub mod nus;
+pub mod output;
+pub mod scan;
+pub mod transfer;
+pub mod verify;
+
+use output::Format;
+
+#[derive(Parser)]
+#[command(name = "ble-util", version, author = "Devin Vander Stelt <devin@vstelt.dev>")]
+#[command(about = "Scan for and talk to bluetooth low energy devices")]
+#[command(after_help = EXIT_CODES)]
+pub struct Cli {
+    /// Bluetooth stack to use; `mock` runs against a simulated adapter instead of the radio
+    #[arg(long, global = true, value_enum, default_value_t = BackendKind::Btleplug, env = "BLE_UTIL_BACKEND")]
+    pub backend: BackendKind,
+
+    /// Adapter to use, by index (see `adapters`) or by name such as `hci1`
+    #[arg(short, long, global = true, default_value = "0")]
+    pub adapter: AdapterSelector,
+
+    /// Output format: text for humans, or a single JSON document / one JSON object per line for
+    /// scripts
+    #[arg(short, long, global = true, value_enum, default_value_t = Format::Text)]
+    pub format: Format,
+
+    /// File naming vendor specific UUIDs, one `<uuid> <name>` per line, in addition to the
+    /// built-in Bluetooth SIG names
+    #[arg(long, global = true, value_name = "FILE", env = "BLE_UTIL_NAMES")]
+    pub names: Option<PathBuf>,
+
+    #[command(subcommand)]
+    pub command: Command,
+}
+
+const EXIT_CODES: &str = "\
+Exit codes:
+  0  success
+  1  bluetooth or I/O error
+  2  invalid command line
+  3  no bluetooth adapter
+  4  device not found
+  5  characteristic or descriptor not found
+  6  characteristic not readable
+  7  characteristic not writable
+  8  timed out
+  9  GATT databases differ, device doesn't match the profile, or file checksum mismatch
+  10 characteristic doesn't support notifications or indications";
+
+#[derive(Clone, Copy, ValueEnum)]
+pub enum BackendKind {
+    Btleplug,
+    Mock,
+}
+
+#[derive(Subcommand)]
+pub enum Command {
+    /// List the local bluetooth adapters
+    Adapters,
+
+    /// Scan for and print nearby devices
+    Scan(ScanArgs),
+
+    /// Scan for beacons (iBeacon, AltBeacon, Eddystone) and estimate how far they are
+    Beacons(BeaconsArgs),
+
+    /// Connect to device and print its services and characteristics
+    Ping(PingArgs),
+
+    /// Compare a GATT database saved with `ping --export` to another one, or to a device
+    #[command(name = "gatt-diff")]
+    GattDiff(GattDiffArgs),
+
+    /// Check that a device has the services and characteristics listed in a TOML profile
+    Verify(VerifyArgs),
+
+    /// Connect to the device and read the value of the characteristic
+    Read {
+        #[command(flatten)]
+        device: DeviceArgs,
+
+        /// Characteristic UUID, full or 16-bit short form
+        #[arg(value_parser = parse_uuid)]
+        characteristic: Uuid,
+
+        /// Decode the value as `hex`, `utf8`, `base64`, a number type (`u8`, `i16le`, `u32be`,
+        /// `f32le`...) or a struct layout such as `<HhI` (see Python's `struct` module)
+        #[arg(long = "as", value_name = "FORMAT")]
+        value_format: Option<ValueFormat>,
+    },
+
+    /// Connect to the device and print the values notified or indicated by characteristics
+    Subscribe(SubscribeArgs),
+
+    /// Connect to the device and write a value to a characteristic. Without a characteristic,
+    /// writes each line of stdin to the Nordic UART RX characteristic and prints what TX notifies
+    Write(WriteArgs),
+
+    /// Interactive terminal to the Nordic UART service of a device: sends what is typed and
+    /// prints what the device sends back
+    Nus(NusArgs),
+
+    /// Send a file to the device, in chunks, and check the checksum it answers with
+    #[command(name = "send-file")]
+    SendFile(SendFileArgs),
+
+    /// Full-screen table of the devices around, updated live, from which to browse, read and
+    /// write the characteristics of one
+    Dashboard(DashboardArgs),
+}
+
+#[derive(Args)]
+pub struct ScanArgs {
+    /// How long to scan for, e.g. `10s`; defaults to 3s, or until Ctrl-C with `--watch`
+    #[arg(short, long, value_parser = parse_duration)]
+    pub duration: Option<Duration>,
+
+    /// Keep scanning and print devices as they appear and update
+    #[arg(short, long)]
+    pub watch: bool,
+
+    /// Print all advertisement data: signal strength, services, manufacturer and service data
+    #[arg(short, long)]
+    pub long: bool,
+
+    /// Order in which to print the devices found
+    #[arg(short, long, value_enum, default_value_t = SortOrder::Address)]
+    pub sort: SortOrder,
+
+    /// Only show devices received at least this strongly, in dBm (e.g. `-70`)
+    #[arg(long, allow_negative_numbers = true)]
+    pub min_rssi: Option<i16>,
+
+    /// Only show devices whose name matches this regular expression
+    #[arg(long)]
+    pub name: Option<Regex>,
+
+    /// Only show devices advertising this service; may be repeated
+    #[arg(long = "service", value_name = "UUID", value_parser = parse_uuid)]
+    pub services: Vec<Uuid>,
+
+    /// Only show devices sending manufacturer data for this company id (e.g. `0x004c`); may be
+    /// repeated
+    #[arg(long = "manufacturer", value_name = "ID", value_parser = parse_u16)]
+    pub manufacturers: Vec<u16>,
+
+    #[command(flatten)]
+    pub ranging: RangingArgs,
+}
+
+impl ScanArgs {
+    pub fn filter(&self) -> DeviceFilter {
+        DeviceFilter {
+            min_rssi: self.min_rssi,
+            name: self.name.clone(),
+            services: self.services.clone(),
+            manufacturers: self.manufacturers.clone(),
+        }
+    }
+}
+
+#[derive(Args)]
+pub struct BeaconsArgs {
+    /// How long to scan for, e.g. `10s`; defaults to 3s, or until Ctrl-C with `--watch`
+    #[arg(short, long, value_parser = parse_duration)]
+    pub duration: Option<Duration>,
+
+    /// Keep scanning and print beacon frames as they are received
+    #[arg(short, long)]
+    pub watch: bool,
+
+    #[command(flatten)]
+    pub ranging: RangingArgs,
+}
+
+/// Arguments of the commands estimating the distance of devices.
+#[derive(Args)]
+pub struct RangingArgs {
+    /// Path loss exponent used to estimate distances: 2 in free space, around 3 indoors, up to 4
+    /// with many obstacles
+    #[arg(long, value_name = "N", default_value_t = distance::FREE_SPACE, value_parser = parse_environment)]
+    pub environment: f64,
+
+    /// How to smooth the signal strength of a device across advertisements with `--watch`
+    #[arg(long, value_enum, default_value_t = SmoothingKind::Kalman)]
+    pub smoothing: SmoothingKind,
+}
+
+impl RangingArgs {
+    pub fn smoothing(&self) -> Smoothing {
+        match self.smoothing {
+            SmoothingKind::None => Smoothing::None,
+            SmoothingKind::Ema => Smoothing::EMA,
+            SmoothingKind::Kalman => Smoothing::KALMAN,
+        }
+    }
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+pub enum SmoothingKind {
+    None,
+    /// Exponential moving average
+    Ema,
+    /// Kalman filter, steadier for devices that don't move
+    Kalman,
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+pub enum SortOrder {
+    Address,
+    /// Strongest signal first
+    Rssi,
+    Name,
+}
+
+#[derive(Args)]
+pub struct PingArgs {
+    #[command(flatten)]
+    pub device: DeviceArgs,
+
+    /// Also list descriptors, and read every readable characteristic and descriptor
+    #[arg(long)]
+    pub values: bool,
+
+    /// Save the GATT database as JSON to this file, for `gatt-diff`
+    #[arg(long, value_name = "FILE")]
+    pub export: Option<PathBuf>,
+}
+
+#[derive(Args)]
+pub struct GattDiffArgs {
+    /// GATT database saved with `ping --export`
+    #[arg(value_name = "A.json")]
+    pub old: PathBuf,
+
+    /// Another saved GATT database, or a device to connect to. Values are read from the device
+    /// if the first database has some
+    #[arg(value_name = "B.json|DEVICE")]
+    pub new: String,
+
+    /// How long to wait for the device to show up, e.g. `10s` or `500ms`; defaults to 10s
+    #[arg(short, long, value_parser = parse_duration)]
+    pub timeout: Option<Duration>,
+}
+
+#[derive(Args)]
+pub struct VerifyArgs {
+    #[command(flatten)]
+    pub device: DeviceArgs,
+
+    /// Expected GATT profile: `[[service]]` tables with a `uuid`, holding
+    /// `[[service.characteristic]]` tables with a `uuid`, the `properties` required and optionally
+    /// a `value` regular expression its value must match, formatted as `format` (see `read --as`)
+    #[arg(value_name = "PROFILE.toml")]
+    pub profile: PathBuf,
+}
+
+#[derive(Args)]
+pub struct SubscribeArgs {
+    #[command(flatten)]
+    pub device: DeviceArgs,
+
+    /// Characteristic UUIDs, full or 16-bit short form
+    #[arg(value_parser = parse_uuid, required = true)]
+    pub characteristics: Vec<Uuid>,
+
+    /// Stop after receiving this many values
+    #[arg(short = 'n', long)]
+    pub count: Option<u64>,
+
+    /// Stop after this long, e.g. `30s`; defaults to until Ctrl-C
+    #[arg(short, long, value_parser = parse_duration)]
+    pub duration: Option<Duration>,
+
+    /// Decode the values, as with `read --as`
+    #[arg(long = "as", value_name = "FORMAT")]
+    pub value_format: Option<ValueFormat>,
+}
+
+#[derive(Args)]
+#[command(group = ArgGroup::new("payload").args(["value", "file"]))]
+pub struct WriteArgs {
+    #[command(flatten)]
+    pub device: DeviceArgs,
+
+    /// Characteristic UUID, full or 16-bit short form
+    #[arg(value_parser = parse_uuid, requires = "payload")]
+    pub characteristic: Option<Uuid>,
+
+    /// Value to write, in hex (`01ff`, `01:ff`, `0x01ff`) unless `--text` is given
+    pub value: Option<String>,
+
+    /// Write VALUE as UTF-8 text instead of hex
+    #[arg(long, requires = "value")]
+    pub text: bool,
+
+    /// Write the contents of this file
+    #[arg(long, value_name = "FILE", requires = "characteristic")]
+    pub file: Option<PathBuf>,
+
+    /// Ask the device to acknowledge the write; the default when the characteristic supports it
+    #[arg(long, requires = "characteristic", conflicts_with = "without_response")]
+    pub with_response: bool,
+
+    /// Write without waiting for an acknowledgement
+    #[arg(long, requires = "characteristic")]
+    pub without_response: bool,
+
+    /// Send the value in a single long write, which the device acknowledges once it has the
+    /// whole of it, instead of in chunks (up to 512 bytes)
+    #[arg(long, requires = "characteristic", conflicts_with_all = ["without_response", "ack"])]
+    pub long: bool,
+
+    #[command(flatten)]
+    pub chunk: ChunkArgs,
+}
+
+#[derive(Args)]
+pub struct DashboardArgs {
+    /// Initial order of the device table; `s` changes it
+    #[arg(short, long, value_enum, default_value_t = SortOrder::Rssi)]
+    pub sort: SortOrder,
+
+    /// Initially only show devices whose address, name or decoded advertisement contains this
+    /// text; `/` changes it
+    #[arg(long)]
+    pub filter: Option<String>,
+
+    /// How long to wait for a device to connect, e.g. `10s` or `500ms`; defaults to 10s
+    #[arg(short, long, value_parser = parse_duration)]
+    pub timeout: Option<Duration>,
+}
+
+#[derive(Args)]
+pub struct NusArgs {
+    #[command(flatten)]
+    pub device: DeviceArgs,
+
+    /// Line ending sent at the end of each line
+    #[arg(long, value_enum, default_value_t = LineEnding::Lf)]
+    pub eol: LineEnding,
+
+    /// Send each keystroke as it is typed instead of whole lines; Ctrl-] quits
+    #[arg(long)]
+    pub raw: bool,
+
+    /// Exit when the link drops instead of connecting again
+    #[arg(long)]
+    pub no_reconnect: bool,
+
+    #[command(flatten)]
+    pub chunk: ChunkArgs,
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+pub enum LineEnding {
+    None,
+    Lf,
+    Cr,
+    Crlf,
+}
+
+impl LineEnding {
+    pub fn bytes(self) -> &'static [u8] {
+        match self {
+            LineEnding::None => b"",
+            LineEnding::Lf => b"\n",
+            LineEnding::Cr => b"\r",
+            LineEnding::Crlf => b"\r\n",
+        }
+    }
+}
+
+#[derive(Args)]
+pub struct SendFileArgs {
+    #[command(flatten)]
+    pub device: DeviceArgs,
+
+    /// File to send
+    pub path: PathBuf,
+
+    /// Characteristic to write the file to; defaults to the Nordic UART RX characteristic
+    #[arg(long, value_name = "UUID", value_parser = parse_uuid)]
+    pub characteristic: Option<Uuid>,
+
+    /// Characteristic notifying the checksum, or holding it to be read once the file is sent if
+    /// it can't notify; defaults to the Nordic UART TX characteristic
+    #[arg(long, value_name = "UUID", value_parser = parse_uuid)]
+    pub reply: Option<Uuid>,
+
+    /// Checksum the device answers with once it has the whole file, as raw bytes (either byte
+    /// order for CRC32) or as hex text
+    #[arg(long, value_enum, default_value_t = Checksum::Crc32)]
+    pub verify: Checksum,
+
+    /// How long to wait for the checksum after sending the file
+    #[arg(long, default_value = "10s", value_parser = parse_duration)]
+    pub verify_timeout: Duration,
+
+    /// Send the size of the file first, as a 32-bit little endian number
+    #[arg(long)]
+    pub size_header: bool,
+
+    #[command(flatten)]
+    pub chunk: ChunkArgs,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum Checksum {
+    /// Don't verify the transfer
+    None,
+    Crc32,
+    Sha256,
+}
+
+/// Arguments of the commands splitting values too long for a single write.
+#[derive(Clone, Args)]
+pub struct ChunkArgs {
+    /// ATT MTU to size the chunks for; defaults to the negotiated one when the bluetooth stack
+    /// reports it, and to 23 otherwise
+    #[arg(long, value_parser = clap::value_parser!(u16).range(23..=517))]
+    pub mtu: Option<u16>,
+
+    /// Pause between chunks, e.g. `20ms`
+    #[arg(long, value_name = "DURATION", value_parser = parse_duration)]
+    pub chunk_interval: Option<Duration>,
+
+    /// Wait after each chunk for this characteristic to notify, for devices acknowledging data or
+    /// granting credits that way
+    #[arg(long, value_name = "UUID", value_parser = parse_uuid)]
+    pub ack: Option<Uuid>,
+}
+
+impl ChunkArgs {
+    pub fn chunking(&self) -> Chunking {
+        Chunking {
+            mtu: self.mtu,
+            interval: self.chunk_interval.unwrap_or_default(),
+            ack: self.ack,
+        }
+    }
+}
+
+/// Arguments shared by the commands connecting to a device.
+#[derive(Clone, Args)]
+pub struct DeviceArgs {
+    /// Device to connect to: a MAC address, an advertised service UUID or a glob on the device
+    /// name. Prefix it with `addr:`, `service:` or `name:` to force one interpretation
+    #[arg(value_name = "DEVICE")]
+    pub target: Target,
+
+    /// How long to wait for the device to show up, e.g. `10s` or `500ms`; defaults to 10s
+    #[arg(short, long, value_parser = parse_duration)]
+    pub timeout: Option<Duration>,
+}
+
+impl DeviceArgs {
+    pub fn timeout(&self) -> Duration {
+        self.timeout.unwrap_or(DEFAULT_TIMEOUT)
+    }
+}
+
+/// Parses a duration given in seconds, optionally with a `ms`, `s` or `m` suffix.
+pub fn parse_duration(s: &str) -> Result<Duration, String> {
+    let (value, scale) = if let Some(v) = s.strip_suffix("ms") {
+        (v, 0.001)
+    } else if let Some(v) = s.strip_suffix('s') {
+        (v, 1.0)
+    } else if let Some(v) = s.strip_suffix('m') {
+        (v, 60.0)
+    } else {
+        (s, 1.0)
+    };
+
+    let secs = value.trim().parse::<f64>().map_err(|_| format!("invalid duration '{}'", s))?;
+    Duration::try_from_secs_f64(secs * scale).map_err(|e| format!("invalid duration '{}': {}", s, e))
+}
+
+/// Parses bytes written in hex, optionally `0x` prefixed and with bytes separated by spaces or
+/// colons.
+pub fn parse_hex(s: &str) -> Result<Vec<u8>, String> {
+    let digits: String = s.strip_prefix("0x").or_else(|| s.strip_prefix("0X")).unwrap_or(s)
+        .chars()
+        .filter(|c| *c != ' ' && *c != ':')
+        .collect();
+
+    if !digits.len().is_multiple_of(2) || !digits.chars().all(|c| c.is_ascii_hexdigit()) {
+        return Err(format!("invalid hex value '{}'", s));
+    }
+
+    Ok((0..digits.len()).step_by(2).map(|i| u8::from_str_radix(&digits[i..i + 2], 16).unwrap()).collect())
+}
+
+/// Parses a path loss exponent, which must be positive.
+fn parse_environment(s: &str) -> Result<f64, String> {
+    match s.parse::<f64>() {
+        Ok(n) if n > 0.0 && n.is_finite() => Ok(n),
+        _ => Err(format!("invalid path loss exponent '{}'", s)),
+    }
+}
+
+/// Parses a decimal or `0x` prefixed hexadecimal 16-bit number.
+pub fn parse_u16(s: &str) -> Result<u16, String> {
+    let parsed = match s.strip_prefix("0x").or_else(|| s.strip_prefix("0X")) {
+        Some(hex) => u16::from_str_radix(hex, 16),
+        None => s.parse(),
+    };
+
+    parsed.map_err(|e| format!("invalid number '{}': {}", s, e))
+}
+
+/// The command a command line parses to, the program name aside.
+#[cfg(test)]
+pub fn parse_command(args: &[&str]) -> Result<Command, clap::Error> {
+    Cli::try_parse_from(std::iter::once("ble-util").chain(args.iter().copied())).map(|cli| cli.command)
+}
+
+/// A session on a mock adapter with a single peripheral.
+#[cfg(test)]
+pub async fn mock_session(peripheral: ble_util::backend::MockPeripheral) -> ble_util::Session {
+    use ble_util::backend::{MockAdapter, MockBackend};
+
+    let backend = MockBackend::new().with_adapter(MockAdapter::new("mock").with_peripheral(peripheral));
+    ble_util::Session::with_backend(Box::new(backend)).await.unwrap()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_durations() {
+        assert_eq!(parse_duration("10"), Ok(Duration::from_secs(10)));
+        assert_eq!(parse_duration("1.5s"), Ok(Duration::from_millis(1500)));
+        assert_eq!(parse_duration("500ms"), Ok(Duration::from_millis(500)));
+        assert_eq!(parse_duration("2m"), Ok(Duration::from_secs(120)));
+    }
+
+    #[test]
+    fn rejects_invalid_durations() {
+        for s in ["", "s", "ten", "-1s", "NaN", "inf", "1e300m"] {
+            assert!(parse_duration(s).is_err(), "{}", s);
+        }
+        let err = parse_duration("1e300m").unwrap_err();
+        assert!(err.starts_with("invalid duration '1e300m': "), "{}", err);
+    }
+}
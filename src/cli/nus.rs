@@ -0,0 +1,373 @@
+//! Interactive terminal to a device's Nordic UART service.
+//!
+//! Input is written to the RX characteristic either line by line, or with `--raw` keystroke by
+//! keystroke, while the values notified by the TX characteristic are printed as they arrive. When
+//! the link drops the device is reconnected to, and what was typed meanwhile is sent once it's back.
+
+use std::error::Error;
+use std::io::{stdin, stdout, BufRead, IsTerminal, Read, Write};
+use std::thread;
+use std::time::{Duration, SystemTime};
+
+use ble_util::backend::EventStream;
+use ble_util::{hex, BleUtilError, CharPropFlags, Chunking, Device, Session, Value, ValueNotification, WriteType};
+use crossterm::terminal;
+use tokio::sync::mpsc;
+use tokio::signal;
+use tokio::time::{self, Instant};
+use tokio_stream::StreamExt;
+
+use super::gatt::{connect, CHAR_READ, CHAR_WRITE};
+use super::output::{local_time, value_json, NotificationRecord, Output, WriteRecord};
+use super::{LineEnding, NusArgs};
+
+/// Ends the session in raw mode, where Ctrl-C is sent to the device like any other key.
+const QUIT: u8 = 0x1d; // Ctrl-]
+
+/// How long to keep printing what the device sends once the input has ended.
+const LINGER: Duration = Duration::from_millis(500);
+
+pub async fn nus(session: &Session, args: &NusArgs, out: Output) -> Result<(), Box<dyn Error>> {
+    let term = Terminal {
+        out,
+        raw: args.raw && stdin().is_terminal(),
+    };
+
+    let dev = connect(session, &args.device, out).await?;
+    let notifications = listen(&dev).await?;
+
+    let _raw_mode = if term.raw {
+        term.status("Raw mode, press Ctrl-] to quit");
+        Some(RawMode::enable()?)
+    } else {
+        None
+    };
+    let input = read_input(term.raw, args.eol);
+    run(session, args, &term, (dev, notifications), input, |dev, n| term.received(dev, n)).await
+}
+
+/// Sends the input until it ends, handing what the device sends meanwhile to `received` and
+/// reconnecting as needed.
+async fn run(
+    session: &Session,
+    args: &NusArgs,
+    term: &Terminal,
+    (mut dev, mut notifications): (Device, EventStream<ValueNotification>),
+    mut input: mpsc::UnboundedReceiver<Vec<u8>>,
+    mut received: impl FnMut(&Device, &ValueNotification),
+) -> Result<(), Box<dyn Error>> {
+    let out = term.out;
+    let chunking = args.chunk.chunking();
+    let mut input_open = true;
+
+    // Answers to the last input are still awaited when it ends
+    let stop = time::sleep(Duration::MAX);
+    tokio::pin!(stop);
+
+    // Notifications just stop when the link drops, so poll for it
+    let mut check = time::interval(Duration::from_secs(1));
+    loop {
+        tokio::select! {
+            data = input.recv(), if input_open => {
+                let data = match data {
+                    Some(data) => data,
+                    None => {
+                        input_open = false;
+                        stop.as_mut().reset(Instant::now() + LINGER);
+                        continue;
+                    }
+                };
+
+                // A write failing on a dropped link is retried once reconnected
+                while let Err(e) = send(&dev, &data, &chunking, out).await {
+                    if dev.peripheral().is_connected().await.unwrap_or(false) {
+                        return Err(e.into());
+                    }
+                    match reconnect(session, args, term).await? {
+                        Some(reconnected) => (dev, notifications) = reconnected,
+                        None => return Ok(()),
+                    }
+                }
+            }
+            n = notifications.next() => match n {
+                Some(n) if n.uuid == CHAR_READ => {
+                    received(&dev, &n);
+                    if !input_open {
+                        stop.as_mut().reset(Instant::now() + LINGER);
+                    }
+                }
+                Some(_) => {}
+                // Stream ended, most likely along with the link: let the next check tell
+                None => notifications = Box::pin(tokio_stream::pending()),
+            },
+            _ = check.tick() => {
+                if !dev.peripheral().is_connected().await.unwrap_or(false) {
+                    match reconnect(session, args, term).await? {
+                        Some(reconnected) => (dev, notifications) = reconnected,
+                        None => return Ok(()),
+                    }
+                }
+            }
+            _ = &mut stop => break,
+            _ = signal::ctrl_c() => break,
+        }
+    }
+
+    // The link may be gone already, nothing left to clean up then
+    dev.unsubscribe(CHAR_READ).await.ok();
+    Ok(())
+}
+
+/// Subscribes to the TX characteristic, returning the stream its values arrive on.
+async fn listen(dev: &Device) -> Result<EventStream<ValueNotification>, BleUtilError> {
+    // Listen before subscribing so the first values aren't missed
+    let notifications = dev.notifications().await?;
+    dev.subscribe(CHAR_READ).await?;
+    Ok(notifications)
+}
+
+async fn send(dev: &Device, data: &[u8], chunking: &Chunking, out: Output) -> Result<(), BleUtilError> {
+    // Not waiting for acknowledgements keeps typing responsive
+    let write_type = if dev.characteristic(CHAR_WRITE)?.properties.contains(CharPropFlags::WRITE_WITHOUT_RESPONSE) {
+        WriteType::WithoutResponse
+    } else {
+        WriteType::WithResponse
+    };
+
+    let chunks = dev.write_chunked(CHAR_WRITE, data, write_type, chunking).await?;
+    out.event(&WriteRecord {
+        address: dev.address().to_string(),
+        characteristic: CHAR_WRITE.to_string(),
+        length: data.len(),
+        chunks,
+        with_response: write_type == WriteType::WithResponse,
+    }, |_| ());
+    Ok(())
+}
+
+/// Connects to the device again after losing the link, until it works. `None` if interrupted
+/// with Ctrl-C.
+async fn reconnect(
+    session: &Session,
+    args: &NusArgs,
+    term: &Terminal,
+) -> Result<Option<(Device, EventStream<ValueNotification>)>, Box<dyn Error>> {
+    if args.no_reconnect {
+        return Err("connection lost".into());
+    }
+    term.status("Connection lost, reconnecting...");
+
+    let retry = async {
+        loop {
+            let attempt = async {
+                let dev = session.connect(&args.device.target, args.device.timeout()).await?;
+                let notifications = listen(&dev).await?;
+                Ok::<_, BleUtilError>((dev, notifications))
+            };
+
+            match attempt.await {
+                Ok(reconnected) => return reconnected,
+                Err(e) => {
+                    term.status(&format!("Reconnecting failed: {}", e));
+                    time::sleep(Duration::from_secs(1)).await;
+                }
+            }
+        }
+    };
+
+    tokio::select! {
+        reconnected = retry => {
+            term.status("Reconnected");
+            Ok(Some(reconnected))
+        }
+        _ = signal::ctrl_c() => Ok(None),
+    }
+}
+
+/// Reads stdin on a thread of its own, as blocking reads would otherwise keep the runtime from
+/// shutting down. The channel closes at the end of the input.
+fn read_input(raw: bool, eol: LineEnding) -> mpsc::UnboundedReceiver<Vec<u8>> {
+    let (tx, rx) = mpsc::unbounded_channel();
+
+    thread::spawn(move || {
+        let mut stdin = stdin().lock();
+        let mut buf = Vec::new();
+        loop {
+            buf.clear();
+            let data = if raw {
+                let mut key = [0; 64];
+                let n = match stdin.read(&mut key) {
+                    Ok(0) | Err(_) => break,
+                    Ok(n) => n,
+                };
+                if key[..n].contains(&QUIT) {
+                    break;
+                }
+
+                keys(&key[..n], eol)
+            } else {
+                match stdin.read_until(b'\n', &mut buf) {
+                    Ok(0) | Err(_) => break,
+                    Ok(_) => {}
+                }
+                line(&buf, eol)
+            };
+
+            if !data.is_empty() && tx.send(data).is_err() {
+                break;
+            }
+        }
+    });
+
+    rx
+}
+
+/// Keys typed in raw mode, where Enter sends a carriage return, with it replaced by `eol`.
+fn keys(keys: &[u8], eol: LineEnding) -> Vec<u8> {
+    let mut data = Vec::with_capacity(keys.len());
+    for &b in keys.iter() {
+        match b {
+            b'\r' => data.extend_from_slice(eol.bytes()),
+            b => data.push(b),
+        }
+    }
+    data
+}
+
+/// A line read from the input, with whatever line ending it had replaced by `eol`.
+fn line(line: &[u8], eol: LineEnding) -> Vec<u8> {
+    let line = line.strip_suffix(b"\n").unwrap_or(line);
+    let line = line.strip_suffix(b"\r").unwrap_or(line);
+    [line, eol.bytes()].concat()
+}
+
+/// Where the output goes, which in raw mode needs explicit carriage returns.
+struct Terminal {
+    out: Output,
+    raw: bool,
+}
+
+impl Terminal {
+    fn received(&self, dev: &Device, n: &ValueNotification) {
+        let text = String::from_utf8_lossy(&n.value);
+        let record = NotificationRecord {
+            time: local_time(SystemTime::now()).to_rfc3339(),
+            address: dev.address().to_string(),
+            characteristic: n.uuid.to_string(),
+            value: hex(&n.value),
+            decoded: Some(value_json(&Value::Text(text.to_string()))),
+        };
+
+        self.out.event(&record, |_| {
+            let text = if self.raw { text.replace('\n', "\r\n") } else { text.into_owned() };
+            let mut stdout = stdout();
+            stdout.write_all(text.as_bytes()).ok();
+            stdout.flush().ok();
+        });
+    }
+
+    fn status(&self, msg: &str) {
+        if self.raw {
+            self.out.status(&format!("\r{}\r", msg));
+        } else {
+            self.out.status(msg);
+        }
+    }
+}
+
+/// Puts the terminal in raw mode until dropped.
+struct RawMode;
+
+impl RawMode {
+    fn enable() -> std::io::Result<RawMode> {
+        terminal::enable_raw_mode()?;
+        Ok(RawMode)
+    }
+}
+
+impl Drop for RawMode {
+    fn drop(&mut self) {
+        terminal::disable_raw_mode().ok();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use ble_util::backend::MockPeripheral;
+
+    use ble_util::Uuid;
+
+    use super::*;
+    use crate::cli::output::Format;
+    use crate::cli::{mock_session, parse_command, Command};
+
+    const ADDRESS: &str = "aa:bb:cc:dd:ee:50";
+
+    #[test]
+    fn ends_lines_as_asked() {
+        assert_eq!(line(b"hello\n", LineEnding::Lf), b"hello\n");
+        assert_eq!(line(b"hello\r\n", LineEnding::Lf), b"hello\n");
+        assert_eq!(line(b"hello\n", LineEnding::Crlf), b"hello\r\n");
+        assert_eq!(line(b"hello\n", LineEnding::Cr), b"hello\r");
+        assert_eq!(line(b"hello\n", LineEnding::None), b"hello");
+        // The last line of the input may have no ending
+        assert_eq!(line(b"hello", LineEnding::Lf), b"hello\n");
+    }
+
+    #[test]
+    fn ends_typed_lines_as_asked() {
+        assert_eq!(keys(b"a", LineEnding::Crlf), b"a");
+        assert_eq!(keys(b"ab\r", LineEnding::Lf), b"ab\n");
+        assert_eq!(keys(b"\rc\r", LineEnding::Crlf), b"\r\nc\r\n");
+        assert_eq!(keys(b"\r", LineEnding::None), b"");
+    }
+
+    /// A Nordic UART device echoing what it receives.
+    fn echo() -> MockPeripheral {
+        let service = Uuid::from_u128(0x6e400001_b5a3_f393_e0a9_e50e24dcca9e);
+        MockPeripheral::new([0xaa, 0xbb, 0xcc, 0xdd, 0xee, 0x50].into())
+            .service(service)
+            .characteristic(service, CHAR_WRITE, CharPropFlags::WRITE | CharPropFlags::WRITE_WITHOUT_RESPONSE, &[])
+            .characteristic(service, CHAR_READ, CharPropFlags::NOTIFY, &[])
+            .on_write(CHAR_WRITE, |data| vec![ValueNotification { uuid: CHAR_READ, value: data.to_vec() }])
+    }
+
+    fn nus_args() -> NusArgs {
+        match parse_command(&["nus", ADDRESS]) {
+            Ok(Command::Nus(args)) => args,
+            _ => unreachable!("parsed nus"),
+        }
+    }
+
+    #[tokio::test]
+    async fn reconnects_and_resubscribes_when_the_link_drops() {
+        let peripheral = echo();
+        let session = mock_session(peripheral.clone()).await;
+        let args = nus_args();
+        let term = Terminal { out: Output::new(Format::Ndjson), raw: false };
+
+        let dev = connect(&session, &args.device, term.out).await.unwrap();
+        let notifications = listen(&dev).await.unwrap();
+        let (input, lines) = mpsc::unbounded_channel();
+        let (answers, mut received) = mpsc::unbounded_channel();
+
+        let send = async {
+            input.send(b"first\n".to_vec()).unwrap();
+            assert_eq!(received.recv().await.unwrap(), b"first\n");
+            peripheral.drop_link();
+
+            // Fails on the dropped link, then goes through once connected again, with the answer
+            // only arriving if subscribed to again
+            input.send(b"second\n".to_vec()).unwrap();
+            assert_eq!(received.recv().await.unwrap(), b"second\n");
+            drop(input);
+        };
+        let run = run(&session, &args, &term, (dev, notifications), lines, |_, n| {
+            answers.send(n.value.clone()).unwrap()
+        });
+        let (res, _) = time::timeout(Duration::from_secs(5), async { tokio::join!(run, send) }).await.unwrap();
+        assert!(res.is_ok(), "{:?}", res);
+
+        assert_eq!(peripheral.writes(CHAR_WRITE), [b"first\n".to_vec(), b"second\n".to_vec()]);
+    }
+}
@@ -0,0 +1,520 @@
+//! Printing of command results, as text or JSON.
+//!
+//! With `--format json` a command prints a single pretty-printed JSON document: an object, or an
+//! array for commands listing several things. `--format ndjson` prints the same objects compactly,
+//! one per line, lists being flattened into one line per element. Commands producing a stream of
+//! results (`scan --watch`, `beacons --watch`, `subscribe`, `write`, `nus`) print one compact
+//! object per line in both JSON formats.
+//!
+//! Addresses are formatted as `AA:BB:CC:DD:EE:FF`, UUIDs in their full lowercase form and binary
+//! data as lowercase hex strings. Fields without a value are `null`. The objects are:
+//!
+//! - adapter (`adapters`): `{"index": 0, "info": "hci0 (usb:v1D6Bp0246d0540)"}`
+//! - device (`scan`):
+//!   `{"address": "AA:BB:CC:DD:EE:FF", "address_type": "public" | "random" | null,
+//!   "name": "sensor" | null, "rssi": -60 | null, "tx_power": 4 | null, "distance": 2.1 | null,
+//!   "services": ["<uuid>"], "manufacturer_data": {"0x004c": "<hex>"},
+//!   "service_data": {"<uuid>": "<hex>"}, "names": {"<uuid>" | "0x004c": "Apple, Inc."},
+//!   "beacons": [<beacon>], "sensors": [{"format": "BTHome v2", "readings": [{"name":
+//!   "temperature", "value": 23.45, "unit": "°C" | null}]}]}`. `names` holds the names known for
+//!   the services and companies of the other fields, `beacons` the beacon frames and `sensors` the
+//!   sensor readings decoded from the manufacturer and service data. Sensor formats are
+//!   `BTHome v2`, `RuuviTag RAWv2` and `ATC`; reading values are numbers, booleans or strings.
+//!   `distance` is the distance to the device estimated in meters, from the signal strength and
+//!   the power of a beacon frame or the transmission power; with `--watch` the signal strength is
+//!   smoothed across advertisements first.
+//! - beacon frame: `{"type": "ibeacon", "uuid": "<uuid>", "major": 1, "minor": 2,
+//!   "measured_power": -59}`, `{"type": "altbeacon", "manufacturer": "0x0118", "id": "<hex>",
+//!   "reference_rssi": -59, "reserved": 0}`, `{"type": "eddystone-uid", "tx_power": -18,
+//!   "namespace": "<hex>", "instance": "<hex>"}`, `{"type": "eddystone-url", "tx_power": -18,
+//!   "url": "https://example.com"}`, `{"type": "eddystone-tlm", "battery": 3000 | null,
+//!   "temperature": 21.5 | null, "advertisements": 1000, "uptime": 360.5}` or
+//!   `{"type": "eddystone-eid", "tx_power": -18, "eid": "<hex>"}`. Powers are in dBm: measured at
+//!   1 m for iBeacon and AltBeacon, at 0 m for Eddystone. The battery is in mV, the temperature in
+//!   °C and the uptime in seconds.
+//! - beacon (`beacons`): `{"address": "...", "name": "..." | null, "rssi": -60 | null,
+//!   "distance": 1.4 | null, "beacon": <beacon frame>}`. `distance` is the estimated distance to
+//!   the beacon in meters, for frames telling their transmission power, estimated as for devices.
+//! - scan event (`scan --watch`):
+//!   `{"event": "discovered" | "updated", "time": "<RFC 3339>", "device": <device>}`
+//! - GATT database (`ping`): `{"address": "...", "appearance": "Thermometer" | null,
+//!   "services": [{"uuid": "<uuid>", "name": "Battery Service" | null, "primary": true,
+//!   "characteristics": [{"uuid": "<uuid>",
+//!   "name": "Battery Level" | null, "properties": ["read", "notify"], "value": "<hex>" | null,
+//!   "error": "..." | null, "descriptors": [{"uuid": "<uuid>", "name": "..." | null,
+//!   "value": "<hex>" | null, "error": "..." | null}]}]}]}`. Values are only read with
+//!   `--values`; `error` is set instead of `value` when reading the attribute failed.
+//!   `appearance` names the kind of device its Appearance characteristic describes, if it has
+//!   one. This is also the format of the files saved with `ping --export`.
+//! - GATT difference (`gatt-diff`): `{"change": "added" | "removed" | "changed",
+//!   "attribute": "service" | "characteristic" | "descriptor", "service": "<uuid>",
+//!   "characteristic": "<uuid>" | null, "descriptor": "<uuid>" | null, "name": "..." | null,
+//!   "field": "primary" | "properties" | "value" | null, "old": <value> | null,
+//!   "new": <value> | null}`. `field`, `old` and `new` are only set for changes.
+//! - profile check (`verify`): `{"ok": true, "check": "service" | "characteristic" |
+//!   "properties" | "value", "service": "<uuid>", "characteristic": "<uuid>" | null,
+//!   "name": "..." | null, "message": "..." | null}`. `message` is a free-form explanation of a
+//!   failed check, such as `missing` or the value that didn't match.
+//! - read value (`read`):
+//!   `{"address": "...", "characteristic": "<uuid>", "value": "<hex>", "decoded": <decoded>,
+//!   "unit": "°C" | null}`. `decoded` is the value decoded as asked for with `--as`, or as
+//!   described by the characteristic's presentation format descriptor, and `null` otherwise. It is
+//!   a string for `hex`, `utf8` and `base64`, a number (or an array of numbers if the value holds
+//!   several), or an array of numbers, strings and booleans for struct layouts. `unit` is only set
+//!   when decoding with a presentation format: the unit symbol, or its `0x27xx` id if unknown.
+//! - notification (`subscribe`, `nus`, `write` without a characteristic): `{"time": "<RFC 3339>",
+//!   "address": "...", "characteristic": "<uuid>", "value": "<hex>", "decoded": <decoded> | null}`,
+//!   `decoded` being as for read values. `nus` and `write` decode them as UTF-8 text.
+//! - write acknowledgement (`write`, `nus`): `{"address": "...", "characteristic": "<uuid>",
+//!   "length": 5, "chunks": 1, "with_response": false}`. `chunks` is the number of writes the
+//!   value was split into to fit the MTU.
+//! - file transfer (`send-file`): `{"address": "...", "characteristic": "<uuid>",
+//!   "length": 4096, "chunks": 205, "seconds": 1.5, "bytes_per_second": 2730.7,
+//!   "checksum": "crc32" | "sha256" | null, "expected": "<hex>" | null,
+//!   "received": "<hex>" | null, "verified": true | null}`. `expected` is the checksum of the
+//!   file, `received` the one the device answered with, as it was sent; `verified` is `null`
+//!   when not verifying.
+//!
+//! Characteristic properties, in GATT databases and profile checks, are named `broadcast`,
+//! `read`, `write_without_response`, `write`, `notify`, `indicate`,
+//! `authenticated_signed_writes` and `extended_properties`.
+
+use std::collections::BTreeMap;
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::time::SystemTime;
+
+use ble_util::beacon::{self, Beacon};
+use ble_util::distance;
+use ble_util::{
+    hex, AdapterInfo, BDAddr, CharPropFlags, CharacteristicInfo, Decoders, DeviceInfo, ScanEvent,
+    ScanEventKind, Names, SensorData, ServiceInfo, Uuid, Value,
+};
+use btleplug::api::AddressType;
+use chrono::{DateTime, Local};
+use clap::ValueEnum;
+use serde::{Deserialize, Serialize, Serializer};
+use serde_json::json;
+
+#[derive(Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum Format {
+    Text,
+    Json,
+    Ndjson,
+}
+
+#[derive(Clone, Copy)]
+pub struct Output {
+    format: Format,
+}
+
+impl Output {
+    pub fn new(format: Format) -> Output {
+        Output { format }
+    }
+
+    /// Prints the result of a command. `text` prints it for humans.
+    pub fn value<T: Serialize>(&self, value: &T, text: impl FnOnce(&T)) {
+        match self.format {
+            Format::Text => text(value),
+            Format::Json => println!("{}", to_json(value, true)),
+            Format::Ndjson => println!("{}", to_json(value, false)),
+        }
+    }
+
+    /// Prints a list of results. `text` prints one element for humans.
+    pub fn list<T: Serialize>(&self, values: &[T], text: impl Fn(&T)) {
+        match self.format {
+            Format::Text => values.iter().for_each(text),
+            Format::Json => println!("{}", to_json(&values, true)),
+            Format::Ndjson => values.iter().for_each(|v| println!("{}", to_json(v, false))),
+        }
+    }
+
+    /// Prints one result out of a stream of them. `text` prints it for humans.
+    pub fn event<T: Serialize>(&self, value: &T, text: impl FnOnce(&T)) {
+        match self.format {
+            Format::Text => text(value),
+            Format::Json | Format::Ndjson => println!("{}", to_json(value, false)),
+        }
+    }
+
+    pub fn is_text(&self) -> bool {
+        self.format == Format::Text
+    }
+
+    /// Progress messages only meant for humans, left out of JSON output.
+    pub fn status(&self, msg: &str) {
+        if self.format == Format::Text {
+            println!("{}", msg);
+        }
+    }
+}
+
+/// Saves a result as pretty-printed JSON, whatever the output format.
+pub fn write_json<T: Serialize>(path: &Path, value: &T) -> io::Result<()> {
+    fs::write(path, to_json(value, true) + "\n")
+}
+
+fn to_json<T: Serialize>(value: &T, pretty: bool) -> String {
+    let json = if pretty {
+        serde_json::to_string_pretty(value)
+    } else {
+        serde_json::to_string(value)
+    };
+
+    // Only plain data structures are serialized, which cannot fail
+    json.expect("output is serializable")
+}
+
+#[derive(Serialize)]
+pub struct AdapterRecord {
+    pub index: usize,
+    pub info: String,
+}
+
+impl From<AdapterInfo> for AdapterRecord {
+    fn from(a: AdapterInfo) -> AdapterRecord {
+        AdapterRecord { index: a.index, info: a.info }
+    }
+}
+
+#[derive(Serialize)]
+pub struct DeviceRecord {
+    pub address: String,
+    pub address_type: Option<&'static str>,
+    pub name: Option<String>,
+    pub rssi: Option<i16>,
+    pub tx_power: Option<i16>,
+    pub distance: Option<f64>,
+    pub services: Vec<String>,
+    pub manufacturer_data: BTreeMap<String, String>,
+    pub service_data: BTreeMap<String, String>,
+    pub names: BTreeMap<String, String>,
+    #[serde(serialize_with = "serialize_beacons")]
+    pub beacons: Vec<Beacon>,
+    #[serde(serialize_with = "serialize_sensors")]
+    pub sensors: Vec<SensorData>,
+}
+
+impl DeviceRecord {
+    pub fn new(d: DeviceInfo, names: &Names, decoders: &Decoders) -> DeviceRecord {
+        let mut known = BTreeMap::new();
+        for uuid in d.services.iter().chain(d.service_data.keys()) {
+            if let Some(name) = names.uuid(*uuid) {
+                known.insert(uuid.to_string(), name.to_string());
+            }
+        }
+        for company in d.manufacturer_data.keys() {
+            if let Some(name) = names.company(*company) {
+                known.insert(format!("{:#06x}", company), name.to_string());
+            }
+        }
+
+        DeviceRecord {
+            beacons: beacon::beacons(&d),
+            sensors: decoders.decode(&d),
+            address: d.address.to_string(),
+            address_type: d.address_type.map(|t| match t {
+                AddressType::Public => "public",
+                AddressType::Random => "random",
+            }),
+            name: d.local_name,
+            rssi: d.rssi,
+            tx_power: d.tx_power,
+            // Depends on the other advertisements of the device, see `scan`
+            distance: None,
+            services: d.services.iter().map(Uuid::to_string).collect(),
+            manufacturer_data: d.manufacturer_data.iter()
+                .map(|(company, data)| (format!("{:#06x}", company), hex(data)))
+                .collect(),
+            service_data: d.service_data.iter()
+                .map(|(uuid, data)| (uuid.to_string(), hex(data)))
+                .collect(),
+            names: known,
+        }
+    }
+}
+
+#[derive(Serialize)]
+pub struct ScanEventRecord {
+    pub event: &'static str,
+    pub time: String,
+    pub device: DeviceRecord,
+}
+
+impl ScanEventRecord {
+    pub fn new(e: ScanEvent, names: &Names, decoders: &Decoders) -> ScanEventRecord {
+        ScanEventRecord {
+            event: match e.kind {
+                ScanEventKind::Discovered => "discovered",
+                ScanEventKind::Updated => "updated",
+            },
+            time: local_time(e.seen).to_rfc3339(),
+            device: DeviceRecord::new(e.device, names, decoders),
+        }
+    }
+}
+
+/// One beacon frame for `beacons`.
+#[derive(Serialize)]
+pub struct BeaconRecord {
+    pub address: String,
+    pub name: Option<String>,
+    pub rssi: Option<i16>,
+    pub distance: Option<f64>,
+    #[serde(serialize_with = "serialize_beacon")]
+    pub beacon: Beacon,
+}
+
+impl BeaconRecord {
+    /// One record for each beacon frame of a device. Distances are estimated from `rssi`, the
+    /// possibly smoothed signal strength of the device, and the path loss exponent `environment`.
+    pub fn all(d: &DeviceInfo, rssi: Option<f64>, environment: f64) -> Vec<BeaconRecord> {
+        beacon::beacons(d).into_iter()
+            .map(|b| BeaconRecord {
+                address: d.address.to_string(),
+                name: d.local_name.clone(),
+                rssi: d.rssi,
+                distance: rssi.zip(b.measured_power()).map(|(r, p)| distance::estimate(r, p, environment)),
+                beacon: b,
+            })
+            .collect()
+    }
+}
+
+fn serialize_beacons<S: Serializer>(beacons: &[Beacon], serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.collect_seq(beacons.iter().map(beacon_json))
+}
+
+fn serialize_beacon<S: Serializer>(beacon: &Beacon, serializer: S) -> Result<S::Ok, S::Error> {
+    beacon_json(beacon).serialize(serializer)
+}
+
+fn serialize_sensors<S: Serializer>(sensors: &[SensorData], serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.collect_seq(sensors.iter().map(|s| json!({
+        "format": s.format,
+        "readings": s.readings.iter()
+            .map(|r| json!({"name": r.name, "value": value_json(&r.value), "unit": r.unit}))
+            .collect::<Vec<_>>(),
+    })))
+}
+
+fn beacon_json(beacon: &Beacon) -> serde_json::Value {
+    let kind = beacon.kind();
+    match *beacon {
+        Beacon::IBeacon { uuid, major, minor, measured_power } => json!({
+            "type": kind, "uuid": uuid.to_string(), "major": major, "minor": minor,
+            "measured_power": measured_power,
+        }),
+        Beacon::AltBeacon { manufacturer, id, reference_rssi, reserved } => json!({
+            "type": kind, "manufacturer": format!("{:#06x}", manufacturer), "id": hex(&id),
+            "reference_rssi": reference_rssi, "reserved": reserved,
+        }),
+        Beacon::EddystoneUid { tx_power, namespace, instance } => json!({
+            "type": kind, "tx_power": tx_power, "namespace": hex(&namespace), "instance": hex(&instance),
+        }),
+        Beacon::EddystoneUrl { tx_power, ref url } => json!({
+            "type": kind, "tx_power": tx_power, "url": url,
+        }),
+        Beacon::EddystoneTlm { battery, temperature, advertisements, uptime } => json!({
+            "type": kind, "battery": battery, "temperature": temperature,
+            "advertisements": advertisements, "uptime": uptime.as_secs_f64(),
+        }),
+        Beacon::EddystoneEid { tx_power, eid } => json!({
+            "type": kind, "tx_power": tx_power, "eid": hex(&eid),
+        }),
+    }
+}
+
+/// `id (name)`, or just `id` without a name.
+pub fn named(id: &str, name: Option<&str>) -> String {
+    match name {
+        Some(name) => format!("{} ({})", id, name),
+        None => id.to_string(),
+    }
+}
+
+pub fn local_time(time: SystemTime) -> DateTime<Local> {
+    time.into()
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct GattRecord {
+    pub address: String,
+    pub appearance: Option<String>,
+    pub services: Vec<ServiceRecord>,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct ServiceRecord {
+    pub uuid: String,
+    pub name: Option<String>,
+    pub primary: bool,
+    pub characteristics: Vec<CharacteristicRecord>,
+}
+
+impl ServiceRecord {
+    pub fn new(s: ServiceInfo, names: &Names) -> ServiceRecord {
+        ServiceRecord {
+            uuid: s.uuid.to_string(),
+            name: names.uuid(s.uuid).map(String::from),
+            primary: s.primary,
+            characteristics: s.characteristics.into_iter()
+                .map(|c| CharacteristicRecord::new(c, names))
+                .collect(),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct CharacteristicRecord {
+    pub uuid: String,
+    pub name: Option<String>,
+    pub properties: Vec<String>,
+    pub value: Option<String>,
+    pub error: Option<String>,
+    pub descriptors: Vec<DescriptorRecord>,
+}
+
+impl CharacteristicRecord {
+    pub fn new(c: CharacteristicInfo, names: &Names) -> CharacteristicRecord {
+        CharacteristicRecord {
+            uuid: c.uuid.to_string(),
+            name: names.uuid(c.uuid).map(String::from),
+            properties: property_names(c.properties).into_iter().map(String::from).collect(),
+            value: None,
+            error: None,
+            descriptors: c.descriptors.iter()
+                .map(|d| DescriptorRecord {
+                    uuid: d.to_string(),
+                    name: names.uuid(*d).map(String::from),
+                    value: None,
+                    error: None,
+                })
+                .collect(),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct DescriptorRecord {
+    pub uuid: String,
+    pub name: Option<String>,
+    pub value: Option<String>,
+    pub error: Option<String>,
+}
+
+const PROPERTIES: [(CharPropFlags, &str); 8] = [
+    (CharPropFlags::BROADCAST, "broadcast"),
+    (CharPropFlags::READ, "read"),
+    (CharPropFlags::WRITE_WITHOUT_RESPONSE, "write_without_response"),
+    (CharPropFlags::WRITE, "write"),
+    (CharPropFlags::NOTIFY, "notify"),
+    (CharPropFlags::INDICATE, "indicate"),
+    (CharPropFlags::AUTHENTICATED_SIGNED_WRITES, "authenticated_signed_writes"),
+    (CharPropFlags::EXTENDED_PROPERTIES, "extended_properties"),
+];
+
+pub fn property_names(properties: CharPropFlags) -> Vec<&'static str> {
+    PROPERTIES.into_iter()
+        .filter(|(flag, _)| properties.contains(*flag))
+        .map(|(_, name)| name)
+        .collect()
+}
+
+/// The property with the given name, as printed by [`property_names`].
+pub fn property_flag(name: &str) -> Option<CharPropFlags> {
+    PROPERTIES.into_iter().find(|(_, n)| *n == name).map(|(flag, _)| flag)
+}
+
+#[derive(Serialize)]
+pub struct ReadRecord {
+    pub address: String,
+    pub characteristic: String,
+    pub value: String,
+    pub decoded: Option<serde_json::Value>,
+    pub unit: Option<String>,
+}
+
+impl ReadRecord {
+    pub fn new(address: BDAddr, characteristic: Uuid, value: &[u8]) -> ReadRecord {
+        ReadRecord {
+            address: address.to_string(),
+            characteristic: characteristic.to_string(),
+            value: hex(value),
+            decoded: None,
+            unit: None,
+        }
+    }
+}
+
+pub fn value_json(value: &Value) -> serde_json::Value {
+    match value {
+        Value::Text(s) => s.clone().into(),
+        Value::Int(i) => (*i).into(),
+        Value::UInt(u) => (*u).into(),
+        Value::Float(x) => (*x).into(),
+        Value::Bool(b) => (*b).into(),
+        Value::List(values) => values.iter().map(value_json).collect(),
+    }
+}
+
+#[derive(Serialize)]
+pub struct WriteRecord {
+    pub address: String,
+    pub characteristic: String,
+    pub length: usize,
+    pub chunks: usize,
+    pub with_response: bool,
+}
+
+/// Outcome of `send-file`.
+#[derive(Serialize)]
+pub struct TransferRecord {
+    pub address: String,
+    pub characteristic: String,
+    pub length: usize,
+    pub chunks: usize,
+    pub seconds: f64,
+    pub bytes_per_second: f64,
+    pub checksum: Option<&'static str>,
+    pub expected: Option<String>,
+    pub received: Option<String>,
+    pub verified: Option<bool>,
+}
+
+/// One attribute added, removed or changed between two GATT databases.
+#[derive(Serialize)]
+pub struct ChangeRecord {
+    pub change: &'static str,
+    pub attribute: &'static str,
+    pub service: String,
+    pub characteristic: Option<String>,
+    pub descriptor: Option<String>,
+    pub name: Option<String>,
+    pub field: Option<&'static str>,
+    pub old: Option<serde_json::Value>,
+    pub new: Option<serde_json::Value>,
+}
+
+/// Outcome of one check of `verify`.
+#[derive(Serialize)]
+pub struct CheckRecord {
+    pub ok: bool,
+    pub check: &'static str,
+    pub service: String,
+    pub characteristic: Option<String>,
+    pub name: Option<String>,
+    pub message: Option<String>,
+}
+
+#[derive(Serialize)]
+pub struct NotificationRecord {
+    pub time: String,
+    pub address: String,
+    pub characteristic: String,
+    pub value: String,
+    pub decoded: Option<serde_json::Value>,
+}
@@ -0,0 +1,220 @@
+use std::cmp::Reverse;
+use std::collections::HashMap;
+use std::error::Error;
+use std::time::Duration;
+
+use ble_util::distance::{self, RssiFilter, Smoothing};
+use ble_util::{BDAddr, Decoders, DeviceFilter, DeviceInfo, Names, Session};
+use tokio::{signal, time};
+
+use super::output::{local_time, named, BeaconRecord, DeviceRecord, Output, ScanEventRecord};
+use super::{BeaconsArgs, RangingArgs, ScanArgs, SortOrder};
+
+const SCAN_TIME: Duration = Duration::from_secs(3);
+
+pub async fn scan_devices(
+    session: &Session,
+    args: &ScanArgs,
+    names: &Names,
+    out: Output,
+) -> Result<(), Box<dyn Error>> {
+    if args.watch {
+        return watch_devices(session, args, names, out).await;
+    }
+
+    let mut devices = session.scan(args.duration.unwrap_or(SCAN_TIME), &args.filter()).await?;
+    match args.sort {
+        SortOrder::Address => devices.sort_by_key(|d| d.address),
+        // Devices without a value go last
+        SortOrder::Rssi => devices.sort_by_key(|d| (d.rssi.is_none(), Reverse(d.rssi))),
+        SortOrder::Name => devices.sort_by(|a, b| {
+            (a.local_name.is_none(), &a.local_name).cmp(&(b.local_name.is_none(), &b.local_name))
+        }),
+    }
+
+    let decoders = Decoders::builtin();
+    let mut ranging = Ranging::new(&args.ranging);
+    let devices: Vec<DeviceRecord> = devices.into_iter()
+        .map(|d| {
+            let distance = ranging.distance(&d);
+            let mut record = DeviceRecord::new(d, names, &decoders);
+            record.distance = distance;
+            record
+        })
+        .collect();
+    out.list(&devices, |dev| {
+        println!("{}: {}", dev.address, dev.name.as_deref().unwrap_or("Unknown"));
+        print_decoded(dev);
+        if args.long {
+            print_details(dev);
+        }
+    });
+
+    Ok(())
+}
+
+fn print_details(dev: &DeviceRecord) {
+    if let Some(rssi) = dev.rssi {
+        println!("\trssi: {} dBm", rssi);
+    }
+    if let Some(tx_power) = dev.tx_power {
+        println!("\ttx power: {} dBm", tx_power);
+    }
+    if let Some(distance) = dev.distance {
+        println!("\tdistance: ~{:.1} m", distance);
+    }
+    if let Some(address_type) = dev.address_type {
+        println!("\taddress type: {}", address_type);
+    }
+    let name = |id: &String| named(id, dev.names.get(id).map(String::as_str));
+    for s in dev.services.iter() {
+        println!("\tservice: {}", name(s));
+    }
+    for (company, data) in dev.manufacturer_data.iter() {
+        println!("\tmanufacturer data {}: {}", name(company), data);
+    }
+    for (uuid, data) in dev.service_data.iter() {
+        println!("\tservice data {}: {}", name(uuid), data);
+    }
+}
+
+/// Prints what was decoded from the advertisement data.
+fn print_decoded(dev: &DeviceRecord) {
+    for b in dev.beacons.iter() {
+        println!("\t{}", b);
+    }
+    for s in dev.sensors.iter() {
+        let readings: Vec<String> = s.readings.iter().map(ToString::to_string).collect();
+        println!("\t{}: {}", s.format, readings.join(", "));
+    }
+}
+
+async fn watch_devices(
+    session: &Session,
+    args: &ScanArgs,
+    names: &Names,
+    out: Output,
+) -> Result<(), Box<dyn Error>> {
+    let mut scanner = session.watch(args.filter()).await?;
+    let decoders = Decoders::builtin();
+    let mut ranging = Ranging::new(&args.ranging);
+
+    let stop = time::sleep(args.duration.unwrap_or(Duration::MAX));
+    tokio::pin!(stop);
+
+    loop {
+        let event = tokio::select! {
+            event = scanner.next() => match event? {
+                Some(event) => event,
+                None => break,
+            },
+            _ = signal::ctrl_c() => break,
+            _ = &mut stop => break,
+        };
+
+        let seen = local_time(event.seen);
+        let distance = ranging.distance(&event.device);
+        let mut record = ScanEventRecord::new(event, names, &decoders);
+        record.device.distance = distance;
+        out.event(&record, |e| {
+            println!(
+                "{} {:<10} {}: {}",
+                seen.format("%H:%M:%S%.3f"),
+                e.event,
+                e.device.address,
+                e.device.name.as_deref().unwrap_or("Unknown")
+            );
+            print_decoded(&e.device);
+            if args.long {
+                print_details(&e.device);
+            }
+        });
+    }
+
+    scanner.stop().await?;
+    Ok(())
+}
+
+pub async fn list_beacons(session: &Session, args: &BeaconsArgs, out: Output) -> Result<(), Box<dyn Error>> {
+    if args.watch {
+        return watch_beacons(session, args, out).await;
+    }
+
+    let devices = session.scan(args.duration.unwrap_or(SCAN_TIME), &DeviceFilter::default()).await?;
+    let mut ranging = Ranging::new(&args.ranging);
+    let mut beacons: Vec<BeaconRecord> = devices.iter()
+        .flat_map(|d| BeaconRecord::all(d, ranging.rssi(d), ranging.environment))
+        .collect();
+    // Nearest first, those without a distance last
+    beacons.sort_by(|a, b| match (a.distance, b.distance) {
+        (Some(a), Some(b)) => a.total_cmp(&b),
+        (a, b) => b.is_some().cmp(&a.is_some()),
+    });
+
+    out.list(&beacons, |b| println!("{}", beacon_line(b)));
+    Ok(())
+}
+
+async fn watch_beacons(session: &Session, args: &BeaconsArgs, out: Output) -> Result<(), Box<dyn Error>> {
+    let mut scanner = session.watch(DeviceFilter::default()).await?;
+    let mut ranging = Ranging::new(&args.ranging);
+
+    let stop = time::sleep(args.duration.unwrap_or(Duration::MAX));
+    tokio::pin!(stop);
+
+    loop {
+        let event = tokio::select! {
+            event = scanner.next() => match event? {
+                Some(event) => event,
+                None => break,
+            },
+            _ = signal::ctrl_c() => break,
+            _ = &mut stop => break,
+        };
+
+        let seen = local_time(event.seen);
+        let rssi = ranging.rssi(&event.device);
+        for b in BeaconRecord::all(&event.device, rssi, ranging.environment) {
+            out.event(&b, |b| println!("{} {}", seen.format("%H:%M:%S%.3f"), beacon_line(b)));
+        }
+    }
+
+    scanner.stop().await?;
+    Ok(())
+}
+
+fn beacon_line(b: &BeaconRecord) -> String {
+    let rssi = b.rssi.map(|r| format!("{} dBm", r)).unwrap_or_default();
+    let distance = b.distance.map(|d| format!("~{:.1} m", d)).unwrap_or_default();
+    format!("{} {:>8} {:>8} {}", b.address, rssi, distance, b.beacon)
+}
+
+/// Estimates the distance of devices, smoothing the signal strength of each across the
+/// advertisements seen.
+struct Ranging {
+    environment: f64,
+    smoothing: Smoothing,
+    filters: HashMap<BDAddr, RssiFilter>,
+}
+
+impl Ranging {
+    fn new(args: &RangingArgs) -> Ranging {
+        Ranging {
+            environment: args.environment,
+            smoothing: args.smoothing(),
+            filters: HashMap::new(),
+        }
+    }
+
+    /// Smoothed signal strength of the device, its latest value included.
+    fn rssi(&mut self, device: &DeviceInfo) -> Option<f64> {
+        let rssi = device.rssi?;
+        let filter = self.filters.entry(device.address).or_insert_with(|| RssiFilter::new(self.smoothing));
+        Some(filter.update(rssi))
+    }
+
+    fn distance(&mut self, device: &DeviceInfo) -> Option<f64> {
+        let rssi = self.rssi(device)?;
+        Some(distance::estimate(rssi, distance::measured_power(device)?, self.environment))
+    }
+}
@@ -0,0 +1,357 @@
+//! Sending files in chunks, checking the device received them intact.
+
+use std::error::Error;
+use std::fs;
+use std::io::{stderr, IsTerminal};
+use std::time::{Duration, Instant};
+
+use ble_util::backend::EventStream;
+use ble_util::{hex, BleUtilError, CharPropFlags, Session, Uuid, ValueNotification, WriteType};
+use sha2::{Digest, Sha256};
+use tokio::time;
+use tokio_stream::StreamExt;
+
+use super::gatt::{connect, printable, CHAR_READ, CHAR_WRITE};
+use super::output::{Output, TransferRecord};
+use super::{Checksum, SendFileArgs};
+
+/// Width of the progress bar, in characters.
+const BAR_WIDTH: usize = 30;
+
+/// Minimum time between two redraws of the progress bar.
+const REDRAW_INTERVAL: Duration = Duration::from_millis(100);
+
+pub async fn send_file(session: &Session, args: &SendFileArgs, out: Output) -> Result<(), Box<dyn Error>> {
+    let path = args.path.display();
+    let data = fs::read(&args.path).map_err(|e| format!("{}: {}", path, e))?;
+    let size = u32::try_from(data.len()).map_err(|_| format!("{}: file too large", path))?;
+
+    let char_id = args.characteristic.unwrap_or(CHAR_WRITE);
+    let reply = args.reply.unwrap_or(CHAR_READ);
+    let expected = digest(args.verify, &data);
+
+    let dev = connect(session, &args.device, out).await?;
+    let write_type = if dev.characteristic(char_id)?.properties.contains(CharPropFlags::WRITE) {
+        WriteType::WithResponse
+    } else {
+        WriteType::WithoutResponse
+    };
+    let chunking = args.chunk.chunking();
+
+    // Listen before sending so that an answer coming right after the last chunk isn't missed. A
+    // reply characteristic that can't notify is read once the file is sent instead.
+    let notifies = dev.characteristic(reply)?.properties.intersects(CharPropFlags::NOTIFY | CharPropFlags::INDICATE);
+    let mut replies = match expected {
+        Some(_) if notifies => {
+            let notifications = dev.notifications().await?;
+            dev.subscribe(reply).await?;
+            Some(notifications)
+        }
+        _ => None,
+    };
+
+    if args.size_header {
+        dev.write_chunked(char_id, &size.to_le_bytes(), write_type, &chunking).await?;
+    }
+
+    let mut progress = Progress::new(data.len(), out);
+    let chunks = dev
+        .write_chunked_with_progress(char_id, &data, write_type, &chunking, |sent| progress.update(sent))
+        .await?;
+    let elapsed = progress.finish();
+
+    let received = match (&expected, &mut replies) {
+        (Some(_), Some(replies)) => {
+            let received = wait_checksum(replies, reply, args.verify, args.verify_timeout).await?;
+            dev.unsubscribe(reply).await?;
+            Some(received)
+        }
+        (Some(_), None) => Some(dev.read(reply).await?),
+        (None, _) => None,
+    };
+    let verified = expected.as_ref().zip(received.as_ref()).map(|(e, r)| matches(args.verify, e, r));
+
+    let seconds = elapsed.as_secs_f64();
+    let record = TransferRecord {
+        address: dev.address().to_string(),
+        characteristic: char_id.to_string(),
+        length: data.len(),
+        chunks,
+        seconds,
+        bytes_per_second: data.len() as f64 / seconds.max(f64::EPSILON),
+        checksum: expected.as_ref().map(|_| name(args.verify)),
+        expected: expected.as_deref().map(hex),
+        received: received.as_deref().map(hex),
+        verified,
+    };
+
+    out.value(&record, |r| {
+        println!(
+            "Sent {} in {} chunk{} in {:.2}s ({}/s)",
+            bytes(r.length as f64),
+            r.chunks,
+            if r.chunks == 1 { "" } else { "s" },
+            r.seconds,
+            bytes(r.bytes_per_second),
+        );
+        match (r.checksum, &r.expected, &r.received, r.verified) {
+            (Some(checksum), Some(expected), _, Some(true)) => println!("{} {} verified", checksum, expected),
+            (Some(checksum), Some(expected), Some(received), _) => {
+                // Answers in hex text are clearer as the text
+                let received = printable(received).unwrap_or_else(|| received.clone());
+                println!("{} mismatch: device answered {}, expected {}", checksum, received, expected);
+            }
+            _ => {}
+        }
+    });
+
+    if verified == Some(false) {
+        return Err(BleUtilError::Mismatch(1).into());
+    }
+    Ok(())
+}
+
+/// Checksum of the file, as the bytes the device is expected to answer with.
+fn digest(checksum: Checksum, data: &[u8]) -> Option<Vec<u8>> {
+    match checksum {
+        Checksum::None => None,
+        Checksum::Crc32 => Some(crc32fast::hash(data).to_be_bytes().to_vec()),
+        Checksum::Sha256 => Some(Sha256::digest(data).to_vec()),
+    }
+}
+
+fn name(checksum: Checksum) -> &'static str {
+    match checksum {
+        Checksum::None => "none",
+        Checksum::Crc32 => "crc32",
+        Checksum::Sha256 => "sha256",
+    }
+}
+
+/// Waits for the device to notify something that looks like a checksum: as many bytes as one, or
+/// twice as many hex digits. Other notifications, such as echoes or progress reports, are skipped.
+async fn wait_checksum(
+    replies: &mut EventStream<ValueNotification>,
+    reply: Uuid,
+    checksum: Checksum,
+    timeout: Duration,
+) -> Result<Vec<u8>, BleUtilError> {
+    let len = match checksum {
+        Checksum::Crc32 => 4,
+        Checksum::Sha256 => 32,
+        Checksum::None => unreachable!("not verifying"),
+    };
+
+    let deadline = time::Instant::now() + timeout;
+    loop {
+        match time::timeout_at(deadline, replies.next()).await {
+            Ok(Some(n)) if n.uuid == reply => {
+                if n.value.len() == len || n.value.trim_ascii().len() == 2 * len {
+                    return Ok(n.value);
+                }
+            }
+            Ok(Some(_)) => {}
+            Ok(None) | Err(_) => return Err(BleUtilError::Timeout(timeout)),
+        }
+    }
+}
+
+/// Whether the device answered with the expected checksum, as raw bytes or as hex text. CRC32s may
+/// come in either byte order.
+fn matches(checksum: Checksum, expected: &[u8], received: &[u8]) -> bool {
+    let reversed: Vec<u8> = expected.iter().rev().copied().collect();
+    let text = String::from_utf8_lossy(received.trim_ascii()).to_ascii_lowercase();
+
+    received == expected
+        || (checksum == Checksum::Crc32 && received == reversed)
+        || text == hex(expected)
+}
+
+/// Formats a number of bytes with a binary unit.
+fn bytes(n: f64) -> String {
+    match n {
+        n if n < 1024.0 => format!("{:.0} B", n),
+        n if n < 1024.0 * 1024.0 => format!("{:.1} KiB", n / 1024.0),
+        n => format!("{:.1} MiB", n / (1024.0 * 1024.0)),
+    }
+}
+
+/// Progress bar with throughput, drawn on stderr when it's a terminal and the output is text.
+struct Progress {
+    total: usize,
+    start: Instant,
+    drawn: Option<Instant>,
+    enabled: bool,
+}
+
+impl Progress {
+    fn new(total: usize, out: Output) -> Progress {
+        Progress {
+            total,
+            start: Instant::now(),
+            drawn: None,
+            enabled: out.is_text() && stderr().is_terminal(),
+        }
+    }
+
+    fn update(&mut self, sent: usize) {
+        if !self.enabled || self.drawn.is_some_and(|t| t.elapsed() < REDRAW_INTERVAL) {
+            return;
+        }
+        self.drawn = Some(Instant::now());
+
+        let fraction = if self.total == 0 { 1.0 } else { sent as f64 / self.total as f64 };
+        let filled = (fraction * BAR_WIDTH as f64) as usize;
+        let rate = sent as f64 / self.start.elapsed().as_secs_f64().max(f64::EPSILON);
+        eprint!(
+            "\r[{}{}] {:3.0}% {} / {} {}/s  ",
+            "#".repeat(filled),
+            " ".repeat(BAR_WIDTH - filled),
+            fraction * 100.0,
+            bytes(sent as f64),
+            bytes(self.total as f64),
+            bytes(rate),
+        );
+    }
+
+    /// Draws the completed bar and returns how long the transfer took.
+    fn finish(mut self) -> Duration {
+        let elapsed = self.start.elapsed();
+        self.drawn = None;
+        self.update(self.total);
+        if self.enabled {
+            eprintln!();
+        }
+        elapsed
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use ble_util::backend::MockPeripheral;
+
+    use super::*;
+    use crate::cli::output::Format;
+    use crate::cli::{mock_session, parse_command, Command};
+
+    /// Characteristic holding the checksum of what was received.
+    const CHECKSUM: Uuid = Uuid::from_u128(0x6e400004_b5a3_f393_e0a9_e50e24dcca9e);
+
+    const CRC32_CHECK: [u8; 4] = [0xcb, 0xf4, 0x39, 0x26];
+    const SHA256_ABC: &str = "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad";
+
+    fn notification(uuid: Uuid, value: &[u8]) -> ValueNotification {
+        ValueNotification { uuid, value: value.to_vec() }
+    }
+
+    #[test]
+    fn digests_data() {
+        assert_eq!(digest(Checksum::None, b"123456789"), None);
+        assert_eq!(digest(Checksum::Crc32, b"123456789"), Some(CRC32_CHECK.to_vec()));
+        assert_eq!(digest(Checksum::Sha256, b"abc").map(|d| hex(&d)).as_deref(), Some(SHA256_ABC));
+    }
+
+    #[test]
+    fn matches_raw_checksums() {
+        assert!(matches(Checksum::Crc32, &CRC32_CHECK, &CRC32_CHECK));
+        assert!(!matches(Checksum::Crc32, &CRC32_CHECK, &[0xcb, 0xf4, 0x39, 0x27]));
+        assert!(!matches(Checksum::Crc32, &CRC32_CHECK, &CRC32_CHECK[..3]));
+    }
+
+    #[test]
+    fn matches_crc32_in_either_byte_order() {
+        let reversed = [0x26, 0x39, 0xf4, 0xcb];
+        assert!(matches(Checksum::Crc32, &CRC32_CHECK, &reversed));
+
+        // Only CRC32s are numbers whose byte order may vary
+        let sha = digest(Checksum::Sha256, b"abc").unwrap();
+        let reversed: Vec<u8> = sha.iter().rev().copied().collect();
+        assert!(!matches(Checksum::Sha256, &sha, &reversed));
+    }
+
+    #[test]
+    fn matches_hex_text_checksums() {
+        assert!(matches(Checksum::Crc32, &CRC32_CHECK, b"cbf43926"));
+        assert!(matches(Checksum::Crc32, &CRC32_CHECK, b"CBF43926\r\n"));
+        assert!(!matches(Checksum::Crc32, &CRC32_CHECK, b"2639f4cb"));
+
+        let sha = digest(Checksum::Sha256, b"abc").unwrap();
+        assert!(matches(Checksum::Sha256, &sha, format!("{}\n", SHA256_ABC).as_bytes()));
+    }
+
+    #[tokio::test]
+    async fn waits_for_a_checksum_sized_reply() {
+        let other = Uuid::from_u128(1);
+        let mut replies: EventStream<ValueNotification> = Box::pin(tokio_stream::iter(vec![
+            // Echo, progress on another characteristic, then the checksum as text
+            notification(CHAR_READ, b"hello"),
+            notification(other, &CRC32_CHECK),
+            notification(CHAR_READ, b"cbf43926\n"),
+        ]));
+
+        let received = wait_checksum(&mut replies, CHAR_READ, Checksum::Crc32, Duration::from_secs(1)).await;
+        assert_eq!(received.unwrap(), b"cbf43926\n");
+    }
+
+    #[tokio::test]
+    async fn times_out_without_a_checksum() {
+        let mut replies: EventStream<ValueNotification> = Box::pin(tokio_stream::iter(vec![
+            notification(CHAR_READ, &CRC32_CHECK),
+        ]));
+
+        let timeout = Duration::from_millis(50);
+        let received = wait_checksum(&mut replies, CHAR_READ, Checksum::Sha256, timeout).await;
+        assert!(matches!(received, Err(BleUtilError::Timeout(t)) if t == timeout));
+    }
+
+    #[test]
+    fn formats_byte_counts() {
+        assert_eq!(bytes(0.0), "0 B");
+        assert_eq!(bytes(1023.0), "1023 B");
+        assert_eq!(bytes(1536.0), "1.5 KiB");
+        assert_eq!(bytes(2.0 * 1024.0 * 1024.0), "2.0 MiB");
+    }
+
+    /// A device taking files on the Nordic UART RX characteristic and answering with their CRC32,
+    /// plus `offset`, in a characteristic that can only be read.
+    fn checksum_device(offset: u32) -> MockPeripheral {
+        let service = Uuid::from_u128(0x6e400001_b5a3_f393_e0a9_e50e24dcca9e);
+        let mut received = Vec::new();
+        MockPeripheral::new([0xaa, 0xbb, 0xcc, 0xdd, 0xee, 0x20].into())
+            .service(service)
+            .characteristic(service, CHAR_WRITE, CharPropFlags::WRITE, &[])
+            .characteristic(service, CHECKSUM, CharPropFlags::READ, &[])
+            .on_write(CHAR_WRITE, move |data| {
+                received.extend_from_slice(data);
+                let crc = crc32fast::hash(&received).wrapping_add(offset);
+                vec![notification(CHECKSUM, &crc.to_le_bytes())]
+            })
+    }
+
+    fn send_file_args(path: &Path) -> SendFileArgs {
+        let reply = CHECKSUM.to_string();
+        match parse_command(&["send-file", "aa:bb:cc:dd:ee:20", path.to_str().unwrap(), "--reply", &reply]) {
+            Ok(Command::SendFile(args)) => args,
+            _ => unreachable!("parsed send-file"),
+        }
+    }
+
+    #[tokio::test]
+    async fn reads_checksums_from_characteristics_that_cant_notify() {
+        let path = std::env::temp_dir().join(format!("ble-util-read-checksum-{}", std::process::id()));
+        fs::write(&path, [0x5a; 100]).unwrap();
+        let args = send_file_args(&path);
+
+        let session = mock_session(checksum_device(0)).await;
+        let res = send_file(&session, &args, Output::new(Format::Ndjson)).await;
+        assert!(res.is_ok(), "{:?}", res);
+
+        let session = mock_session(checksum_device(1)).await;
+        let res = send_file(&session, &args, Output::new(Format::Ndjson)).await;
+        fs::remove_file(&path).ok();
+        let err = res.unwrap_err();
+        assert!(matches!(err.downcast_ref::<BleUtilError>(), Some(BleUtilError::Mismatch(_))), "{}", err);
+    }
+}
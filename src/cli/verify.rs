@@ -0,0 +1,354 @@
+//! Checking a device against an expected GATT profile.
+//!
+//! A profile is a TOML file listing the services the device must have, and in each the
+//! characteristics it must have:
+//!
+//! ```toml
+//! [[service]]
+//! uuid = "180f"
+//!
+//! [[service.characteristic]]
+//! uuid = "2a19"
+//! # Properties the characteristic must have at least, named as in `ping`
+//! properties = ["read", "notify"]
+//! # Optional: read the value, format it as with `read --as` (hex by default) and check the
+//! # whole of it matches a regular expression
+//! format = "u8"
+//! value = "[0-9]+"
+//! ```
+
+use std::error::Error;
+use std::fs;
+use std::path::Path;
+
+use ble_util::{parse_uuid, BleUtilError, CharPropFlags, Device, Names, ServiceInfo, Session, Uuid, ValueFormat};
+use regex::Regex;
+use serde::de::{self, Deserializer};
+use serde::Deserialize;
+
+use super::gatt::connect;
+use super::output::{named, property_flag, property_names, CheckRecord, Output};
+use super::VerifyArgs;
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct Profile {
+    #[serde(default, rename = "service")]
+    services: Vec<ServiceProfile>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct ServiceProfile {
+    #[serde(deserialize_with = "uuid")]
+    uuid: Uuid,
+    #[serde(default, rename = "characteristic")]
+    characteristics: Vec<CharacteristicProfile>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct CharacteristicProfile {
+    #[serde(deserialize_with = "uuid")]
+    uuid: Uuid,
+    #[serde(default, deserialize_with = "properties")]
+    properties: CharPropFlags,
+    #[serde(default, deserialize_with = "format")]
+    format: Option<ValueFormat>,
+    value: Option<Pattern>,
+}
+
+/// A regular expression the whole of a value must match.
+struct Pattern {
+    source: String,
+    regex: Regex,
+}
+
+impl<'de> Deserialize<'de> for Pattern {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Pattern, D::Error> {
+        let source = String::deserialize(deserializer)?;
+        let regex = Regex::new(&format!("^(?:{})$", source)).map_err(de::Error::custom)?;
+        Ok(Pattern { source, regex })
+    }
+}
+
+fn uuid<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Uuid, D::Error> {
+    let s = String::deserialize(deserializer)?;
+    parse_uuid(&s).map_err(|e| de::Error::custom(format!("invalid uuid '{}': {}", s, e)))
+}
+
+fn properties<'de, D: Deserializer<'de>>(deserializer: D) -> Result<CharPropFlags, D::Error> {
+    Vec::<String>::deserialize(deserializer)?
+        .iter()
+        .map(|name| property_flag(name).ok_or_else(|| de::Error::custom(format!("unknown property '{}'", name))))
+        .collect()
+}
+
+fn format<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Option<ValueFormat>, D::Error> {
+    String::deserialize(deserializer)?.parse().map(Some).map_err(de::Error::custom)
+}
+
+pub async fn verify(session: &Session, args: &VerifyArgs, names: &Names, out: Output) -> Result<(), Box<dyn Error>> {
+    let profile = load(&args.profile)?;
+    let dev = connect(session, &args.device, out).await?;
+
+    let services = dev.services();
+    let mut checks = Vec::new();
+    for expected in profile.services.iter() {
+        check_service(&dev, &services, expected, names, &mut checks).await;
+    }
+
+    out.list(&checks, |c| {
+        let uuid = c.characteristic.as_ref().unwrap_or(&c.service);
+        let status = if c.ok { "PASS" } else { "FAIL" };
+        match &c.message {
+            Some(msg) => println!("{} {} {}: {}", status, c.check, named(uuid, c.name.as_deref()), msg),
+            None => println!("{} {} {}", status, c.check, named(uuid, c.name.as_deref())),
+        }
+    });
+
+    let failed = checks.iter().filter(|c| !c.ok).count();
+    if failed > 0 {
+        return Err(BleUtilError::Mismatch(failed).into());
+    }
+
+    out.status(&format!("All {} checks passed", checks.len()));
+    Ok(())
+}
+
+fn load(path: &Path) -> Result<Profile, Box<dyn Error>> {
+    let text = fs::read_to_string(path).map_err(|e| format!("{}: {}", path.display(), e))?;
+    Ok(toml::from_str(&text).map_err(|e| format!("{}: {}", path.display(), e))?)
+}
+
+async fn check_service(
+    dev: &Device,
+    services: &[ServiceInfo],
+    expected: &ServiceProfile,
+    names: &Names,
+    checks: &mut Vec<CheckRecord>,
+) {
+    let check = |kind, characteristic: Option<Uuid>, message: Option<String>| CheckRecord {
+        ok: message.is_none(),
+        check: kind,
+        service: expected.uuid.to_string(),
+        characteristic: characteristic.map(|c| c.to_string()),
+        name: names.uuid(characteristic.unwrap_or(expected.uuid)).map(String::from),
+        message,
+    };
+
+    let service = match services.iter().find(|s| s.uuid == expected.uuid) {
+        Some(s) => s,
+        None => {
+            checks.push(check("service", None, Some("missing".into())));
+            return;
+        }
+    };
+    checks.push(check("service", None, None));
+
+    for expected in expected.characteristics.iter() {
+        let uuid = Some(expected.uuid);
+        let c = match service.characteristics.iter().find(|c| c.uuid == expected.uuid) {
+            Some(c) => c,
+            None => {
+                checks.push(check("characteristic", uuid, Some("missing".into())));
+                continue;
+            }
+        };
+        checks.push(check("characteristic", uuid, None));
+
+        if !expected.properties.is_empty() {
+            let missing = expected.properties - c.properties;
+            let message = (!missing.is_empty()).then(|| format!("missing {}", property_names(missing).join(", ")));
+            checks.push(check("properties", uuid, message));
+        }
+
+        if let Some(pattern) = &expected.value {
+            let message = match read_formatted(dev, c.uuid, expected.format.as_ref()).await {
+                Ok(value) if pattern.regex.is_match(&value) => None,
+                Ok(value) => Some(format!("'{}' doesn't match '{}'", value, pattern.source)),
+                Err(e) => Some(e),
+            };
+            checks.push(check("value", uuid, message));
+        }
+    }
+}
+
+async fn read_formatted(dev: &Device, uuid: Uuid, format: Option<&ValueFormat>) -> Result<String, String> {
+    let data = dev.read(uuid).await.map_err(|e| e.to_string())?;
+    Ok(format.unwrap_or(&ValueFormat::Hex).decode(&data)?.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use ble_util::backend::MockPeripheral;
+
+    use super::*;
+    use crate::cli::mock_session;
+
+    const BATTERY_SERVICE: Uuid = Uuid::from_u128(0x0000180f_0000_1000_8000_00805f9b34fb);
+    const BATTERY_LEVEL: Uuid = Uuid::from_u128(0x00002a19_0000_1000_8000_00805f9b34fb);
+    const SERIAL_NUMBER: Uuid = Uuid::from_u128(0x00002a25_0000_1000_8000_00805f9b34fb);
+
+    fn parse(text: &str) -> Result<Profile, String> {
+        toml::from_str(text).map_err(|e| e.to_string())
+    }
+
+    #[test]
+    fn parses_profiles() {
+        let profile = parse(
+            r#"
+            [[service]]
+            uuid = "180f"
+
+            [[service.characteristic]]
+            uuid = "2a19"
+            properties = ["read", "notify"]
+            format = "u8"
+            value = "[0-9]+"
+            "#,
+        )
+        .unwrap();
+
+        assert_eq!(profile.services.len(), 1);
+        let service = &profile.services[0];
+        assert_eq!(service.uuid, BATTERY_SERVICE);
+        let c = &service.characteristics[0];
+        assert_eq!(c.uuid, BATTERY_LEVEL);
+        assert_eq!(c.properties, CharPropFlags::READ | CharPropFlags::NOTIFY);
+        assert!(c.format.is_some());
+        let pattern = c.value.as_ref().unwrap();
+        assert!(pattern.regex.is_match("100"));
+        // The whole value has to match
+        assert!(!pattern.regex.is_match("100%"));
+    }
+
+    #[test]
+    fn rejects_unknown_fields() {
+        let err = parse("[[service]]\nuuid = \"180f\"\nname = \"battery\"\n").err().unwrap();
+        assert!(err.contains("unknown field `name`"), "{}", err);
+
+        let err = parse("[[service]]\nuuid = \"180f\"\n[[service.characteristic]]\nuuid = \"2a19\"\nvalues = \"1\"\n")
+            .err()
+            .unwrap();
+        assert!(err.contains("unknown field `values`"), "{}", err);
+    }
+
+    #[test]
+    fn rejects_unknown_properties() {
+        let err = parse(r#"
+            [[service]]
+            uuid = "180f"
+            [[service.characteristic]]
+            uuid = "2a19"
+            properties = ["read", "listen"]
+            "#)
+        .err()
+        .unwrap();
+        assert!(err.contains("unknown property 'listen'"), "{}", err);
+    }
+
+    #[test]
+    fn rejects_bad_uuids() {
+        let err = parse("[[service]]\nuuid = \"battery\"\n").err().unwrap();
+        assert!(err.contains("invalid uuid 'battery'"), "{}", err);
+    }
+
+    fn device() -> MockPeripheral {
+        MockPeripheral::new([0xaa, 0xbb, 0xcc, 0xdd, 0xee, 0x30].into())
+            .service(BATTERY_SERVICE)
+            .characteristic(BATTERY_SERVICE, BATTERY_LEVEL, CharPropFlags::READ, &[87])
+            .characteristic(BATTERY_SERVICE, SERIAL_NUMBER, CharPropFlags::READ, &[])
+            .fail(SERIAL_NUMBER, "insufficient authentication")
+    }
+
+    async fn check(profile: &str) -> Vec<CheckRecord> {
+        let session = mock_session(device()).await;
+        let dev = session.connect(&"aa:bb:cc:dd:ee:30".parse().unwrap(), Duration::from_secs(2)).await.unwrap();
+        let services = dev.services();
+        let mut checks = Vec::new();
+        for expected in parse(profile).unwrap().services.iter() {
+            check_service(&dev, &services, expected, &Names::new(), &mut checks).await;
+        }
+        checks
+    }
+
+    fn summary(checks: &[CheckRecord]) -> Vec<(&str, Option<&str>)> {
+        checks.iter().map(|c| (c.check, c.message.as_deref())).collect()
+    }
+
+    #[tokio::test]
+    async fn passes_matching_devices() {
+        let checks = check(
+            r#"
+            [[service]]
+            uuid = "180f"
+            [[service.characteristic]]
+            uuid = "2a19"
+            properties = ["read"]
+            format = "u8"
+            value = "8[0-9]"
+            "#,
+        )
+        .await;
+        let passed = [("service", None), ("characteristic", None), ("properties", None), ("value", None)];
+        assert_eq!(summary(&checks), passed);
+        assert!(checks.iter().all(|c| c.ok));
+        assert_eq!(checks[1].name.as_deref(), Some("Battery Level"));
+    }
+
+    #[tokio::test]
+    async fn reports_missing_services() {
+        let checks = check("[[service]]\nuuid = \"180a\"\n[[service.characteristic]]\nuuid = \"2a29\"\n").await;
+        // Its characteristics aren't checked
+        assert_eq!(summary(&checks), [("service", Some("missing"))]);
+        assert!(!checks[0].ok);
+    }
+
+    #[tokio::test]
+    async fn reports_missing_characteristics_and_properties() {
+        let checks = check(
+            "[[service]]\nuuid = \"180f\"\n[[service.characteristic]]\nuuid = \"2a1a\"\n\
+             [[service.characteristic]]\nuuid = \"2a19\"\nproperties = [\"read\", \"notify\", \"indicate\"]\n",
+        )
+        .await;
+        assert_eq!(
+            summary(&checks),
+            [
+                ("service", None),
+                ("characteristic", Some("missing")),
+                ("characteristic", None),
+                ("properties", Some("missing notify, indicate")),
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn reports_mismatched_and_unreadable_values() {
+        let checks = check(
+            "[[service]]\nuuid = \"180f\"\n[[service.characteristic]]\nuuid = \"2a19\"\nvalue = \"64\"\n\
+             [[service.characteristic]]\nuuid = \"2a25\"\nvalue = \".*\"\n",
+        )
+        .await;
+        assert_eq!(checks[2].message.as_deref(), Some("'57' doesn't match '64'"));
+        let message = checks[4].message.as_deref().unwrap();
+        assert!(message.contains("insufficient authentication"), "{}", message);
+        assert!(!checks[4].ok);
+    }
+
+    #[tokio::test]
+    async fn reads_formatted_values() {
+        let session = mock_session(device()).await;
+        let dev = session.connect(&"aa:bb:cc:dd:ee:30".parse().unwrap(), Duration::from_secs(2)).await.unwrap();
+
+        assert_eq!(read_formatted(&dev, BATTERY_LEVEL, None).await.as_deref(), Ok("57"));
+        let format: ValueFormat = "u8".parse().unwrap();
+        assert_eq!(read_formatted(&dev, BATTERY_LEVEL, Some(&format)).await.as_deref(), Ok("87"));
+        // Too short for the format
+        let format: ValueFormat = "u16".parse().unwrap();
+        assert!(read_formatted(&dev, BATTERY_LEVEL, Some(&format)).await.is_err());
+        assert!(read_formatted(&dev, SERIAL_NUMBER, None).await.is_err());
+    }
+}
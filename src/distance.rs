@@ -0,0 +1,203 @@
+//! Estimating how far a device is from its signal strength, with the log-distance path loss
+//! model, and smoothing the signal strength across advertisements.
+
+use crate::beacon;
+use crate::session::DeviceInfo;
+
+/// Path loss exponent of free space.
+pub const FREE_SPACE: f64 = 2.0;
+
+/// Signal lost over the first meter at 2.4 GHz, in dB: the difference between the transmission
+/// power and the strength at which it is received from 1 m.
+pub const LOSS_AT_1M: i16 = 41;
+
+/// Estimated distance in meters of a transmitter received at `rssi` dBm, and at `measured_power`
+/// dBm from 1 m. `environment` is the path loss exponent, from 2 in free space to about 4 with
+/// many obstacles.
+pub fn estimate(rssi: f64, measured_power: i16, environment: f64) -> f64 {
+    10f64.powf((measured_power as f64 - rssi) / (10.0 * environment))
+}
+
+/// Strength at which a device is received from 1 m, in dBm: the one of its beacon frames if it
+/// has any, otherwise derived from its advertised transmission power.
+pub fn measured_power(device: &DeviceInfo) -> Option<i16> {
+    beacon::beacons(device).iter()
+        .find_map(|b| b.measured_power())
+        .or_else(|| Some(device.tx_power? - LOSS_AT_1M))
+}
+
+/// How to smooth the signal strength of a device, which varies a lot from one advertisement to
+/// the next.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Smoothing {
+    None,
+    /// Exponential moving average, each new value weighing `alpha`, between 0 and 1
+    Ema { alpha: f64 },
+    /// One-dimensional Kalman filter for a still transmitter. The noises are variances, in dB²
+    Kalman { process_noise: f64, measurement_noise: f64 },
+}
+
+impl Smoothing {
+    /// Moving average weighing each new value a quarter.
+    pub const EMA: Smoothing = Smoothing::Ema { alpha: 0.25 };
+
+    /// Kalman filter allowing for slow movement and a couple of dB of noise.
+    pub const KALMAN: Smoothing = Smoothing::Kalman { process_noise: 0.1, measurement_noise: 4.0 };
+}
+
+/// Smoothed signal strength of one device.
+#[derive(Debug, Clone)]
+pub struct RssiFilter {
+    smoothing: Smoothing,
+    estimate: Option<f64>,
+    /// Variance of the estimate, for the Kalman filter
+    error: f64,
+}
+
+impl RssiFilter {
+    pub fn new(smoothing: Smoothing) -> RssiFilter {
+        RssiFilter { smoothing, estimate: None, error: 0.0 }
+    }
+
+    /// Adds a new measurement, returning the smoothed signal strength.
+    pub fn update(&mut self, rssi: i16) -> f64 {
+        let rssi = rssi as f64;
+        let estimate = match (self.smoothing, self.estimate) {
+            (Smoothing::None, _) | (_, None) => {
+                if let Smoothing::Kalman { measurement_noise, .. } = self.smoothing {
+                    self.error = measurement_noise;
+                }
+                rssi
+            }
+            (Smoothing::Ema { alpha }, Some(previous)) => previous + alpha * (rssi - previous),
+            (Smoothing::Kalman { process_noise, measurement_noise }, Some(previous)) => {
+                let error = self.error + process_noise;
+                let gain = error / (error + measurement_noise);
+                self.error = (1.0 - gain) * error;
+                previous + gain * (rssi - previous)
+            }
+        };
+
+        self.estimate = Some(estimate);
+        estimate
+    }
+
+    /// The smoothed signal strength, `None` before the first measurement.
+    pub fn value(&self) -> Option<f64> {
+        self.estimate
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+
+    use btleplug::api::BDAddr;
+
+    use super::*;
+
+    fn assert_close(actual: f64, expected: f64, tolerance: f64) {
+        assert!((actual - expected).abs() <= tolerance, "{} is not within {} of {}", actual, tolerance, expected);
+    }
+
+    fn device(tx_power: Option<i16>, manufacturer_data: BTreeMap<u16, Vec<u8>>) -> DeviceInfo {
+        DeviceInfo {
+            address: BDAddr::default(),
+            address_type: None,
+            local_name: None,
+            rssi: Some(-70),
+            tx_power,
+            services: Vec::new(),
+            manufacturer_data,
+            service_data: BTreeMap::new(),
+        }
+    }
+
+    /// Signal around -70 dBm, as noisy as a real one.
+    fn noisy() -> impl Iterator<Item = i16> {
+        const NOISE: [i16; 10] = [0, -4, 3, -7, 2, 5, -2, -6, 4, 5];
+        NOISE.into_iter().cycle().map(|n| -70 + n)
+    }
+
+    #[test]
+    fn estimates_one_meter_at_the_measured_power() {
+        for environment in [FREE_SPACE, 3.0, 4.0] {
+            assert_close(estimate(-59.0, -59, environment), 1.0, 1e-9);
+        }
+    }
+
+    #[test]
+    fn estimates_distances_with_the_environment_factor() {
+        // 20 dB weaker is 10 times as far in free space
+        assert_close(estimate(-79.0, -59, FREE_SPACE), 10.0, 1e-9);
+        assert_close(estimate(-65.0, -59, FREE_SPACE), 2.0, 0.01);
+        // The same loss means less distance where the signal fades faster
+        assert_close(estimate(-79.0, -59, 4.0), 10f64.sqrt(), 1e-9);
+        assert!(estimate(-79.0, -59, 3.0) < estimate(-79.0, -59, FREE_SPACE));
+        // And stronger than at 1 m means closer
+        assert_close(estimate(-39.0, -59, FREE_SPACE), 0.1, 1e-9);
+    }
+
+    #[test]
+    fn measured_power_prefers_beacon_frames() {
+        let ibeacon = [&[0x02, 0x15][..], &[0; 16], &[0, 1, 0, 2, 0xc5]].concat();
+        let beacon = device(Some(-4), BTreeMap::from([(0x004c, ibeacon)]));
+        assert_eq!(measured_power(&beacon), Some(-59));
+
+        assert_eq!(measured_power(&device(Some(-4), BTreeMap::new())), Some(-45));
+        assert_eq!(measured_power(&device(None, BTreeMap::new())), None);
+    }
+
+    #[test]
+    fn no_smoothing_keeps_the_last_value() {
+        let mut filter = RssiFilter::new(Smoothing::None);
+        assert_eq!(filter.value(), None);
+        assert_eq!(filter.update(-60), -60.0);
+        assert_eq!(filter.update(-80), -80.0);
+        assert_eq!(filter.value(), Some(-80.0));
+    }
+
+    #[test]
+    fn moving_average_converges() {
+        let mut filter = RssiFilter::new(Smoothing::EMA);
+        assert_eq!(filter.update(-80), -80.0);
+        assert_eq!(filter.update(-60), -75.0);
+
+        for _ in 0..30 {
+            filter.update(-60);
+        }
+        assert_close(filter.value().unwrap(), -60.0, 0.01);
+    }
+
+    #[test]
+    fn kalman_filter_converges() {
+        let mut filter = RssiFilter::new(Smoothing::KALMAN);
+        assert_eq!(filter.update(-80), -80.0);
+
+        // Moves towards a new level, less and less with each measurement
+        let first = filter.update(-60) - -80.0;
+        let second = filter.update(-60) - -80.0 - first;
+        assert!(first > 0.0 && second > 0.0 && second < first);
+
+        for _ in 0..100 {
+            filter.update(-60);
+        }
+        assert_close(filter.value().unwrap(), -60.0, 0.5);
+    }
+
+    #[test]
+    fn filters_smooth_noise() {
+        for smoothing in [Smoothing::EMA, Smoothing::KALMAN] {
+            let mut filter = RssiFilter::new(smoothing);
+            let values: Vec<f64> = noisy().take(200).map(|rssi| filter.update(rssi)).collect();
+
+            // Settles around the mean of the signal, moving much less than it
+            let settled = &values[100..];
+            let spread = settled.iter().cloned().fold(f64::MIN, f64::max) - settled.iter().cloned().fold(f64::MAX, f64::min);
+            assert!(spread < 6.0, "{:?} spread {}", smoothing, spread);
+            for v in settled {
+                assert_close(*v, -70.0, 4.0);
+            }
+        }
+    }
+}
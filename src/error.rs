@@ -0,0 +1,86 @@
+use std::time::Duration;
+
+use thiserror::Error;
+use uuid::Uuid;
+
+use crate::lookup::Target;
+
+pub type Result<T> = std::result::Result<T, BleUtilError>;
+
+/// Everything that can go wrong while talking to a device.
+///
+/// Each variant maps to a distinct process exit code, see [`BleUtilError::exit_code`]:
+///
+/// | code | error                    |
+/// |------|--------------------------|
+/// | 1    | `Backend`                |
+/// | 2    | invalid command line     |
+/// | 3    | `NoAdapter`              |
+/// | 4    | `DeviceNotFound`         |
+/// | 5    | `CharacteristicNotFound` |
+/// | 5    | `DescriptorNotFound`     |
+/// | 6    | `NotReadable`            |
+/// | 7    | `NotWritable`            |
+/// | 8    | `Timeout`                |
+/// | 9    | `Mismatch`               |
+/// | 10   | `NotSubscribable`        |
+#[derive(Debug, Error)]
+pub enum BleUtilError {
+    #[error("no bluetooth adapter found")]
+    NoAdapter,
+
+    #[error("unable to find device matching '{0}'")]
+    DeviceNotFound(Target),
+
+    #[error("device has no characteristic {0}")]
+    CharacteristicNotFound(Uuid),
+
+    #[error("characteristic {0} has no descriptor {1}")]
+    DescriptorNotFound(Uuid, Uuid),
+
+    #[error("characteristic {0} is not readable")]
+    NotReadable(Uuid),
+
+    #[error("characteristic {0} is not writable")]
+    NotWritable(Uuid),
+
+    #[error("characteristic {0} doesn't support notifications or indications")]
+    NotSubscribable(Uuid),
+
+    #[error("timed out after {0:?}")]
+    Timeout(Duration),
+
+    /// A device, GATT database or transferred file differs from the expected one, with the number
+    /// of differences
+    #[error("{0} mismatch(es) found")]
+    Mismatch(usize),
+
+    #[error("bluetooth error: {0}")]
+    Backend(btleplug::Error),
+}
+
+impl BleUtilError {
+    /// Exit code the command line tool returns for this error.
+    pub fn exit_code(&self) -> u8 {
+        match self {
+            BleUtilError::Backend(_) => 1,
+            BleUtilError::NoAdapter => 3,
+            BleUtilError::DeviceNotFound(_) => 4,
+            BleUtilError::CharacteristicNotFound(_) | BleUtilError::DescriptorNotFound(..) => 5,
+            BleUtilError::NotReadable(_) => 6,
+            BleUtilError::NotWritable(_) => 7,
+            BleUtilError::Timeout(_) => 8,
+            BleUtilError::Mismatch(_) => 9,
+            BleUtilError::NotSubscribable(_) => 10,
+        }
+    }
+}
+
+impl From<btleplug::Error> for BleUtilError {
+    fn from(e: btleplug::Error) -> BleUtilError {
+        match e {
+            btleplug::Error::TimedOut(d) => BleUtilError::Timeout(d),
+            e => BleUtilError::Backend(e),
+        }
+    }
+}
@@ -0,0 +1,32 @@
+//! Utilities for scanning for and talking to bluetooth low energy devices.
+//!
+//! Open a [`Session`] to scan or to [`Session::connect`] to a [`Device`], then read and write its
+//! characteristics. The bluetooth stack in use is abstracted away by the [`backend`] module.
+
+pub mod backend;
+pub mod beacon;
+pub mod distance;
+mod error;
+pub mod lookup;
+mod names;
+mod scan;
+pub mod sensor;
+mod session;
+mod uuids;
+pub mod value;
+
+pub use backend::DEFAULT_MTU;
+pub use beacon::Beacon;
+pub use btleplug::api::{BDAddr, CharPropFlags, ValueNotification, WriteType};
+pub use error::{BleUtilError, Result};
+pub use lookup::Target;
+pub use names::Names;
+pub use scan::{DeviceFilter, ScanEvent, ScanEventKind, Scanner};
+pub use sensor::{Decoders, Reading, SensorData};
+pub use session::{
+    adapters, AdapterInfo, AdapterSelector, CharacteristicInfo, Chunking, Device, DeviceInfo,
+    ServiceInfo, Session,
+};
+pub use uuid::Uuid;
+pub use uuids::parse_uuid;
+pub use value::{hex, PresentationFormat, Value, ValueFormat};
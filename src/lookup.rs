@@ -0,0 +1,279 @@
+//! Finding a specific device among the advertising ones.
+
+use std::fmt;
+use std::str::FromStr;
+use std::time::Duration;
+
+use btleplug::api::{BDAddr, PeripheralProperties, ScanFilter};
+use tokio::time::{self, Instant};
+use tokio_stream::StreamExt;
+use uuid::Uuid;
+
+use crate::backend::{Adapter, AdapterEvent, EventStream, Peripheral};
+use crate::error::Result;
+use crate::uuids::parse_uuid;
+
+/// How long to wait for a device to show up before giving up.
+pub const DEFAULT_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Describes which device to look for.
+///
+/// Parsed from a string: `aa:bb:cc:dd:ee:ff` matches an address (case-insensitively), a full UUID
+/// matches an advertised service and anything else is a glob (`*`, `?`) on the local name. The
+/// `addr:`, `name:` and `service:` prefixes force an interpretation, `service:` also accepting
+/// short UUIDs such as `180f`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Target {
+    Address(BDAddr),
+    Name(String),
+    Service(Uuid),
+}
+
+impl Target {
+    pub fn matches(&self, props: &PeripheralProperties) -> bool {
+        match self {
+            Target::Address(addr) => props.address == *addr,
+            Target::Name(pattern) => props.local_name.as_deref()
+                .map(|name| glob_match(pattern, name))
+                .unwrap_or(false),
+            Target::Service(uuid) => props.services.contains(uuid)
+                || props.service_data.contains_key(uuid),
+        }
+    }
+}
+
+impl FromStr for Target {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Target, String> {
+        if let Some(addr) = s.strip_prefix("addr:") {
+            return addr.parse().map(Target::Address).map_err(|e| format!("invalid address '{}': {}", addr, e));
+        }
+
+        if let Some(name) = s.strip_prefix("name:") {
+            return Ok(Target::Name(name.into()));
+        }
+
+        if let Some(uuid) = s.strip_prefix("service:") {
+            return parse_uuid(uuid).map(Target::Service).map_err(|e| format!("invalid service uuid '{}': {}", uuid, e));
+        }
+
+        // Something that looks like an address must be a valid one rather than a name
+        if s.contains(':') && s.chars().all(|c| c == ':' || c.is_ascii_hexdigit()) {
+            return BDAddr::from_str_delim(s).map(Target::Address).map_err(|e| format!("invalid address '{}': {}", s, e));
+        }
+
+        if let Ok(uuid) = Uuid::parse_str(s) {
+            Ok(Target::Service(uuid))
+        } else {
+            Ok(Target::Name(s.into()))
+        }
+    }
+}
+
+impl fmt::Display for Target {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Target::Address(addr) => write!(f, "{}", addr),
+            Target::Name(pattern) => write!(f, "name:{}", pattern),
+            Target::Service(uuid) => write!(f, "service:{}", uuid),
+        }
+    }
+}
+
+/// Scans until a device matching `target` shows up, returning as soon as it does. Returns `None`
+/// if nothing matched within `timeout`.
+pub async fn find(
+    adapter: &dyn Adapter,
+    target: &Target,
+    timeout: Duration,
+) -> Result<Option<Box<dyn Peripheral>>> {
+    // Subscribe before scanning so no advertisement is missed
+    let mut events = adapter.events().await?;
+    // Not filtering on services, as the stack would leave out devices only sending service data
+    adapter.start_scan(ScanFilter::default()).await?;
+
+    let found = find_scanning(adapter, target, timeout, &mut events).await;
+    adapter.stop_scan().await?;
+    found
+}
+
+async fn find_scanning(
+    adapter: &dyn Adapter,
+    target: &Target,
+    timeout: Duration,
+    events: &mut EventStream<AdapterEvent>,
+) -> Result<Option<Box<dyn Peripheral>>> {
+    // The stack may already know about the device from an earlier scan
+    for p in adapter.peripherals().await? {
+        if check(p.as_ref(), target).await? {
+            return Ok(Some(p));
+        }
+    }
+
+    let deadline = Instant::now() + timeout;
+    loop {
+        let addr = match time::timeout_at(deadline, events.next()).await {
+            Ok(Some(AdapterEvent::DeviceDiscovered(addr) | AdapterEvent::DeviceUpdated(addr))) => addr,
+            Ok(Some(_)) => continue,
+            Ok(None) | Err(_) => return Ok(None),
+        };
+
+        if let Target::Address(wanted) = target {
+            if addr != *wanted {
+                continue;
+            }
+        }
+
+        for p in adapter.peripherals().await? {
+            if p.address() == addr && check(p.as_ref(), target).await? {
+                return Ok(Some(p));
+            }
+        }
+    }
+}
+
+async fn check(p: &dyn Peripheral, target: &Target) -> btleplug::Result<bool> {
+    Ok(p.properties().await?.map(|props| target.matches(&props)).unwrap_or(false))
+}
+
+/// Matches `text` against a glob where `*` matches any run of characters and `?` any single one.
+fn glob_match(pattern: &str, text: &str) -> bool {
+    let pattern: Vec<char> = pattern.chars().collect();
+    let text: Vec<char> = text.chars().collect();
+
+    let (mut p, mut t) = (0, 0);
+    // Position of the last `*` seen and the text position it was tried at
+    let mut backtrack = None;
+
+    while t < text.len() {
+        if p < pattern.len() && (pattern[p] == '?' || pattern[p] == text[t]) {
+            p += 1;
+            t += 1;
+        } else if p < pattern.len() && pattern[p] == '*' {
+            backtrack = Some((p, t));
+            p += 1;
+        } else if let Some((star, matched)) = backtrack {
+            p = star + 1;
+            t = matched + 1;
+            backtrack = Some((star, matched + 1));
+        } else {
+            return false;
+        }
+    }
+
+    pattern[p..].iter().all(|&c| c == '*')
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backend::{MockAdapter, MockPeripheral};
+
+    #[test]
+    fn glob_matches_literals() {
+        assert!(glob_match("sensor", "sensor"));
+        assert!(!glob_match("sensor", "sensors"));
+        assert!(!glob_match("sensor", "senso"));
+        assert!(glob_match("", ""));
+        assert!(!glob_match("", "a"));
+    }
+
+    #[test]
+    fn glob_matches_wildcards() {
+        assert!(glob_match("*", ""));
+        assert!(glob_match("*", "anything"));
+        assert!(glob_match("ble-*", "ble-util mock"));
+        assert!(glob_match("*mock", "ble-util mock"));
+        assert!(glob_match("*util*", "ble-util mock"));
+        assert!(glob_match("b?e*", "ble-util"));
+        assert!(!glob_match("?", ""));
+        assert!(!glob_match("b?e", "bee-"));
+        // Needs backtracking over the first candidate
+        assert!(glob_match("*ab", "aab"));
+        assert!(glob_match("a*b*c", "abxbyc"));
+        assert!(!glob_match("a*b*c", "abxbyd"));
+    }
+
+    #[test]
+    fn glob_is_case_sensitive() {
+        assert!(!glob_match("Sensor", "sensor"));
+        assert!(!glob_match("S*", "sensor"));
+    }
+
+    #[test]
+    fn glob_handles_multibyte_characters() {
+        assert!(glob_match("th?rmo", "thérmo"));
+        assert!(glob_match("*°C", "21 °C"));
+    }
+
+    #[test]
+    fn parses_addresses() {
+        let addr = BDAddr::from([0xaa, 0xbb, 0xcc, 0xdd, 0xee, 0x01]);
+        assert_eq!("aa:bb:cc:dd:ee:01".parse(), Ok(Target::Address(addr)));
+        assert_eq!("AA:BB:CC:DD:EE:01".parse(), Ok(Target::Address(addr)));
+        assert_eq!("addr:aa:bb:cc:dd:ee:01".parse(), Ok(Target::Address(addr)));
+
+        // Looks like an address, so must be a valid one
+        assert!("aa:bb:cc".parse::<Target>().is_err());
+        assert!("addr:sensor".parse::<Target>().is_err());
+    }
+
+    #[test]
+    fn parses_services() {
+        let nus = Uuid::from_u128(0x6e400001_b5a3_f393_e0a9_e50e24dcca9e);
+        assert_eq!("6e400001-b5a3-f393-e0a9-e50e24dcca9e".parse(), Ok(Target::Service(nus)));
+        assert_eq!(
+            "service:180f".parse(),
+            Ok(Target::Service(Uuid::from_u128(0x0000180f_0000_1000_8000_00805f9b34fb)))
+        );
+        assert!("service:nope".parse::<Target>().is_err());
+
+        // A short UUID without the prefix is a name
+        assert_eq!("180f".parse(), Ok(Target::Name("180f".into())));
+    }
+
+    #[test]
+    fn parses_names() {
+        assert_eq!("ble-util*".parse(), Ok(Target::Name("ble-util*".into())));
+        assert_eq!("name:aa:bb:cc:dd:ee:01".parse(), Ok(Target::Name("aa:bb:cc:dd:ee:01".into())));
+        // Not only hex digits and colons
+        assert_eq!("Sensor: 1".parse(), Ok(Target::Name("Sensor: 1".into())));
+    }
+
+    #[test]
+    fn displays_parseable_targets() {
+        for s in ["AA:BB:CC:DD:EE:01", "name:ble-util*", "service:6e400001-b5a3-f393-e0a9-e50e24dcca9e"] {
+            let target: Target = s.parse().unwrap();
+            assert_eq!(target.to_string(), s);
+            assert_eq!(target.to_string().parse(), Ok(target));
+        }
+    }
+
+    #[test]
+    fn matches_properties() {
+        let props = PeripheralProperties {
+            address: BDAddr::from([0xaa, 0xbb, 0xcc, 0xdd, 0xee, 0x01]),
+            local_name: Some("ble-util mock".into()),
+            services: vec![Uuid::from_u128(1)],
+            ..Default::default()
+        };
+
+        assert!(Target::Address(props.address).matches(&props));
+        assert!(Target::Name("ble-util*".into()).matches(&props));
+        assert!(!Target::Name("mock".into()).matches(&props));
+        assert!(Target::Service(Uuid::from_u128(1)).matches(&props));
+        assert!(!Target::Service(Uuid::from_u128(2)).matches(&props));
+    }
+
+    #[tokio::test]
+    async fn finds_services_only_sent_as_service_data() {
+        let service = Uuid::from_u128(0x0000feaa_0000_1000_8000_00805f9b34fb);
+        let peripheral = MockPeripheral::new([0xaa, 0xbb, 0xcc, 0xdd, 0xee, 0x60].into())
+            .service_data(service, &[0x10, 0x00]);
+        let adapter = MockAdapter::new("mock").with_peripheral(peripheral);
+
+        let found = find(&adapter, &Target::Service(service), Duration::from_secs(2)).await.unwrap();
+        assert_eq!(found.map(|p| p.address()), Some([0xaa, 0xbb, 0xcc, 0xdd, 0xee, 0x60].into()));
+    }
+}
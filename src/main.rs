@@ -1,222 +1,70 @@
-use std::env::args;
-use std::time::Duration;
-use std::io::stdin;
-
-use btleplug::{platform::Manager, api::{Manager as _, ScanFilter, Central, Peripheral, WriteType}};
-use tokio::time;
 use std::error::Error;
+use std::process::ExitCode;
 
-static HELP_MSG: &'static str = r###"ble-util v0.1
-Devin Vander Stelt <devin@vstelt.dev>
-
-Usage:
-    ble-util <command> <args>
+use ble_util::backend::{Backend, BtleplugBackend, MockBackend};
+use ble_util::{BleUtilError, Names, Session};
+use clap::Parser;
 
-Commands:
-    scan                scan for and print nearby devices
-    ping <addr>         connect to device and print its services and characteristics
-    read <addr> <char>  connect to the device and read the value of the characteristic
-    write <addr>        connect to the device and write a value to the characteristic via stdin
-    help                print this help message
-"###;
+mod cli;
 
-static CHAR_WRITE: &'static str = "6e400002-b5a3-f393-e0a9-e50e24dcca9e";
-static CHAR_READ: &'static str = "6e400003-b5a3-f393-e0a9-e50e24dcca9e";
+use cli::output::Output;
+use cli::{adapters, dashboard, diff, gatt, nus, scan, transfer, verify, BackendKind, Cli, Command};
 
 #[tokio::main]
-async fn main() -> Result<(), Box<dyn Error>> {
-    let args: Vec<String> = args().collect();
-
-    if args.get(1).is_none() {
-        eprintln!("No command specified\n");
-        help();
-        return Ok(());
-    }
-
-    match args[1].as_str() {
-        "scan" => scan_devices().await?,
-        "ping" => {
-            if args.get(2).is_none() {
-                eprintln!("No address specified\n");
-                help();
-                return Ok(());
-            }
-
-            ping(&args[2]).await?;
-        }
-        "read" => {
-            if args.get(2).is_none() {
-                eprintln!("No address specified\n");
-                help();
-                return Ok(());
+async fn main() -> ExitCode {
+    let cli = Cli::parse();
+
+    match run(cli).await {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            eprintln!("error: {}", e);
+            match e.downcast_ref::<BleUtilError>() {
+                Some(e) => ExitCode::from(e.exit_code()),
+                None => ExitCode::FAILURE,
             }
-
-            if args.get(3).is_none() {
-                eprintln!("No characteristic uuid specified\n");
-                help();
-                return Ok(());
-            }
-
-            read(&args[2], &args[3]).await?;
-        },
-        "write" => {
-            if args.get(2).is_none() {
-                eprintln!("No address specified\n");
-                help();
-                return Ok(());
-            }
-
-            write(&args[2]).await?;
-        },
-        "help" => help(),
-        _ => {
-            eprintln!("Unrecognized command '{}'\n", args[1]);
-            help();
-        }
-    }
-
-    Ok(())
-}
-
-async fn scan_devices() -> Result<(), Box<dyn Error>> {
-    let manager = Manager::new().await?;
-    let adapters = manager.adapters().await?;
-    let central = adapters.into_iter().nth(0).unwrap();
-
-    central.start_scan(ScanFilter::default()).await?;
-    time::sleep(Duration::from_secs(3)).await;
-
-    for peripheral in central.peripherals().await?.iter() {
-        let props = peripheral.properties().await?.unwrap();
-        println!("{}: {}", props.address, props.local_name.unwrap_or("Unknown".into()));
-    }
-
-    Ok(())
-}
-
-async fn ping(addr: &str) -> Result<(), Box<dyn Error>> {
-    let manager = Manager::new().await?;
-    let adapters = manager.adapters().await?;
-    let central = adapters.into_iter().nth(0).unwrap();
-
-    central.start_scan(ScanFilter::default()).await?;
-    time::sleep(Duration::from_secs(3)).await;
-
-    let mut dev = None;
-    for p in central.peripherals().await? {
-        if p.properties().await?.unwrap().address.to_string().eq(addr) {
-            dev = Some(p);
-        }
-    }
-
-    if dev.is_none() {
-        eprintln!("Unable to find device");
-        return Ok(());
-    }
-
-    let dev = dev.unwrap();
-    dev.connect().await?;
-    println!("Connected");
-    dev.discover_services().await?;
-
-    // Print out the device servers and characteristics
-    println!("Services:");
-    for s in dev.services() {
-        println!("{}:", s.uuid);
-
-        for c in s.characteristics.iter() {
-            println!("\t{}: {:?}", c.uuid, c.properties);
         }
     }
-
-    Ok(())
 }
 
-async fn read(addr: &str, char_id: &str) -> Result<(), Box<dyn Error>> {
-    let manager = Manager::new().await?;
-    let adapters = manager.adapters().await?;
-    let central = adapters.into_iter().nth(0).unwrap();
-
-    central.start_scan(ScanFilter::default()).await?;
-    time::sleep(Duration::from_secs(3)).await;
-
-    let mut dev = None;
-    for p in central.peripherals().await? {
-        if p.properties().await?.unwrap().address.to_string().eq(addr) {
-            dev = Some(p);
-        }
+async fn run(cli: Cli) -> Result<(), Box<dyn Error>> {
+    let backend: Box<dyn Backend> = match cli.backend {
+        BackendKind::Mock => Box::new(MockBackend::demo()),
+        BackendKind::Btleplug => Box::new(BtleplugBackend::new().await?),
+    };
+
+    let out = Output::new(cli.format);
+    let names = match &cli.names {
+        Some(path) => Names::load(path)?,
+        None => Names::new(),
+    };
+
+    // Every other command works on a single adapter
+    if let Command::Adapters = cli.command {
+        return adapters::list_adapters(backend.as_ref(), out).await;
     }
 
-    if dev.is_none() {
-        eprintln!("Unable to find device");
-        return Ok(());
+    // Only needs an adapter when comparing against a device
+    if let Command::GattDiff(args) = &cli.command {
+        return diff::gatt_diff(backend, &cli.adapter, args, &names, out).await;
     }
 
-    let dev = dev.unwrap();
-    dev.connect().await?;
-
-    println!("Connected");
-    dev.discover_services().await?;
-
-    let chars = dev.characteristics();
-    let ch = chars.iter()
-        .find(|a| a.uuid.to_string().eq(char_id))
-        .unwrap();
-
-    let res = dev.read(ch).await?;
-    println!("{:?}", res);
-    Ok(())
-}
-
-async fn write(addr: &str) -> Result<(), Box<dyn Error>> {
-    let manager = Manager::new().await?;
-    let adapters = manager.adapters().await?;
-    let central = adapters.into_iter().nth(0).unwrap();
-
-    central.start_scan(ScanFilter::default()).await?;
-    time::sleep(Duration::from_secs(2)).await;
+    let session = Session::with_adapter(backend, &cli.adapter).await?;
 
-    let mut dev = None;
-    for p in central.peripherals().await? {
-        if p.properties().await?.unwrap().address.to_string().eq(addr) {
-            dev = Some(p);
+    match cli.command {
+        Command::Adapters | Command::GattDiff(_) => unreachable!("handled above"),
+        Command::Scan(args) => scan::scan_devices(&session, &args, &names, out).await?,
+        Command::Beacons(args) => scan::list_beacons(&session, &args, out).await?,
+        Command::Ping(args) => gatt::ping(&session, &args, &names, out).await?,
+        Command::Read { device, characteristic, value_format } => {
+            gatt::read(&session, &device, characteristic, value_format.as_ref(), out).await?
         }
+        Command::Verify(args) => verify::verify(&session, &args, &names, out).await?,
+        Command::Subscribe(args) => gatt::subscribe(&session, &args, out).await?,
+        Command::Write(args) => gatt::write(&session, &args, out).await?,
+        Command::Nus(args) => nus::nus(&session, &args, out).await?,
+        Command::SendFile(args) => transfer::send_file(&session, &args, out).await?,
+        Command::Dashboard(args) => dashboard::dashboard(&session, &args, &names, out).await?,
     }
 
-    if dev.is_none() {
-        eprintln!("Unable to find device");
-        return Ok(());
-    }
-
-    let dev = dev.unwrap();
-
-    dev.connect().await?;
-
-    println!("Connected");
-    dev.discover_services().await?;
-
-    let chars = dev.characteristics();
-    let ch_write = chars.iter()
-        .find(|a| a.uuid.to_string().eq(CHAR_WRITE))
-        .unwrap();
-
-    let ch_read = chars.iter()
-        .find(|a| a.uuid.to_string().eq(CHAR_READ))
-        .unwrap();
-
-    let mut buf = String::new();
-    while let Ok(_) = stdin().read_line(&mut buf) {
-        let res = dev.write(ch_write, buf.trim().as_bytes(), WriteType::WithoutResponse).await?;
-        println!("{:?}", res);
-
-        let res = dev.read(ch_read).await?;
-        println!("{:?}", res);
-    }
-
-
     Ok(())
 }
-
-fn help() {
-    eprintln!("{}", HELP_MSG);
-}
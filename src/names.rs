@@ -0,0 +1,474 @@
+//! Human readable names for Bluetooth SIG assigned numbers and vendor UUIDs.
+//!
+//! The embedded tables cover the common entries of the assigned numbers documents: GATT services,
+//! characteristics and descriptors, company identifiers and appearance values. Names for other
+//! UUIDs, typically vendor specific services, can be loaded from a file.
+
+use std::collections::HashMap;
+use std::fs;
+use std::io;
+use std::path::Path;
+
+use btleplug::api::bleuuid::BleUuid;
+use uuid::Uuid;
+
+use crate::uuids::parse_uuid;
+
+/// Names for UUIDs, company identifiers and appearance values.
+#[derive(Debug, Clone, Default)]
+pub struct Names {
+    custom: HashMap<Uuid, String>,
+}
+
+impl Names {
+    /// The embedded names only.
+    pub fn new() -> Names {
+        Names::default()
+    }
+
+    /// The embedded names, plus or overridden by those listed in a file.
+    ///
+    /// Each line of the file holds a UUID, full or short, and its name separated by whitespace,
+    /// e.g. `6e400001-b5a3-f393-e0a9-e50e24dcca9e Nordic UART Service`. Empty lines and lines
+    /// starting with `#` are ignored.
+    pub fn load(path: &Path) -> io::Result<Names> {
+        let mut names = Names::new();
+
+        for (i, line) in fs::read_to_string(path)?.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            let invalid = |msg: String| {
+                io::Error::new(io::ErrorKind::InvalidData, format!("{}:{}: {}", path.display(), i + 1, msg))
+            };
+
+            let (uuid, name) = line.split_once(char::is_whitespace)
+                .ok_or_else(|| invalid(format!("expected a UUID and a name, got '{}'", line)))?;
+            let uuid = parse_uuid(uuid).map_err(|e| invalid(format!("invalid uuid '{}': {}", uuid, e)))?;
+            names.custom.insert(uuid, name.trim().into());
+        }
+
+        Ok(names)
+    }
+
+    /// Name of a service, characteristic or descriptor.
+    pub fn uuid(&self, uuid: Uuid) -> Option<&str> {
+        if let Some(name) = self.custom.get(&uuid) {
+            return Some(name);
+        }
+
+        if let Some((_, name)) = VENDOR_UUIDS.iter().find(|(u, _)| *u == uuid.as_u128()) {
+            return Some(name);
+        }
+
+        let short = uuid.to_ble_u16()?;
+        [SERVICES, MEMBER_SERVICES, CHARACTERISTICS, DESCRIPTORS]
+            .into_iter()
+            .find_map(|table| lookup(table, short))
+    }
+
+    /// Name of the company owning a manufacturer data identifier.
+    pub fn company(&self, id: u16) -> Option<&'static str> {
+        lookup(COMPANIES, id)
+    }
+
+    /// Name of an appearance value, falling back to the name of its category for subcategories
+    /// that aren't known.
+    pub fn appearance(&self, value: u16) -> Option<&'static str> {
+        lookup(APPEARANCES, value).or_else(|| lookup(APPEARANCES, value & !0x3f))
+    }
+}
+
+fn lookup(table: &[(u16, &'static str)], id: u16) -> Option<&'static str> {
+    table.iter().find(|(i, _)| *i == id).map(|(_, name)| *name)
+}
+
+const SERVICES: &[(u16, &str)] = &[
+    (0x1800, "Generic Access"),
+    (0x1801, "Generic Attribute"),
+    (0x1802, "Immediate Alert"),
+    (0x1803, "Link Loss"),
+    (0x1804, "Tx Power"),
+    (0x1805, "Current Time"),
+    (0x1806, "Reference Time Update"),
+    (0x1807, "Next DST Change"),
+    (0x1808, "Glucose"),
+    (0x1809, "Health Thermometer"),
+    (0x180a, "Device Information"),
+    (0x180d, "Heart Rate"),
+    (0x180e, "Phone Alert Status"),
+    (0x180f, "Battery Service"),
+    (0x1810, "Blood Pressure"),
+    (0x1811, "Alert Notification"),
+    (0x1812, "Human Interface Device"),
+    (0x1813, "Scan Parameters"),
+    (0x1814, "Running Speed and Cadence"),
+    (0x1815, "Automation IO"),
+    (0x1816, "Cycling Speed and Cadence"),
+    (0x1818, "Cycling Power"),
+    (0x1819, "Location and Navigation"),
+    (0x181a, "Environmental Sensing"),
+    (0x181b, "Body Composition"),
+    (0x181c, "User Data"),
+    (0x181d, "Weight Scale"),
+    (0x181e, "Bond Management"),
+    (0x181f, "Continuous Glucose Monitoring"),
+    (0x1820, "Internet Protocol Support"),
+    (0x1821, "Indoor Positioning"),
+    (0x1822, "Pulse Oximeter"),
+    (0x1823, "HTTP Proxy"),
+    (0x1824, "Transport Discovery"),
+    (0x1825, "Object Transfer"),
+    (0x1826, "Fitness Machine"),
+    (0x1827, "Mesh Provisioning"),
+    (0x1828, "Mesh Proxy"),
+    (0x1829, "Reconnection Configuration"),
+    (0x183a, "Insulin Delivery"),
+    (0x183b, "Binary Sensor"),
+    (0x183c, "Emergency Configuration"),
+    (0x183d, "Authorization Control"),
+    (0x183e, "Physical Activity Monitor"),
+    (0x183f, "Elapsed Time"),
+    (0x1840, "Generic Health Sensor"),
+    (0x1843, "Audio Input Control"),
+    (0x1844, "Volume Control"),
+    (0x1845, "Volume Offset Control"),
+    (0x1846, "Coordinated Set Identification"),
+    (0x1847, "Device Time"),
+    (0x1848, "Media Control"),
+    (0x1849, "Generic Media Control"),
+    (0x184a, "Constant Tone Extension"),
+    (0x184b, "Telephone Bearer"),
+    (0x184c, "Generic Telephone Bearer"),
+    (0x184d, "Microphone Control"),
+    (0x184e, "Audio Stream Control"),
+    (0x184f, "Broadcast Audio Scan"),
+    (0x1850, "Published Audio Capabilities"),
+    (0x1851, "Basic Audio Announcement"),
+    (0x1852, "Broadcast Audio Announcement"),
+    (0x1853, "Common Audio"),
+    (0x1854, "Hearing Access"),
+    (0x1855, "Telephony and Media Audio"),
+    (0x1856, "Public Broadcast Announcement"),
+    (0x1857, "Electronic Shelf Label"),
+    (0x1858, "Gaming Audio"),
+    (0x1859, "Mesh Proxy Solicitation"),
+];
+
+/// 16-bit service UUIDs allocated to SIG members
+const MEMBER_SERVICES: &[(u16, &str)] = &[
+    (0xfcd2, "BTHome"),
+    (0xfd6f, "Exposure Notification"),
+    (0xfe2c, "Google Fast Pair"),
+    (0xfe59, "Nordic Secure DFU"),
+    (0xfe95, "Xiaomi"),
+    (0xfe9f, "Google"),
+    (0xfeaa, "Eddystone"),
+    (0xfebe, "Bose"),
+    (0xfeec, "Tile"),
+    (0xfeed, "Tile"),
+];
+
+const CHARACTERISTICS: &[(u16, &str)] = &[
+    (0x2a00, "Device Name"),
+    (0x2a01, "Appearance"),
+    (0x2a02, "Peripheral Privacy Flag"),
+    (0x2a03, "Reconnection Address"),
+    (0x2a04, "Peripheral Preferred Connection Parameters"),
+    (0x2a05, "Service Changed"),
+    (0x2a06, "Alert Level"),
+    (0x2a07, "Tx Power Level"),
+    (0x2a08, "Date Time"),
+    (0x2a09, "Day of Week"),
+    (0x2a0a, "Day Date Time"),
+    (0x2a0c, "Exact Time 256"),
+    (0x2a0d, "DST Offset"),
+    (0x2a0e, "Time Zone"),
+    (0x2a0f, "Local Time Information"),
+    (0x2a11, "Time with DST"),
+    (0x2a12, "Time Accuracy"),
+    (0x2a13, "Time Source"),
+    (0x2a14, "Reference Time Information"),
+    (0x2a16, "Time Update Control Point"),
+    (0x2a17, "Time Update State"),
+    (0x2a18, "Glucose Measurement"),
+    (0x2a19, "Battery Level"),
+    (0x2a1c, "Temperature Measurement"),
+    (0x2a1d, "Temperature Type"),
+    (0x2a1e, "Intermediate Temperature"),
+    (0x2a21, "Measurement Interval"),
+    (0x2a22, "Boot Keyboard Input Report"),
+    (0x2a23, "System ID"),
+    (0x2a24, "Model Number String"),
+    (0x2a25, "Serial Number String"),
+    (0x2a26, "Firmware Revision String"),
+    (0x2a27, "Hardware Revision String"),
+    (0x2a28, "Software Revision String"),
+    (0x2a29, "Manufacturer Name String"),
+    (0x2a2a, "IEEE 11073-20601 Regulatory Certification Data List"),
+    (0x2a2b, "Current Time"),
+    (0x2a31, "Scan Refresh"),
+    (0x2a32, "Boot Keyboard Output Report"),
+    (0x2a33, "Boot Mouse Input Report"),
+    (0x2a34, "Glucose Measurement Context"),
+    (0x2a35, "Blood Pressure Measurement"),
+    (0x2a36, "Intermediate Cuff Pressure"),
+    (0x2a37, "Heart Rate Measurement"),
+    (0x2a38, "Body Sensor Location"),
+    (0x2a39, "Heart Rate Control Point"),
+    (0x2a3f, "Alert Status"),
+    (0x2a40, "Ringer Control Point"),
+    (0x2a41, "Ringer Setting"),
+    (0x2a42, "Alert Category ID Bit Mask"),
+    (0x2a43, "Alert Category ID"),
+    (0x2a44, "Alert Notification Control Point"),
+    (0x2a45, "Unread Alert Status"),
+    (0x2a46, "New Alert"),
+    (0x2a47, "Supported New Alert Category"),
+    (0x2a48, "Supported Unread Alert Category"),
+    (0x2a49, "Blood Pressure Feature"),
+    (0x2a4a, "HID Information"),
+    (0x2a4b, "Report Map"),
+    (0x2a4c, "HID Control Point"),
+    (0x2a4d, "Report"),
+    (0x2a4e, "Protocol Mode"),
+    (0x2a4f, "Scan Interval Window"),
+    (0x2a50, "PnP ID"),
+    (0x2a51, "Glucose Feature"),
+    (0x2a52, "Record Access Control Point"),
+    (0x2a53, "RSC Measurement"),
+    (0x2a54, "RSC Feature"),
+    (0x2a55, "SC Control Point"),
+    (0x2a5a, "Aggregate"),
+    (0x2a5b, "CSC Measurement"),
+    (0x2a5c, "CSC Feature"),
+    (0x2a5d, "Sensor Location"),
+    (0x2a5e, "PLX Spot-Check Measurement"),
+    (0x2a5f, "PLX Continuous Measurement"),
+    (0x2a60, "PLX Features"),
+    (0x2a63, "Cycling Power Measurement"),
+    (0x2a64, "Cycling Power Vector"),
+    (0x2a65, "Cycling Power Feature"),
+    (0x2a66, "Cycling Power Control Point"),
+    (0x2a67, "Location and Speed"),
+    (0x2a68, "Navigation"),
+    (0x2a6c, "Elevation"),
+    (0x2a6d, "Pressure"),
+    (0x2a6e, "Temperature"),
+    (0x2a6f, "Humidity"),
+    (0x2a70, "True Wind Speed"),
+    (0x2a71, "True Wind Direction"),
+    (0x2a72, "Apparent Wind Speed"),
+    (0x2a73, "Apparent Wind Direction"),
+    (0x2a74, "Gust Factor"),
+    (0x2a75, "Pollen Concentration"),
+    (0x2a76, "UV Index"),
+    (0x2a77, "Irradiance"),
+    (0x2a78, "Rainfall"),
+    (0x2a79, "Wind Chill"),
+    (0x2a7a, "Heat Index"),
+    (0x2a7b, "Dew Point"),
+    (0x2a7d, "Descriptor Value Changed"),
+    (0x2a9b, "Body Composition Feature"),
+    (0x2a9c, "Body Composition Measurement"),
+    (0x2a9d, "Weight Measurement"),
+    (0x2a9e, "Weight Scale Feature"),
+    (0x2aa6, "Central Address Resolution"),
+    (0x2aa7, "CGM Measurement"),
+    (0x2ac9, "Resolvable Private Address Only"),
+    (0x2acc, "Fitness Machine Feature"),
+    (0x2acd, "Treadmill Data"),
+    (0x2ad2, "Indoor Bike Data"),
+    (0x2ad9, "Fitness Machine Control Point"),
+    (0x2b29, "Client Supported Features"),
+    (0x2b2a, "Database Hash"),
+    (0x2b3a, "Server Supported Features"),
+];
+
+const DESCRIPTORS: &[(u16, &str)] = &[
+    (0x2900, "Characteristic Extended Properties"),
+    (0x2901, "Characteristic User Description"),
+    (0x2902, "Client Characteristic Configuration"),
+    (0x2903, "Server Characteristic Configuration"),
+    (0x2904, "Characteristic Presentation Format"),
+    (0x2905, "Characteristic Aggregate Format"),
+    (0x2906, "Valid Range"),
+    (0x2907, "External Report Reference"),
+    (0x2908, "Report Reference"),
+    (0x2909, "Number of Digitals"),
+    (0x290a, "Value Trigger Setting"),
+    (0x290b, "Environmental Sensing Configuration"),
+    (0x290c, "Environmental Sensing Measurement"),
+    (0x290d, "Environmental Sensing Trigger Setting"),
+    (0x290e, "Time Trigger Setting"),
+    (0x290f, "Complete BR-EDR Transport Block Data"),
+];
+
+/// Well known vendor specific 128-bit UUIDs
+const VENDOR_UUIDS: &[(u128, &str)] = &[
+    (0x6e400001_b5a3_f393_e0a9_e50e24dcca9e, "Nordic UART Service"),
+    (0x6e400002_b5a3_f393_e0a9_e50e24dcca9e, "Nordic UART RX"),
+    (0x6e400003_b5a3_f393_e0a9_e50e24dcca9e, "Nordic UART TX"),
+    (0x8ec90001_f315_4f60_9fb8_838830daea50, "Nordic Buttonless DFU"),
+];
+
+const COMPANIES: &[(u16, &str)] = &[
+    (0x0000, "Ericsson AB"),
+    (0x0001, "Nokia Mobile Phones"),
+    (0x0002, "Intel Corp."),
+    (0x0003, "IBM Corp."),
+    (0x0004, "Toshiba Corp."),
+    (0x0006, "Microsoft"),
+    (0x0008, "Motorola"),
+    (0x0009, "Infineon Technologies AG"),
+    (0x000a, "Qualcomm Technologies International, Ltd."),
+    (0x000d, "Texas Instruments Inc."),
+    (0x000f, "Broadcom Corporation"),
+    (0x0013, "Atmel Corporation"),
+    (0x001d, "Qualcomm"),
+    (0x0025, "NXP Semiconductors"),
+    (0x0030, "STMicroelectronics"),
+    (0x0036, "Renesas Electronics Corporation"),
+    (0x003f, "Bluetooth SIG, Inc"),
+    (0x0045, "Atheros Communications, Inc."),
+    (0x0046, "MediaTek, Inc."),
+    (0x0047, "Bluegiga"),
+    (0x0048, "Marvell Technology Group Ltd."),
+    (0x004c, "Apple, Inc."),
+    (0x0055, "Plantronics, Inc."),
+    (0x0056, "Sony Ericsson Mobile Communications"),
+    (0x0057, "Harman International Industries, Inc."),
+    (0x0059, "Nordic Semiconductor ASA"),
+    (0x005d, "Realtek Semiconductor Corporation"),
+    (0x0060, "RivieraWaves S.A.S"),
+    (0x0065, "HP, Inc."),
+    (0x0067, "GN Audio A/S"),
+    (0x006b, "Polar Electro OY"),
+    (0x0075, "Samsung Electronics Co. Ltd."),
+    (0x0077, "Laird Connectivity LLC"),
+    (0x0078, "Nike, Inc."),
+    (0x0082, "DSEA A/S"),
+    (0x0087, "Garmin International, Inc."),
+    (0x008a, "Jawbone"),
+    (0x009e, "Bose Corporation"),
+    (0x009f, "Suunto Oy"),
+    (0x00b8, "Qualcomm Innovation Center, Inc. (QuIC)"),
+    (0x00c4, "LG Electronics"),
+    (0x00cc, "Beats Electronics"),
+    (0x00cd, "Microchip Technology Inc."),
+    (0x00d7, "Qualcomm Technologies, Inc."),
+    (0x00df, "Misfit Wearables Corp"),
+    (0x00e0, "Google"),
+    (0x0118, "Radius Networks, Inc."),
+    (0x012d, "Sony Corporation"),
+    (0x0131, "Cypress Semiconductor"),
+    (0x0157, "Anhui Huami Information Technology Co., Ltd."),
+    (0x0171, "Amazon.com Services LLC"),
+    (0x02e5, "Espressif Systems (Shanghai) Co., Ltd."),
+    (0x02ff, "Silicon Laboratories"),
+    (0x038f, "Xiaomi Inc."),
+    (0x0499, "Ruuvi Innovations Ltd."),
+    (0x0822, "Adafruit Industries"),
+    (0xffff, "Reserved for internal use"),
+];
+
+const APPEARANCES: &[(u16, &str)] = &[
+    (0, "Unknown"),
+    (64, "Phone"),
+    (128, "Computer"),
+    (192, "Watch"),
+    (193, "Sports Watch"),
+    (256, "Clock"),
+    (320, "Display"),
+    (384, "Remote Control"),
+    (448, "Eye-glasses"),
+    (512, "Tag"),
+    (576, "Keyring"),
+    (640, "Media Player"),
+    (704, "Barcode Scanner"),
+    (768, "Thermometer"),
+    (769, "Ear Thermometer"),
+    (832, "Heart Rate Sensor"),
+    (833, "Heart Rate Belt"),
+    (896, "Blood Pressure"),
+    (897, "Arm Blood Pressure"),
+    (898, "Wrist Blood Pressure"),
+    (960, "Human Interface Device"),
+    (961, "Keyboard"),
+    (962, "Mouse"),
+    (963, "Joystick"),
+    (964, "Gamepad"),
+    (965, "Digitizer Tablet"),
+    (966, "Card Reader"),
+    (967, "Digital Pen"),
+    (968, "Barcode Scanner"),
+    (1024, "Glucose Meter"),
+    (1088, "Running Walking Sensor"),
+    (1152, "Cycling"),
+    (1153, "Cycling Computer"),
+    (1154, "Speed Sensor"),
+    (1155, "Cadence Sensor"),
+    (1156, "Power Sensor"),
+    (1157, "Speed and Cadence Sensor"),
+    (1216, "Control Device"),
+    (1280, "Network Device"),
+    (1344, "Sensor"),
+    (1408, "Light Fixtures"),
+    (1472, "Fan"),
+    (1536, "HVAC"),
+    (1600, "Air Conditioning"),
+    (1664, "Humidifier"),
+    (1728, "Heating"),
+    (1792, "Access Control"),
+    (1856, "Motorized Device"),
+    (1920, "Power Device"),
+    (1984, "Light Source"),
+    (2048, "Window Covering"),
+    (2112, "Audio Sink"),
+    (2176, "Audio Source"),
+    (2240, "Motorized Vehicle"),
+    (2304, "Domestic Appliance"),
+    (2368, "Wearable Audio Device"),
+    (2432, "Aircraft"),
+    (2496, "AV Equipment"),
+    (2560, "Display Equipment"),
+    (2624, "Hearing aid"),
+    (2688, "Gaming"),
+    (2752, "Signage"),
+    (3136, "Pulse Oximeter"),
+    (3200, "Weight Scale"),
+    (3264, "Personal Mobility Device"),
+    (3328, "Continuous Glucose Monitor"),
+    (3392, "Insulin Pump"),
+    (3456, "Medication Delivery"),
+    (3520, "Spirometer"),
+    (5184, "Outdoor Sports Activity"),
+];
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use btleplug::api::bleuuid::uuid_from_u16;
+
+    #[test]
+    fn names_assigned_numbers() {
+        let names = Names::new();
+        assert_eq!(names.uuid(uuid_from_u16(0x180f)), Some("Battery Service"));
+        assert_eq!(names.uuid(uuid_from_u16(0x2a19)), Some("Battery Level"));
+        assert_eq!(names.company(0x0059), Some("Nordic Semiconductor ASA"));
+        assert_eq!(names.uuid(Uuid::nil()), None);
+    }
+
+    #[test]
+    fn names_appearances_by_category() {
+        let names = Names::new();
+        assert_eq!(names.appearance(768), Some("Thermometer"));
+        assert_eq!(names.appearance(769), Some("Ear Thermometer"));
+        // An unknown subcategory of a known category
+        assert_eq!(names.appearance(770), Some("Thermometer"));
+        assert_eq!(names.appearance(0xffc0), None);
+    }
+}
@@ -0,0 +1,121 @@
+//! Scan filtering and continuous scanning, reporting devices as they advertise.
+
+use std::time::SystemTime;
+
+use btleplug::api::{PeripheralProperties, ScanFilter};
+use regex::Regex;
+use tokio_stream::StreamExt;
+use uuid::Uuid;
+
+use crate::backend::{Adapter, AdapterEvent, EventStream};
+use crate::error::Result;
+use crate::session::DeviceInfo;
+
+/// Restricts which devices a scan reports. Empty criteria match everything.
+#[derive(Debug, Clone, Default)]
+pub struct DeviceFilter {
+    /// Weakest signal to report, in dBm
+    pub min_rssi: Option<i16>,
+    /// Pattern the local name has to match
+    pub name: Option<Regex>,
+    /// The device has to advertise at least one of these services
+    pub services: Vec<Uuid>,
+    /// The device has to send manufacturer data for at least one of these company ids
+    pub manufacturers: Vec<u16>,
+}
+
+impl DeviceFilter {
+    pub fn matches(&self, props: &PeripheralProperties) -> bool {
+        if let Some(min) = self.min_rssi {
+            if props.rssi.map(|rssi| rssi < min).unwrap_or(true) {
+                return false;
+            }
+        }
+
+        if let Some(pattern) = &self.name {
+            if !props.local_name.as_deref().map(|n| pattern.is_match(n)).unwrap_or(false) {
+                return false;
+            }
+        }
+
+        if !self.services.is_empty() && !self.services.iter().any(|s| {
+            props.services.contains(s) || props.service_data.contains_key(s)
+        }) {
+            return false;
+        }
+
+        self.manufacturers.is_empty()
+            || self.manufacturers.iter().any(|m| props.manufacturer_data.contains_key(m))
+    }
+
+    /// The part of the filter the bluetooth stack can apply itself. That isn't the services, as
+    /// the stack would leave out devices only sending service data.
+    pub(crate) fn scan_filter(&self) -> ScanFilter {
+        ScanFilter::default()
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ScanEventKind {
+    /// First advertisement of the device since the scan started
+    Discovered,
+    /// New advertisement data for an already discovered device
+    Updated,
+}
+
+/// A device advertising during a scan.
+#[derive(Debug, Clone)]
+pub struct ScanEvent {
+    pub kind: ScanEventKind,
+    pub device: DeviceInfo,
+    /// When the advertisement was received
+    pub seen: SystemTime,
+}
+
+/// A running scan, created by [`Session::watch`](crate::Session::watch).
+pub struct Scanner<'a> {
+    adapter: &'a dyn Adapter,
+    filter: DeviceFilter,
+    events: EventStream<AdapterEvent>,
+}
+
+impl<'a> Scanner<'a> {
+    pub(crate) async fn start(adapter: &'a dyn Adapter, filter: DeviceFilter) -> Result<Scanner<'a>> {
+        let events = adapter.events().await?;
+        adapter.start_scan(filter.scan_filter()).await?;
+
+        Ok(Scanner { adapter, filter, events })
+    }
+
+    /// Waits for the next advertisement of a device matching the filter. Returns `None` if the
+    /// backend stops reporting events.
+    pub async fn next(&mut self) -> Result<Option<ScanEvent>> {
+        while let Some(event) = self.events.next().await {
+            let kind = match event {
+                AdapterEvent::DeviceDiscovered(_) => ScanEventKind::Discovered,
+                AdapterEvent::DeviceUpdated(_) => ScanEventKind::Updated,
+                _ => continue,
+            };
+
+            for p in self.adapter.peripherals().await? {
+                if p.address() != event.address() {
+                    continue;
+                }
+
+                if let Some(props) = p.properties().await?.filter(|props| self.filter.matches(props)) {
+                    return Ok(Some(ScanEvent {
+                        kind,
+                        device: DeviceInfo::from(props),
+                        seen: SystemTime::now(),
+                    }));
+                }
+            }
+        }
+
+        Ok(None)
+    }
+
+    pub async fn stop(self) -> Result<()> {
+        Ok(self.adapter.stop_scan().await?)
+    }
+}
@@ -0,0 +1,576 @@
+//! Decoding of sensor readings broadcast in advertisements.
+//!
+//! [`Decoders`] holds the formats to try, each implementing [`AdvertisementDecoder`]. The
+//! built-in ones cover BTHome v2, RuuviTag data format 5 (RAWv2) and the ATC1441 and pvvx custom
+//! firmwares of Xiaomi thermometers.
+
+use std::fmt;
+
+use btleplug::api::bleuuid::uuid_from_u16;
+use uuid::Uuid;
+
+use crate::session::DeviceInfo;
+use crate::value::{hex, Value};
+
+/// Service whose service data carries BTHome frames.
+pub const BTHOME: Uuid = uuid_from_u16(0xfcd2);
+
+/// Company id of Ruuvi Innovations.
+pub const RUUVI: u16 = 0x0499;
+
+/// Service whose service data carries ATC1441 and pvvx frames.
+pub const ENVIRONMENTAL_SENSING: Uuid = uuid_from_u16(0x181a);
+
+/// One measurement, such as a temperature.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Reading {
+    /// What is measured, e.g. `temperature`
+    pub name: &'static str,
+    pub value: Value,
+    /// Unit symbol, if the value has one
+    pub unit: Option<&'static str>,
+}
+
+impl fmt::Display for Reading {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.unit {
+            Some(unit) => write!(f, "{} {} {}", self.name, self.value, unit),
+            None => write!(f, "{} {}", self.name, self.value),
+        }
+    }
+}
+
+/// Readings decoded from the advertisement of a device, in one format.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SensorData {
+    /// Name of the format, e.g. `BTHome v2`
+    pub format: &'static str,
+    pub readings: Vec<Reading>,
+}
+
+/// A format of sensor readings in advertisements.
+pub trait AdvertisementDecoder: Send + Sync {
+    /// Name of the format, e.g. `BTHome v2`.
+    fn name(&self) -> &'static str;
+
+    /// The readings advertised by a device, `None` if it doesn't use this format.
+    fn decode(&self, device: &DeviceInfo) -> Option<Vec<Reading>>;
+}
+
+/// The advertisement formats to try on every device.
+#[derive(Default)]
+pub struct Decoders {
+    decoders: Vec<Box<dyn AdvertisementDecoder>>,
+}
+
+impl Decoders {
+    /// No decoders at all.
+    pub fn new() -> Decoders {
+        Decoders { decoders: Vec::new() }
+    }
+
+    /// The decoders for all built-in formats.
+    pub fn builtin() -> Decoders {
+        Decoders::new()
+            .with(Box::new(BtHome))
+            .with(Box::new(Ruuvi))
+            .with(Box::new(Atc))
+    }
+
+    pub fn with(mut self, decoder: Box<dyn AdvertisementDecoder>) -> Decoders {
+        self.register(decoder);
+        self
+    }
+
+    pub fn register(&mut self, decoder: Box<dyn AdvertisementDecoder>) {
+        self.decoders.push(decoder);
+    }
+
+    /// The readings of a device in every format it matches.
+    pub fn decode(&self, device: &DeviceInfo) -> Vec<SensorData> {
+        self.decoders.iter()
+            .filter_map(|d| Some(SensorData { format: d.name(), readings: d.decode(device)? }))
+            .collect()
+    }
+}
+
+/// BTHome v2, unencrypted. See <https://bthome.io/format/>.
+pub struct BtHome;
+
+/// How a BTHome object is encoded.
+#[derive(Clone, Copy)]
+enum Object {
+    /// Little endian unsigned number of the given size, scaled by a power of 10
+    Unsigned(usize, i32),
+    Signed(usize, i32),
+    /// One byte, 0 or 1
+    Binary,
+    /// Length prefixed
+    Text,
+    Raw,
+}
+
+/// Object ids, with their name, encoding and unit.
+const BTHOME_OBJECTS: &[(u8, &str, Object, Option<&str>)] = &[
+    (0x00, "packet_id", Object::Unsigned(1, 0), None),
+    (0x01, "battery", Object::Unsigned(1, 0), Some("%")),
+    (0x02, "temperature", Object::Signed(2, -2), Some("°C")),
+    (0x03, "humidity", Object::Unsigned(2, -2), Some("%")),
+    (0x04, "pressure", Object::Unsigned(3, -2), Some("hPa")),
+    (0x05, "illuminance", Object::Unsigned(3, -2), Some("lx")),
+    (0x06, "mass", Object::Unsigned(2, -2), Some("kg")),
+    (0x07, "mass", Object::Unsigned(2, -2), Some("lb")),
+    (0x08, "dew_point", Object::Signed(2, -2), Some("°C")),
+    (0x09, "count", Object::Unsigned(1, 0), None),
+    (0x0a, "energy", Object::Unsigned(3, -3), Some("kWh")),
+    (0x0b, "power", Object::Unsigned(3, -2), Some("W")),
+    (0x0c, "voltage", Object::Unsigned(2, -3), Some("V")),
+    (0x0d, "pm2_5", Object::Unsigned(2, 0), Some("µg/m³")),
+    (0x0e, "pm10", Object::Unsigned(2, 0), Some("µg/m³")),
+    (0x0f, "generic", Object::Binary, None),
+    (0x10, "power_on", Object::Binary, None),
+    (0x11, "opening", Object::Binary, None),
+    (0x12, "co2", Object::Unsigned(2, 0), Some("ppm")),
+    (0x13, "tvoc", Object::Unsigned(2, 0), Some("µg/m³")),
+    (0x14, "moisture", Object::Unsigned(2, -2), Some("%")),
+    (0x15, "battery_low", Object::Binary, None),
+    (0x16, "battery_charging", Object::Binary, None),
+    (0x17, "carbon_monoxide", Object::Binary, None),
+    (0x18, "cold", Object::Binary, None),
+    (0x19, "connectivity", Object::Binary, None),
+    (0x1a, "door", Object::Binary, None),
+    (0x1b, "garage_door", Object::Binary, None),
+    (0x1c, "gas", Object::Binary, None),
+    (0x1d, "heat", Object::Binary, None),
+    (0x1e, "light", Object::Binary, None),
+    (0x1f, "lock", Object::Binary, None),
+    (0x20, "moisture_detected", Object::Binary, None),
+    (0x21, "motion", Object::Binary, None),
+    (0x22, "moving", Object::Binary, None),
+    (0x23, "occupancy", Object::Binary, None),
+    (0x24, "plug", Object::Binary, None),
+    (0x25, "presence", Object::Binary, None),
+    (0x26, "problem", Object::Binary, None),
+    (0x27, "running", Object::Binary, None),
+    (0x28, "safety", Object::Binary, None),
+    (0x29, "smoke", Object::Binary, None),
+    (0x2a, "sound", Object::Binary, None),
+    (0x2b, "tamper", Object::Binary, None),
+    (0x2c, "vibration", Object::Binary, None),
+    (0x2d, "window", Object::Binary, None),
+    (0x2e, "humidity", Object::Unsigned(1, 0), Some("%")),
+    (0x2f, "moisture", Object::Unsigned(1, 0), Some("%")),
+    (0x3a, "button", Object::Unsigned(1, 0), None),
+    (0x3c, "dimmer", Object::Unsigned(2, 0), None),
+    (0x3d, "count", Object::Unsigned(2, 0), None),
+    (0x3e, "count", Object::Unsigned(4, 0), None),
+    (0x3f, "rotation", Object::Signed(2, -1), Some("°")),
+    (0x40, "distance", Object::Unsigned(2, 0), Some("mm")),
+    (0x41, "distance", Object::Unsigned(2, -1), Some("m")),
+    (0x42, "duration", Object::Unsigned(3, -3), Some("s")),
+    (0x43, "current", Object::Unsigned(2, -3), Some("A")),
+    (0x44, "speed", Object::Unsigned(2, -2), Some("m/s")),
+    (0x45, "temperature", Object::Signed(2, -1), Some("°C")),
+    (0x46, "uv_index", Object::Unsigned(1, -1), None),
+    (0x47, "volume", Object::Unsigned(2, -1), Some("L")),
+    (0x48, "volume", Object::Unsigned(2, 0), Some("mL")),
+    (0x49, "volume_flow_rate", Object::Unsigned(2, -3), Some("m³/h")),
+    (0x4a, "voltage", Object::Unsigned(2, -1), Some("V")),
+    (0x4b, "gas", Object::Unsigned(3, -3), Some("m³")),
+    (0x4c, "gas", Object::Unsigned(4, -3), Some("m³")),
+    (0x4d, "energy", Object::Unsigned(4, -3), Some("kWh")),
+    (0x4e, "volume", Object::Unsigned(4, -3), Some("L")),
+    (0x4f, "water", Object::Unsigned(4, -3), Some("L")),
+    (0x50, "timestamp", Object::Unsigned(4, 0), Some("s")),
+    (0x51, "acceleration", Object::Unsigned(2, -3), Some("m/s²")),
+    (0x52, "gyroscope", Object::Unsigned(2, -3), Some("°/s")),
+    (0x53, "text", Object::Text, None),
+    (0x54, "raw", Object::Raw, None),
+    (0x55, "volume_storage", Object::Unsigned(4, -3), Some("L")),
+    (0x56, "conductivity", Object::Unsigned(2, 0), Some("µS/cm")),
+    (0x57, "temperature", Object::Signed(1, 0), Some("°C")),
+    (0x59, "count", Object::Signed(1, 0), None),
+    (0x5a, "count", Object::Signed(2, 0), None),
+    (0x5b, "count", Object::Signed(4, 0), None),
+    (0x5c, "power", Object::Signed(4, -2), Some("W")),
+    (0x5d, "current", Object::Signed(2, -3), Some("A")),
+    (0x5e, "direction", Object::Unsigned(2, -2), Some("°")),
+    (0x5f, "precipitation", Object::Unsigned(2, -1), Some("mm")),
+    (0x60, "channel", Object::Unsigned(1, 0), None),
+    (0xf0, "device_type", Object::Unsigned(2, 0), None),
+    (0xf1, "firmware", Object::Unsigned(4, 0), None),
+    (0xf2, "firmware", Object::Unsigned(3, 0), None),
+];
+
+impl AdvertisementDecoder for BtHome {
+    fn name(&self) -> &'static str {
+        "BTHome v2"
+    }
+
+    fn decode(&self, device: &DeviceInfo) -> Option<Vec<Reading>> {
+        let (&info, mut data) = device.service_data.get(&BTHOME)?.split_first()?;
+        // Version 2 in the top bits, and no encryption
+        if info >> 5 != 2 || info & 0x01 != 0 {
+            return None;
+        }
+
+        let mut readings = Vec::new();
+        while let Some((&id, rest)) = data.split_first() {
+            // The size of unknown objects is unknown too, so nothing after them can be decoded
+            let Some(&(_, name, object, unit)) = BTHOME_OBJECTS.iter().find(|o| o.0 == id) else {
+                break;
+            };
+
+            let (value, rest) = match object {
+                Object::Unsigned(size, exponent) => {
+                    let (bytes, rest) = split(rest, size)?;
+                    (scaled(le_unsigned(bytes), exponent), rest)
+                }
+                Object::Signed(size, exponent) => {
+                    let (bytes, rest) = split(rest, size)?;
+                    // Sign extend from the top byte
+                    let shift = 64 - 8 * size as u32;
+                    let value = ((le_unsigned(bytes) << shift) as i64) >> shift;
+                    (scaled_signed(value, exponent), rest)
+                }
+                Object::Binary => {
+                    let (bytes, rest) = split(rest, 1)?;
+                    (Value::Bool(bytes[0] != 0), rest)
+                }
+                Object::Text | Object::Raw => {
+                    let (&len, rest) = rest.split_first()?;
+                    let (bytes, rest) = split(rest, len as usize)?;
+                    let value = match object {
+                        Object::Text => Value::Text(String::from_utf8_lossy(bytes).into_owned()),
+                        _ => Value::Text(hex(bytes)),
+                    };
+                    (value, rest)
+                }
+            };
+
+            readings.push(Reading { name, value, unit });
+            data = rest;
+        }
+
+        Some(readings)
+    }
+}
+
+/// RuuviTag data format 5 (RAWv2). See <https://docs.ruuvi.com/communication/bluetooth-advertisements/data-format-5-rawv2>.
+pub struct Ruuvi;
+
+impl AdvertisementDecoder for Ruuvi {
+    fn name(&self) -> &'static str {
+        "RuuviTag RAWv2"
+    }
+
+    fn decode(&self, device: &DeviceInfo) -> Option<Vec<Reading>> {
+        let data = device.manufacturer_data.get(&RUUVI)?;
+        if data.len() < 24 || data[0] != 5 {
+            return None;
+        }
+
+        let u16_at = |i: usize| u16::from_be_bytes([data[i], data[i + 1]]);
+        let i16_at = |i: usize| i16::from_be_bytes([data[i], data[i + 1]]);
+
+        // Each field has a value meaning it isn't available
+        let mut readings = Vec::new();
+        let mut push = |name, value: Option<Value>, unit| {
+            if let Some(value) = value {
+                readings.push(Reading { name, value, unit });
+            }
+        };
+
+        let float = Value::Float;
+        push("temperature", (i16_at(1) != i16::MIN).then(|| float(i16_at(1) as f64 / 200.0)), Some("°C"));
+        push("humidity", (u16_at(3) != u16::MAX).then(|| float(u16_at(3) as f64 / 400.0)), Some("%"));
+        push("pressure", (u16_at(5) != u16::MAX).then(|| float((u16_at(5) as f64 + 50000.0) / 100.0)), Some("hPa"));
+        for (i, name) in [(7, "acceleration_x"), (9, "acceleration_y"), (11, "acceleration_z")] {
+            push(name, (i16_at(i) != i16::MIN).then(|| float(i16_at(i) as f64 / 1000.0)), Some("g"));
+        }
+
+        let power = u16_at(13);
+        push("voltage", (power >> 5 != 0x7ff).then(|| float(((power >> 5) as f64 + 1600.0) / 1000.0)), Some("V"));
+        push("tx_power", (power & 0x1f != 0x1f).then(|| Value::Int((power & 0x1f) as i64 * 2 - 40)), Some("dBm"));
+        push("movements", (data[15] != u8::MAX).then(|| Value::UInt(data[15] as u64)), None);
+        push("sequence", (u16_at(16) != u16::MAX).then(|| Value::UInt(u16_at(16) as u64)), None);
+
+        Some(readings)
+    }
+}
+
+/// ATC1441 and pvvx custom firmwares for Xiaomi thermometers, told apart by their length. See
+/// <https://github.com/pvvx/ATC_MiThermometer#bluetooth-advertising-formats>.
+pub struct Atc;
+
+impl AdvertisementDecoder for Atc {
+    fn name(&self) -> &'static str {
+        "ATC"
+    }
+
+    fn decode(&self, device: &DeviceInfo) -> Option<Vec<Reading>> {
+        let data = device.service_data.get(&ENVIRONMENTAL_SENSING)?;
+        let reading = |name, value, unit| Reading { name, value, unit: Some(unit) };
+
+        match data.len() {
+            // ATC1441: MAC, then big endian temperature in 0.1 °C, humidity %, battery % and mV
+            13 => Some(vec![
+                reading("temperature", Value::Float(i16::from_be_bytes([data[6], data[7]]) as f64 / 10.0), "°C"),
+                reading("humidity", Value::UInt(data[8] as u64), "%"),
+                reading("battery", Value::UInt(data[9] as u64), "%"),
+                reading("voltage", Value::Float(u16::from_be_bytes([data[10], data[11]]) as f64 / 1000.0), "V"),
+            ]),
+            // pvvx: reversed MAC, then little endian temperature and humidity in hundredths, mV and
+            // battery %
+            15 => Some(vec![
+                reading("temperature", Value::Float(i16::from_le_bytes([data[6], data[7]]) as f64 / 100.0), "°C"),
+                reading("humidity", Value::Float(u16::from_le_bytes([data[8], data[9]]) as f64 / 100.0), "%"),
+                reading("voltage", Value::Float(u16::from_le_bytes([data[10], data[11]]) as f64 / 1000.0), "V"),
+                reading("battery", Value::UInt(data[12] as u64), "%"),
+            ]),
+            _ => None,
+        }
+    }
+}
+
+fn split(data: &[u8], len: usize) -> Option<(&[u8], &[u8])> {
+    (data.len() >= len).then(|| data.split_at(len))
+}
+
+fn le_unsigned(bytes: &[u8]) -> u64 {
+    bytes.iter().rev().fold(0, |n, b| n << 8 | *b as u64)
+}
+
+fn scaled(value: u64, exponent: i32) -> Value {
+    match exponent {
+        0 => Value::UInt(value),
+        e => Value::Float(value as f64 / 10f64.powi(-e)),
+    }
+}
+
+fn scaled_signed(value: i64, exponent: i32) -> Value {
+    match exponent {
+        0 => Value::Int(value),
+        e => Value::Float(value as f64 / 10f64.powi(-e)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+
+    use btleplug::api::BDAddr;
+
+    use super::*;
+
+    fn device(manufacturer_data: &[(u16, &[u8])], service_data: &[(Uuid, &[u8])]) -> DeviceInfo {
+        DeviceInfo {
+            address: BDAddr::default(),
+            address_type: None,
+            local_name: None,
+            rssi: None,
+            tx_power: None,
+            services: Vec::new(),
+            manufacturer_data: manufacturer_data.iter().map(|(c, d)| (*c, d.to_vec())).collect(),
+            service_data: service_data.iter().map(|(u, d)| (*u, d.to_vec())).collect::<BTreeMap<_, _>>(),
+        }
+    }
+
+    fn reading(name: &'static str, value: Value, unit: &'static str) -> Reading {
+        Reading { name, value, unit: (!unit.is_empty()).then_some(unit) }
+    }
+
+    fn bthome(data: &[u8]) -> Option<Vec<Reading>> {
+        BtHome.decode(&device(&[], &[(BTHOME, data)]))
+    }
+
+    fn ruuvi(hex: &str) -> Option<Vec<Reading>> {
+        let data: Vec<u8> = (0..hex.len()).step_by(2).map(|i| u8::from_str_radix(&hex[i..i + 2], 16).unwrap()).collect();
+        Ruuvi.decode(&device(&[(RUUVI, &data)], &[]))
+    }
+
+    fn atc(data: &[u8]) -> Option<Vec<Reading>> {
+        Atc.decode(&device(&[], &[(ENVIRONMENTAL_SENSING, data)]))
+    }
+
+    #[test]
+    fn decodes_bthome_numbers() {
+        assert_eq!(
+            bthome(&[0x40, 0x01, 87, 0x02, 0xca, 0x09, 0x03, 0xbf, 0x13, 0x04, 0x13, 0x8a, 0x01, 0x57, 0xea]),
+            Some(vec![
+                reading("battery", Value::UInt(87), "%"),
+                reading("temperature", Value::Float(25.06), "°C"),
+                reading("humidity", Value::Float(50.55), "%"),
+                reading("pressure", Value::Float(1008.83), "hPa"),
+                reading("temperature", Value::Int(-22), "°C"),
+            ])
+        );
+        assert_eq!(
+            bthome(&[0x40, 0x5a, 0x0c, 0xfe, 0x3e, 0x2a, 0x00, 0x00, 0x01]),
+            Some(vec![
+                reading("count", Value::Int(-500), ""),
+                reading("count", Value::UInt(0x0100_002a), ""),
+            ])
+        );
+    }
+
+    #[test]
+    fn decodes_bthome_binary_text_and_raw() {
+        let mut data = vec![0x40, 0x21, 0x01, 0x2d, 0x00, 0x53, 0x0c];
+        data.extend(b"Hello World!");
+        data.extend([0x54, 0x03, 0x01, 0xab, 0xff]);
+        assert_eq!(
+            bthome(&data),
+            Some(vec![
+                reading("motion", Value::Bool(true), ""),
+                reading("window", Value::Bool(false), ""),
+                reading("text", Value::Text("Hello World!".into()), ""),
+                reading("raw", Value::Text("01abff".into()), ""),
+            ])
+        );
+    }
+
+    #[test]
+    fn only_decodes_unencrypted_bthome_v2() {
+        assert_eq!(bthome(&[0x40]), Some(Vec::new()));
+        // Encrypted
+        assert_eq!(bthome(&[0x41, 0x01, 87]), None);
+        // Version 1
+        assert_eq!(bthome(&[0x20, 0x01, 87]), None);
+        assert_eq!(bthome(&[]), None);
+    }
+
+    #[test]
+    fn stops_at_unknown_bthome_objects() {
+        assert_eq!(
+            bthome(&[0x40, 0x01, 87, 0xfe, 0x01, 0x02, 0x29, 0x09]),
+            Some(vec![reading("battery", Value::UInt(87), "%")])
+        );
+        assert_eq!(bthome(&[0x40, 0x61, 0x01, 87]), Some(Vec::new()));
+    }
+
+    #[test]
+    fn truncated_bthome_objects_are_not_decoded() {
+        let data = [0x40, 0x01, 87, 0x04, 0x13, 0x8a, 0x01, 0x53, 0x02, b'h', b'i'];
+        for len in [2, 4, 5, 6, 8, 9, 10] {
+            assert_eq!(bthome(&data[..len]), None, "{} bytes", len);
+        }
+        assert_eq!(bthome(&data[..3]).map(|r| r.len()), Some(1));
+        assert_eq!(bthome(&data[..7]).map(|r| r.len()), Some(2));
+        assert_eq!(bthome(&data).map(|r| r.len()), Some(3));
+    }
+
+    #[test]
+    fn decodes_ruuvi_valid_data() {
+        // Test vectors from the data format 5 specification
+        assert_eq!(
+            ruuvi("0512FC5394C37C0004FFFC040CAC364200CDCBB8334C884F"),
+            Some(vec![
+                reading("temperature", Value::Float(24.3), "°C"),
+                reading("humidity", Value::Float(53.49), "%"),
+                reading("pressure", Value::Float(1000.44), "hPa"),
+                reading("acceleration_x", Value::Float(0.004), "g"),
+                reading("acceleration_y", Value::Float(-0.004), "g"),
+                reading("acceleration_z", Value::Float(1.036), "g"),
+                reading("voltage", Value::Float(2.977), "V"),
+                reading("tx_power", Value::Int(4), "dBm"),
+                reading("movements", Value::UInt(66), ""),
+                reading("sequence", Value::UInt(205), ""),
+            ])
+        );
+    }
+
+    #[test]
+    fn decodes_ruuvi_extremes() {
+        assert_eq!(
+            ruuvi("057FFFFFFEFFFE7FFF7FFF7FFFFFDEFEFFFECBB8334C884F"),
+            Some(vec![
+                reading("temperature", Value::Float(163.835), "°C"),
+                reading("humidity", Value::Float(163.835), "%"),
+                reading("pressure", Value::Float(1155.34), "hPa"),
+                reading("acceleration_x", Value::Float(32.767), "g"),
+                reading("acceleration_y", Value::Float(32.767), "g"),
+                reading("acceleration_z", Value::Float(32.767), "g"),
+                reading("voltage", Value::Float(3.646), "V"),
+                reading("tx_power", Value::Int(20), "dBm"),
+                reading("movements", Value::UInt(254), ""),
+                reading("sequence", Value::UInt(65534), ""),
+            ])
+        );
+        assert_eq!(
+            ruuvi("058001000000008001800180010000000000CBB8334C884F"),
+            Some(vec![
+                reading("temperature", Value::Float(-163.835), "°C"),
+                reading("humidity", Value::Float(0.0), "%"),
+                reading("pressure", Value::Float(500.0), "hPa"),
+                reading("acceleration_x", Value::Float(-32.767), "g"),
+                reading("acceleration_y", Value::Float(-32.767), "g"),
+                reading("acceleration_z", Value::Float(-32.767), "g"),
+                reading("voltage", Value::Float(1.6), "V"),
+                reading("tx_power", Value::Int(-40), "dBm"),
+                reading("movements", Value::UInt(0), ""),
+                reading("sequence", Value::UInt(0), ""),
+            ])
+        );
+    }
+
+    #[test]
+    fn skips_unavailable_ruuvi_values() {
+        assert_eq!(ruuvi("058000FFFFFFFF800080008000FFFFFFFFFFFFFFFFFFFFFF"), Some(Vec::new()));
+    }
+
+    #[test]
+    fn rejects_other_ruuvi_frames() {
+        let valid = "0512FC5394C37C0004FFFC040CAC364200CDCBB8334C884F";
+        for len in (0..valid.len()).step_by(2) {
+            assert_eq!(ruuvi(&valid[..len]), None, "{}", &valid[..len]);
+        }
+        // Data format 3
+        assert_eq!(ruuvi("0312FC5394C37C0004FFFC040CAC364200CDCBB8334C884F"), None);
+    }
+
+    #[test]
+    fn decodes_atc1441() {
+        assert_eq!(
+            atc(&[0xa4, 0xc1, 0x38, 0x12, 0x34, 0x56, 0xff, 0x9c, 45, 81, 0x0b, 0x86, 0x07]),
+            Some(vec![
+                reading("temperature", Value::Float(-10.0), "°C"),
+                reading("humidity", Value::UInt(45), "%"),
+                reading("battery", Value::UInt(81), "%"),
+                reading("voltage", Value::Float(2.95), "V"),
+            ])
+        );
+    }
+
+    #[test]
+    fn decodes_pvvx() {
+        assert_eq!(
+            atc(&[0x56, 0x34, 0x12, 0x38, 0xc1, 0xa4, 0x66, 0x08, 0xa8, 0x11, 0x86, 0x0b, 81, 0x01, 0x04]),
+            Some(vec![
+                reading("temperature", Value::Float(21.5), "°C"),
+                reading("humidity", Value::Float(45.2), "%"),
+                reading("voltage", Value::Float(2.95), "V"),
+                reading("battery", Value::UInt(81), "%"),
+            ])
+        );
+    }
+
+    #[test]
+    fn rejects_other_atc_lengths() {
+        let data = [0u8; 16];
+        for len in [0, 6, 12, 14, 16] {
+            assert_eq!(atc(&data[..len]), None, "{} bytes", len);
+        }
+    }
+
+    #[test]
+    fn decodes_every_matching_format() {
+        let device = device(
+            &[(RUUVI, &[0x05; 24])],
+            &[(BTHOME, &[0x40, 0x01, 87]), (ENVIRONMENTAL_SENSING, &[0; 4])],
+        );
+        let formats: Vec<&str> = Decoders::builtin().decode(&device).iter().map(|s| s.format).collect();
+        assert_eq!(formats, ["BTHome v2", "RuuviTag RAWv2"]);
+        assert!(Decoders::new().decode(&device).is_empty());
+    }
+}
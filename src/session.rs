@@ -0,0 +1,420 @@
+//! High level API: scanning for devices and talking to a connected one.
+
+use std::collections::{BTreeMap, HashSet};
+use std::str::FromStr;
+use std::sync::{Mutex, MutexGuard};
+use std::time::Duration;
+
+use btleplug::api::{
+    AddressType, BDAddr, CharPropFlags, Characteristic, PeripheralProperties, Service,
+    ValueNotification, WriteType,
+};
+use tokio::time;
+use tokio_stream::StreamExt;
+use uuid::Uuid;
+
+use crate::backend::{Adapter, Backend, BtleplugBackend, EventStream, Peripheral, DEFAULT_MTU};
+use crate::error::{BleUtilError, Result};
+use crate::lookup::{self, Target};
+use crate::scan::{DeviceFilter, Scanner};
+use crate::value::{self, PresentationFormat};
+
+/// A backend along with the adapter used for all operations.
+pub struct Session {
+    // Kept alive for as long as the adapter is in use
+    _backend: Box<dyn Backend>,
+    adapter: Box<dyn Adapter>,
+}
+
+impl Session {
+    /// Opens a session on the first adapter of the platform bluetooth stack.
+    pub async fn new() -> Result<Session> {
+        Session::with_backend(Box::new(BtleplugBackend::new().await?)).await
+    }
+
+    /// Opens a session on the first adapter of the given backend.
+    pub async fn with_backend(backend: Box<dyn Backend>) -> Result<Session> {
+        Session::with_adapter(backend, &AdapterSelector::Index(0)).await
+    }
+
+    /// Opens a session on the adapter of the given backend matching `selector`.
+    pub async fn with_adapter(backend: Box<dyn Backend>, selector: &AdapterSelector) -> Result<Session> {
+        let mut adapter = None;
+        for (index, a) in backend.adapters().await?.into_iter().enumerate() {
+            let selected = match selector {
+                AdapterSelector::Index(i) => index == *i,
+                AdapterSelector::Name(name) => a.info().await?.contains(name.as_str()),
+            };
+
+            if selected {
+                adapter = Some(a);
+                break;
+            }
+        }
+
+        let adapter = adapter.ok_or(BleUtilError::NoAdapter)?;
+        Ok(Session { _backend: backend, adapter })
+    }
+
+    pub fn adapter(&self) -> &dyn Adapter {
+        self.adapter.as_ref()
+    }
+
+    /// Scans for `duration` and returns every device seen that matches `filter`.
+    pub async fn scan(&self, duration: Duration, filter: &DeviceFilter) -> Result<Vec<DeviceInfo>> {
+        self.adapter.start_scan(filter.scan_filter()).await?;
+        time::sleep(duration).await;
+        self.adapter.stop_scan().await?;
+
+        let mut devices = Vec::new();
+        for p in self.adapter.peripherals().await? {
+            if let Some(props) = p.properties().await?.filter(|props| filter.matches(props)) {
+                devices.push(DeviceInfo::from(props));
+            }
+        }
+
+        Ok(devices)
+    }
+
+    /// Starts scanning, reporting devices matching `filter` as they advertise until
+    /// [`Scanner::stop`] is called.
+    pub async fn watch(&self, filter: DeviceFilter) -> Result<Scanner<'_>> {
+        Scanner::start(self.adapter.as_ref(), filter).await
+    }
+
+    /// Waits up to `timeout` for a device matching `target` to advertise.
+    pub async fn find(&self, target: &Target, timeout: Duration) -> Result<Box<dyn Peripheral>> {
+        lookup::find(self.adapter.as_ref(), target, timeout).await?
+            .ok_or_else(|| BleUtilError::DeviceNotFound(target.clone()))
+    }
+
+    /// Finds a device matching `target`, connects to it and discovers its services. `timeout`
+    /// applies to the lookup and to the connection separately.
+    pub async fn connect(&self, target: &Target, timeout: Duration) -> Result<Device> {
+        let dev = Device {
+            peripheral: self.find(target, timeout).await?,
+            subscriptions: Mutex::new(HashSet::new()),
+        };
+
+        time::timeout(timeout, async {
+            dev.peripheral.connect().await?;
+            dev.peripheral.discover_services().await
+        })
+        .await
+        .map_err(|_| BleUtilError::Timeout(timeout))??;
+
+        Ok(dev)
+    }
+}
+
+/// Lists the adapters of a backend, in the order [`AdapterSelector::Index`] refers to them.
+pub async fn adapters(backend: &dyn Backend) -> Result<Vec<AdapterInfo>> {
+    let mut infos = Vec::new();
+    for (index, a) in backend.adapters().await?.into_iter().enumerate() {
+        infos.push(AdapterInfo { index, info: a.info().await? });
+    }
+
+    Ok(infos)
+}
+
+#[derive(Debug, Clone)]
+pub struct AdapterInfo {
+    pub index: usize,
+    /// Description reported by the bluetooth stack, usually starting with the adapter name
+    pub info: String,
+}
+
+/// Picks one of the local adapters, either by position or by a name found in its description
+/// (`hci1`, a USB vendor id...).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum AdapterSelector {
+    Index(usize),
+    Name(String),
+}
+
+impl FromStr for AdapterSelector {
+    type Err = std::convert::Infallible;
+
+    fn from_str(s: &str) -> std::result::Result<AdapterSelector, Self::Err> {
+        Ok(match s.parse() {
+            Ok(index) => AdapterSelector::Index(index),
+            Err(_) => AdapterSelector::Name(s.into()),
+        })
+    }
+}
+
+/// A device seen while scanning, along with its latest advertisement data.
+#[derive(Debug, Clone)]
+pub struct DeviceInfo {
+    pub address: BDAddr,
+    pub address_type: Option<AddressType>,
+    pub local_name: Option<String>,
+    /// Received signal strength, in dBm
+    pub rssi: Option<i16>,
+    /// Advertised transmission power, in dBm
+    pub tx_power: Option<i16>,
+    /// Advertised service UUIDs
+    pub services: Vec<Uuid>,
+    /// Manufacturer specific data, by company id
+    pub manufacturer_data: BTreeMap<u16, Vec<u8>>,
+    /// Service data, by service UUID
+    pub service_data: BTreeMap<Uuid, Vec<u8>>,
+}
+
+impl From<PeripheralProperties> for DeviceInfo {
+    fn from(props: PeripheralProperties) -> DeviceInfo {
+        DeviceInfo {
+            address: props.address,
+            address_type: props.address_type,
+            local_name: props.local_name,
+            rssi: props.rssi,
+            tx_power: props.tx_power_level,
+            services: props.services,
+            manufacturer_data: props.manufacturer_data.into_iter().collect(),
+            service_data: props.service_data.into_iter().collect(),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct ServiceInfo {
+    pub uuid: Uuid,
+    pub primary: bool,
+    pub characteristics: Vec<CharacteristicInfo>,
+}
+
+impl From<Service> for ServiceInfo {
+    fn from(s: Service) -> ServiceInfo {
+        ServiceInfo {
+            uuid: s.uuid,
+            primary: s.primary,
+            characteristics: s.characteristics.into_iter().map(CharacteristicInfo::from).collect(),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct CharacteristicInfo {
+    pub uuid: Uuid,
+    pub service_uuid: Uuid,
+    pub properties: CharPropFlags,
+    /// UUIDs of the characteristic's descriptors
+    pub descriptors: Vec<Uuid>,
+}
+
+impl From<Characteristic> for CharacteristicInfo {
+    fn from(c: Characteristic) -> CharacteristicInfo {
+        CharacteristicInfo {
+            uuid: c.uuid,
+            service_uuid: c.service_uuid,
+            properties: c.properties,
+            descriptors: c.descriptors.into_iter().map(|d| d.uuid).collect(),
+        }
+    }
+}
+
+/// Bytes of each ATT write taken by the opcode and attribute handle.
+const WRITE_HEADER: u16 = 3;
+
+/// How long [`Device::write_chunked`] waits for the device to acknowledge a chunk.
+const ACK_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// How [`Device::write_chunked`] splits and paces long values.
+#[derive(Clone, Debug, Default)]
+pub struct Chunking {
+    /// ATT MTU to size the chunks for, instead of the connection's
+    pub mtu: Option<u16>,
+    /// Pause between chunks, for devices with small receive buffers
+    pub interval: Duration,
+    /// Characteristic the device notifies after each chunk, acknowledging it or granting credit
+    /// for the next one
+    pub ack: Option<Uuid>,
+}
+
+/// A connected device whose services have been discovered.
+pub struct Device {
+    peripheral: Box<dyn Peripheral>,
+    /// Characteristics subscribed to through this device
+    subscriptions: Mutex<HashSet<Uuid>>,
+}
+
+impl Device {
+    pub fn address(&self) -> BDAddr {
+        self.peripheral.address()
+    }
+
+    /// The underlying peripheral, for operations not covered by `Device`.
+    pub fn peripheral(&self) -> &dyn Peripheral {
+        self.peripheral.as_ref()
+    }
+
+    /// ATT MTU of the connection, [`DEFAULT_MTU`] if the backend can't tell.
+    pub fn mtu(&self) -> u16 {
+        self.peripheral.mtu().unwrap_or(DEFAULT_MTU)
+    }
+
+    pub fn services(&self) -> Vec<ServiceInfo> {
+        self.peripheral.services().into_iter().map(ServiceInfo::from).collect()
+    }
+
+    pub fn characteristic(&self, uuid: Uuid) -> Result<Characteristic> {
+        self.peripheral.characteristics()
+            .into_iter()
+            .find(|c| c.uuid == uuid)
+            .ok_or(BleUtilError::CharacteristicNotFound(uuid))
+    }
+
+    pub async fn read(&self, uuid: Uuid) -> Result<Vec<u8>> {
+        let ch = self.characteristic(uuid)?;
+        if !ch.properties.contains(CharPropFlags::READ) {
+            return Err(BleUtilError::NotReadable(uuid));
+        }
+
+        Ok(self.peripheral.read(&ch).await?)
+    }
+
+    pub async fn read_descriptor(&self, characteristic: Uuid, uuid: Uuid) -> Result<Vec<u8>> {
+        let ch = self.characteristic(characteristic)?;
+        let descriptor = ch.descriptors.iter()
+            .find(|d| d.uuid == uuid)
+            .ok_or(BleUtilError::DescriptorNotFound(characteristic, uuid))?;
+
+        Ok(self.peripheral.read_descriptor(descriptor).await?)
+    }
+
+    /// Reads the Characteristic Presentation Format descriptor of a characteristic. `None` if it
+    /// has none, or an invalid one.
+    pub async fn presentation_format(&self, uuid: Uuid) -> Result<Option<PresentationFormat>> {
+        match self.read_descriptor(uuid, value::PRESENTATION_FORMAT).await {
+            Ok(data) => Ok(PresentationFormat::parse(&data)),
+            Err(BleUtilError::DescriptorNotFound(..)) => Ok(None),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Writes to a characteristic, provided its properties allow `write_type`.
+    pub async fn write(&self, uuid: Uuid, data: &[u8], write_type: WriteType) -> Result<()> {
+        let ch = self.characteristic(uuid)?;
+        let required = match write_type {
+            WriteType::WithResponse => CharPropFlags::WRITE,
+            WriteType::WithoutResponse => CharPropFlags::WRITE_WITHOUT_RESPONSE,
+        };
+        if !ch.properties.contains(required) {
+            return Err(BleUtilError::NotWritable(uuid));
+        }
+
+        Ok(self.peripheral.write(&ch, data, write_type).await?)
+    }
+
+    /// Writes a value of any length as successive writes each fitting in a single ATT packet.
+    /// Returns the number of writes made.
+    pub async fn write_chunked(
+        &self,
+        uuid: Uuid,
+        data: &[u8],
+        write_type: WriteType,
+        chunking: &Chunking,
+    ) -> Result<usize> {
+        self.write_chunked_with_progress(uuid, data, write_type, chunking, |_| ()).await
+    }
+
+    /// Same as [`Device::write_chunked`], calling `progress` with the number of bytes written so
+    /// far after each chunk. The acknowledgement characteristic, if any, is subscribed to for the
+    /// duration of the writes unless it already was.
+    pub async fn write_chunked_with_progress(
+        &self,
+        uuid: Uuid,
+        data: &[u8],
+        write_type: WriteType,
+        chunking: &Chunking,
+        mut progress: impl FnMut(usize) + Send,
+    ) -> Result<usize> {
+        let mtu = chunking.mtu.unwrap_or_else(|| self.mtu());
+        let size = mtu.saturating_sub(WRITE_HEADER).max(1) as usize;
+
+        // A subscription of the caller is left as it is
+        let mut subscribed = None;
+        let mut acks = match chunking.ack {
+            Some(ack) => {
+                let notifications = self.notifications().await?;
+                if !self.is_subscribed(ack) {
+                    self.subscribe(ack).await?;
+                    subscribed = Some(ack);
+                }
+                Some(notifications.filter(move |n| n.uuid == ack))
+            }
+            None => None,
+        };
+
+        // An empty value still takes a write
+        let chunks: Vec<&[u8]> = if data.is_empty() { vec![data] } else { data.chunks(size).collect() };
+        let res = async {
+            let mut written = 0;
+            for (i, chunk) in chunks.iter().enumerate() {
+                if i > 0 && !chunking.interval.is_zero() {
+                    time::sleep(chunking.interval).await;
+                }
+
+                self.write(uuid, chunk, write_type).await?;
+                written += chunk.len();
+                progress(written);
+
+                if let Some(acks) = &mut acks {
+                    match time::timeout(ACK_TIMEOUT, acks.next()).await {
+                        Ok(Some(_)) => {}
+                        _ => return Err(BleUtilError::Timeout(ACK_TIMEOUT)),
+                    }
+                }
+            }
+            Ok(chunks.len())
+        }.await;
+
+        // Whether or not the writes went through, as the link may be gone by now
+        if let Some(ack) = subscribed {
+            self.unsubscribe(ack).await.ok();
+        }
+        res
+    }
+
+    /// Enables notifications or indications, whichever the characteristic supports, of a
+    /// characteristic. The values are received through [`Device::notifications`].
+    pub async fn subscribe(&self, uuid: Uuid) -> Result<()> {
+        let ch = self.characteristic(uuid)?;
+        if !ch.properties.intersects(CharPropFlags::NOTIFY | CharPropFlags::INDICATE) {
+            return Err(BleUtilError::NotSubscribable(uuid));
+        }
+
+        self.peripheral.subscribe(&ch).await?;
+        lock(&self.subscriptions).insert(uuid);
+        Ok(())
+    }
+
+    pub async fn unsubscribe(&self, uuid: Uuid) -> Result<()> {
+        let ch = self.characteristic(uuid)?;
+        self.peripheral.unsubscribe(&ch).await?;
+        lock(&self.subscriptions).remove(&uuid);
+        Ok(())
+    }
+
+    /// Whether a characteristic was subscribed to through this device and not unsubscribed from
+    /// since.
+    pub fn is_subscribed(&self, uuid: Uuid) -> bool {
+        lock(&self.subscriptions).contains(&uuid)
+    }
+
+    /// Stream of the values notified or indicated by all subscribed characteristics. Only values
+    /// received after the call are returned.
+    pub async fn notifications(&self) -> Result<EventStream<ValueNotification>> {
+        Ok(self.peripheral.notifications().await?)
+    }
+
+    pub async fn disconnect(&self) -> Result<()> {
+        self.peripheral.disconnect().await?;
+        lock(&self.subscriptions).clear();
+        Ok(())
+    }
+}
+
+fn lock<T>(m: &Mutex<T>) -> MutexGuard<'_, T> {
+    m.lock().unwrap_or_else(|e| e.into_inner())
+}
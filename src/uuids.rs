@@ -0,0 +1,16 @@
+//! Parsing of the UUID notations accepted on the command line.
+
+use btleplug::api::bleuuid::{uuid_from_u16, uuid_from_u32};
+use uuid::Uuid;
+
+/// Parses a full 128-bit UUID, or a 16/32-bit short UUID (`180f`, `0x180f`) based on the
+/// Bluetooth base UUID.
+pub fn parse_uuid(s: &str) -> Result<Uuid, uuid::Error> {
+    let short = s.strip_prefix("0x").or_else(|| s.strip_prefix("0X")).unwrap_or(s);
+
+    match short.len() {
+        4 => u16::from_str_radix(short, 16).map(uuid_from_u16).or_else(|_| Uuid::parse_str(s)),
+        8 => u32::from_str_radix(short, 16).map(uuid_from_u32).or_else(|_| Uuid::parse_str(s)),
+        _ => Uuid::parse_str(s),
+    }
+}
@@ -0,0 +1,776 @@
+//! Decoding of raw characteristic values into something readable.
+
+use std::fmt;
+use std::str::FromStr;
+
+use base64::engine::general_purpose::STANDARD as BASE64;
+use base64::Engine;
+use btleplug::api::bleuuid::uuid_from_u16;
+use uuid::Uuid;
+
+/// Longest value an attribute can hold, in bytes.
+pub const MAX_LEN: usize = 512;
+
+/// How to interpret the bytes of a value.
+///
+/// Parsed from `hex`, `utf8`, `base64`, a number type such as `u8`, `i16le`, `u32be` or `f32le`,
+/// or a struct layout in the style of Python's `struct` module: an optional byte order (`<`
+/// little endian, the default, `>` or `!` big endian) followed by field codes, each optionally
+/// preceded by a repeat count. Supported codes are `x` (padding byte), `c` (character), `b`/`B`
+/// (8-bit), `h`/`H` (16-bit), `i`/`I`/`l`/`L` (32-bit), `q`/`Q` (64-bit) signed/unsigned integers,
+/// `f`/`d` (32/64-bit floats), `?` (boolean) and `s` (string, the count being its length). For
+/// example `<HhI` is a little endian u16, i16 and u32. Layouts are at most [`MAX_LEN`] bytes
+/// long.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ValueFormat {
+    Hex,
+    Utf8,
+    Base64,
+    /// A single number type, repeated if the value holds several of them
+    Number(NumberType, Endian),
+    Struct(Vec<Field>, Endian),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Endian {
+    Little,
+    Big,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NumberType {
+    U8,
+    I8,
+    U16,
+    I16,
+    U32,
+    I32,
+    U64,
+    I64,
+    F32,
+    F64,
+}
+
+impl NumberType {
+    pub fn size(self) -> usize {
+        match self {
+            NumberType::U8 | NumberType::I8 => 1,
+            NumberType::U16 | NumberType::I16 => 2,
+            NumberType::U32 | NumberType::I32 | NumberType::F32 => 4,
+            NumberType::U64 | NumberType::I64 | NumberType::F64 => 8,
+        }
+    }
+
+    /// Decodes exactly `self.size()` bytes.
+    fn decode(self, bytes: &[u8], endian: Endian) -> Value {
+        let mut buf = [0; 8];
+        buf[..bytes.len()].copy_from_slice(bytes);
+        if endian == Endian::Big {
+            buf[..bytes.len()].reverse();
+        }
+        let raw = u64::from_le_bytes(buf);
+
+        match self {
+            NumberType::U8 | NumberType::U16 | NumberType::U32 | NumberType::U64 => Value::UInt(raw),
+            NumberType::I8 => Value::Int(raw as u8 as i8 as i64),
+            NumberType::I16 => Value::Int(raw as u16 as i16 as i64),
+            NumberType::I32 => Value::Int(raw as u32 as i32 as i64),
+            NumberType::I64 => Value::Int(raw as i64),
+            NumberType::F32 => Value::Float(f32::from_bits(raw as u32) as f64),
+            NumberType::F64 => Value::Float(f64::from_bits(raw)),
+        }
+    }
+}
+
+/// A field of a struct layout.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Field {
+    Padding,
+    Char,
+    Bool,
+    Number(NumberType),
+    /// A string of the given length
+    Str(usize),
+}
+
+impl Field {
+    fn size(self) -> usize {
+        match self {
+            Field::Padding | Field::Char | Field::Bool => 1,
+            Field::Number(n) => n.size(),
+            Field::Str(len) => len,
+        }
+    }
+}
+
+/// A decoded value.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Value {
+    Text(String),
+    Int(i64),
+    UInt(u64),
+    Float(f64),
+    Bool(bool),
+    List(Vec<Value>),
+}
+
+impl fmt::Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Value::Text(s) => write!(f, "{}", s),
+            Value::Int(i) => write!(f, "{}", i),
+            Value::UInt(u) => write!(f, "{}", u),
+            Value::Float(x) => write!(f, "{}", x),
+            Value::Bool(b) => write!(f, "{}", b),
+            Value::List(values) => {
+                for (i, v) in values.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, " ")?;
+                    }
+                    write!(f, "{}", v)?;
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+impl ValueFormat {
+    pub fn decode(&self, data: &[u8]) -> Result<Value, String> {
+        match self {
+            ValueFormat::Hex => Ok(Value::Text(hex(data))),
+            ValueFormat::Utf8 => String::from_utf8(data.to_vec())
+                .map(Value::Text)
+                .map_err(|e| format!("value is not valid UTF-8: {}", e)),
+            ValueFormat::Base64 => Ok(Value::Text(BASE64.encode(data))),
+            ValueFormat::Number(n, endian) => {
+                if data.is_empty() || !data.len().is_multiple_of(n.size()) {
+                    return Err(format!("expected a multiple of {} bytes, got {}", n.size(), data.len()));
+                }
+
+                let mut values: Vec<Value> = data.chunks(n.size()).map(|c| n.decode(c, *endian)).collect();
+                if values.len() == 1 {
+                    Ok(values.remove(0))
+                } else {
+                    Ok(Value::List(values))
+                }
+            }
+            ValueFormat::Struct(fields, endian) => {
+                let size = fields.iter()
+                    .try_fold(0usize, |size, f| size.checked_add(f.size()))
+                    .ok_or("struct layout too long")?;
+                if data.len() != size {
+                    return Err(format!("expected {} bytes, got {}", size, data.len()));
+                }
+
+                let mut values = Vec::new();
+                let mut rest = data;
+                for field in fields {
+                    let (bytes, tail) = rest.split_at(field.size());
+                    rest = tail;
+
+                    match field {
+                        Field::Padding => continue,
+                        Field::Char => values.push(Value::Text((bytes[0] as char).to_string())),
+                        Field::Bool => values.push(Value::Bool(bytes[0] != 0)),
+                        Field::Number(n) => values.push(n.decode(bytes, *endian)),
+                        Field::Str(_) => values.push(Value::Text(String::from_utf8_lossy(bytes).into())),
+                    }
+                }
+
+                Ok(Value::List(values))
+            }
+        }
+    }
+}
+
+impl FromStr for ValueFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<ValueFormat, String> {
+        match s {
+            "hex" => return Ok(ValueFormat::Hex),
+            "utf8" | "text" => return Ok(ValueFormat::Utf8),
+            "base64" => return Ok(ValueFormat::Base64),
+            _ => {}
+        }
+
+        if let Some(n) = parse_number_type(s) {
+            return Ok(n);
+        }
+
+        parse_struct(s).ok_or_else(|| format!("unknown value format '{}'", s))
+    }
+}
+
+fn parse_number_type(s: &str) -> Option<ValueFormat> {
+    let (name, endian) = if let Some(name) = s.strip_suffix("le") {
+        (name, Endian::Little)
+    } else if let Some(name) = s.strip_suffix("be") {
+        (name, Endian::Big)
+    } else {
+        (s, Endian::Little)
+    };
+
+    let n = match name {
+        "u8" => NumberType::U8,
+        "i8" => NumberType::I8,
+        "u16" => NumberType::U16,
+        "i16" => NumberType::I16,
+        "u32" => NumberType::U32,
+        "i32" => NumberType::I32,
+        "u64" => NumberType::U64,
+        "i64" => NumberType::I64,
+        "f32" => NumberType::F32,
+        "f64" => NumberType::F64,
+        _ => return None,
+    };
+
+    Some(ValueFormat::Number(n, endian))
+}
+
+fn parse_struct(s: &str) -> Option<ValueFormat> {
+    let (endian, spec) = match s.chars().next()? {
+        '<' | '=' | '@' => (Endian::Little, &s[1..]),
+        '>' | '!' => (Endian::Big, &s[1..]),
+        _ => (Endian::Little, s),
+    };
+
+    let mut fields = Vec::new();
+    let mut count = String::new();
+    let mut size: usize = 0;
+    for c in spec.chars() {
+        if c.is_ascii_digit() {
+            count.push(c);
+            continue;
+        }
+
+        let n: usize = if count.is_empty() { 1 } else { count.parse().ok()? };
+        count.clear();
+
+        let field = match c {
+            'x' => Field::Padding,
+            'c' => Field::Char,
+            '?' => Field::Bool,
+            'b' => Field::Number(NumberType::I8),
+            'B' => Field::Number(NumberType::U8),
+            'h' => Field::Number(NumberType::I16),
+            'H' => Field::Number(NumberType::U16),
+            'i' | 'l' => Field::Number(NumberType::I32),
+            'I' | 'L' => Field::Number(NumberType::U32),
+            'q' => Field::Number(NumberType::I64),
+            'Q' => Field::Number(NumberType::U64),
+            'f' => Field::Number(NumberType::F32),
+            'd' => Field::Number(NumberType::F64),
+            's' => Field::Str(n),
+            _ => return None,
+        };
+
+        // A string takes the count as its length rather than as a repeat count
+        let repeat = if let Field::Str(_) = field { 1 } else { n };
+
+        // Counts come from the user: no layout can be longer than a value
+        size = size.checked_add(field.size().checked_mul(repeat)?)?;
+        if size > MAX_LEN {
+            return None;
+        }
+        fields.extend(std::iter::repeat_n(field, repeat));
+    }
+
+    if !count.is_empty() || fields.is_empty() {
+        return None;
+    }
+
+    Some(ValueFormat::Struct(fields, endian))
+}
+
+/// UUID of the Characteristic Presentation Format descriptor
+pub const PRESENTATION_FORMAT: Uuid = uuid_from_u16(0x2904);
+
+/// Contents of a Characteristic Presentation Format descriptor (0x2904), describing how the
+/// value of its characteristic is encoded.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PresentationFormat {
+    /// Format code from the Bluetooth assigned numbers, e.g. `0x0e` for a signed 16-bit integer
+    pub format: u8,
+    /// Base 10 exponent applied to integer values
+    pub exponent: i8,
+    /// Unit UUID (`0x27xx`) from the Bluetooth assigned numbers
+    pub unit: u16,
+    pub namespace: u8,
+    pub description: u16,
+}
+
+impl PresentationFormat {
+    /// Parses the 7 bytes of the descriptor value.
+    pub fn parse(data: &[u8]) -> Option<PresentationFormat> {
+        if data.len() < 7 {
+            return None;
+        }
+
+        Some(PresentationFormat {
+            format: data[0],
+            exponent: data[1] as i8,
+            unit: u16::from_le_bytes([data[2], data[3]]),
+            namespace: data[4],
+            description: u16::from_le_bytes([data[5], data[6]]),
+        })
+    }
+
+    /// Decodes a characteristic value, scaling integers by the exponent.
+    pub fn decode(&self, data: &[u8]) -> Result<Value, String> {
+        // Integer formats as (size in bytes, significant bits, signed)
+        let (size, bits, signed) = match self.format {
+            0x01 => return Ok(Value::Bool(take(data, 1)?[0] & 1 != 0)),
+            0x02 => (1, 2, false),
+            0x03 => (1, 4, false),
+            0x04 => (1, 8, false),
+            0x05 => (2, 12, false),
+            0x06 => (2, 16, false),
+            0x07 => (3, 24, false),
+            0x08 => (4, 32, false),
+            0x09 => (6, 48, false),
+            0x0a => (8, 64, false),
+            0x0c => (1, 8, true),
+            0x0d => (2, 12, true),
+            0x0e => (2, 16, true),
+            0x0f => (3, 24, true),
+            0x10 => (4, 32, true),
+            0x11 => (6, 48, true),
+            0x12 => (8, 64, true),
+            0x14 => return Ok(NumberType::F32.decode(take(data, 4)?, Endian::Little)),
+            0x15 => return Ok(NumberType::F64.decode(take(data, 8)?, Endian::Little)),
+            0x16 => {
+                let raw = u16::from_le_bytes(take(data, 2)?.try_into().unwrap());
+                return Ok(Value::Float(sfloat(raw)));
+            }
+            0x17 => {
+                let raw = u32::from_le_bytes(take(data, 4)?.try_into().unwrap());
+                return Ok(Value::Float(medfloat(raw)));
+            }
+            0x19 => return ValueFormat::Utf8.decode(data),
+            0x1a => {
+                if !data.len().is_multiple_of(2) {
+                    return Err(format!("UTF-16 value has an odd length of {} bytes", data.len()));
+                }
+                let units: Vec<u16> = data.chunks_exact(2).map(|c| u16::from_le_bytes([c[0], c[1]])).collect();
+                return String::from_utf16(&units)
+                    .map(Value::Text)
+                    .map_err(|e| format!("value is not valid UTF-16: {}", e));
+            }
+            f => return Err(format!("unsupported presentation format {:#04x}", f)),
+        };
+
+        let mut buf = [0; 8];
+        buf[..size].copy_from_slice(take(data, size)?);
+        let raw = u64::from_le_bytes(buf) & (u64::MAX >> (64 - bits));
+
+        let value = if signed {
+            // Sign extend from the top significant bit
+            let shift = 64 - bits;
+            Value::Int(((raw << shift) as i64) >> shift)
+        } else {
+            Value::UInt(raw)
+        };
+
+        Ok(match (value, self.exponent) {
+            (value, 0) => value,
+            (Value::Int(i), e) => Value::Float(scale(i as f64, e)),
+            (Value::UInt(u), e) => Value::Float(scale(u as f64, e)),
+            (value, _) => value,
+        })
+    }
+
+    /// Symbol of the unit, if it is a known one.
+    pub fn unit_symbol(&self) -> Option<&'static str> {
+        unit_symbol(self.unit)
+    }
+}
+
+/// Lowercase hex digits of `data`, two per byte with nothing in between.
+pub fn hex(data: &[u8]) -> String {
+    data.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+/// The first `size` bytes of `data`, or an error if it is too short.
+fn take(data: &[u8], size: usize) -> Result<&[u8], String> {
+    data.get(..size).ok_or_else(|| format!("expected {} bytes, got {}", size, data.len()))
+}
+
+fn scale(value: f64, exponent: i8) -> f64 {
+    // Dividing keeps values like 2345e-2 exact where multiplying by 0.01 would not
+    if exponent < 0 {
+        value / 10f64.powi(-(exponent as i32))
+    } else {
+        value * 10f64.powi(exponent as i32)
+    }
+}
+
+/// IEEE 11073 16-bit float: 4-bit exponent and 12-bit mantissa.
+fn sfloat(raw: u16) -> f64 {
+    match raw {
+        // NaN, not at this resolution, reserved
+        0x07ff..=0x0801 => f64::NAN,
+        0x07fe => f64::INFINITY,
+        0x0802 => f64::NEG_INFINITY,
+        _ => {
+            let mantissa = ((raw << 4) as i16 >> 4) as f64;
+            let exponent = (raw as i16 >> 12) as i8;
+            scale(mantissa, exponent)
+        }
+    }
+}
+
+/// IEEE 11073 32-bit float: 8-bit exponent and 24-bit mantissa.
+fn medfloat(raw: u32) -> f64 {
+    match raw {
+        0x007f_ffff..=0x0080_0001 => f64::NAN,
+        0x007f_fffe => f64::INFINITY,
+        0x0080_0002 => f64::NEG_INFINITY,
+        _ => {
+            let mantissa = ((raw << 8) as i32 >> 8) as f64;
+            let exponent = (raw >> 24) as i8;
+            scale(mantissa, exponent)
+        }
+    }
+}
+
+/// Symbol of a unit from the Bluetooth assigned numbers.
+pub fn unit_symbol(unit: u16) -> Option<&'static str> {
+    let symbol = match unit {
+        0x2700 => "",
+        0x2701 => "m",
+        0x2702 => "kg",
+        0x2703 => "s",
+        0x2704 => "A",
+        0x2705 => "K",
+        0x2706 => "mol",
+        0x2707 => "cd",
+        0x2710 => "m²",
+        0x2711 => "m³",
+        0x2712 => "m/s",
+        0x2713 => "m/s²",
+        0x2714 => "1/m",
+        0x2715 => "kg/m³",
+        0x2716 => "kg/m²",
+        0x2717 => "m³/kg",
+        0x2718 => "A/m²",
+        0x2719 => "A/m",
+        0x271a => "mol/m³",
+        0x271b => "kg/m³",
+        0x271c => "cd/m²",
+        0x2720 => "rad",
+        0x2721 => "sr",
+        0x2722 => "Hz",
+        0x2723 => "N",
+        0x2724 => "Pa",
+        0x2725 => "J",
+        0x2726 => "W",
+        0x2727 => "C",
+        0x2728 => "V",
+        0x2729 => "F",
+        0x272a => "Ω",
+        0x272b => "S",
+        0x272c => "Wb",
+        0x272d => "T",
+        0x272e => "H",
+        0x272f => "°C",
+        0x2730 => "lm",
+        0x2731 => "lx",
+        0x2732 => "Bq",
+        0x2733 => "Gy",
+        0x2734 => "Sv",
+        0x2735 => "kat",
+        0x2740 => "Pa·s",
+        0x2741 => "N·m",
+        0x2742 => "N/m",
+        0x2743 => "rad/s",
+        0x2744 => "rad/s²",
+        0x2745 => "W/m²",
+        0x2746 => "J/K",
+        0x2747 => "J/(kg·K)",
+        0x2748 => "J/kg",
+        0x2749 => "W/(m·K)",
+        0x274a => "J/m³",
+        0x274b => "V/m",
+        0x274c => "C/m³",
+        0x274d => "C/m²",
+        0x274e => "C/m²",
+        0x274f => "F/m",
+        0x2750 => "H/m",
+        0x2751 => "J/mol",
+        0x2752 => "J/(mol·K)",
+        0x2753 => "C/kg",
+        0x2754 => "Gy/s",
+        0x2755 => "W/sr",
+        0x2756 => "W/(m²·sr)",
+        0x2757 => "kat/m³",
+        0x2760 => "min",
+        0x2761 => "h",
+        0x2762 => "d",
+        0x2763 => "°",
+        0x2764 => "′",
+        0x2765 => "″",
+        0x2766 => "ha",
+        0x2767 => "L",
+        0x2768 => "t",
+        0x2780 => "bar",
+        0x2781 => "mmHg",
+        0x2782 => "Å",
+        0x2783 => "nmi",
+        0x2784 => "b",
+        0x2785 => "kn",
+        0x2786 => "Np",
+        0x2787 => "B",
+        0x27a0 => "yd",
+        0x27a1 => "pc",
+        0x27a2 => "in",
+        0x27a3 => "ft",
+        0x27a4 => "mi",
+        0x27a5 => "psi",
+        0x27a6 => "km/h",
+        0x27a7 => "mph",
+        0x27a8 => "rpm",
+        0x27a9 => "cal",
+        0x27aa => "kcal",
+        0x27ab => "kWh",
+        0x27ac => "°F",
+        0x27ad => "%",
+        0x27ae => "‰",
+        0x27af => "bpm",
+        0x27b0 => "Ah",
+        0x27b1 => "mg/dL",
+        0x27b2 => "mmol/L",
+        0x27b3 => "y",
+        0x27b4 => "mo",
+        0x27c4 => "ppm",
+        0x27c5 => "ppb",
+        _ => return None,
+    };
+
+    Some(symbol)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use Field::{Bool, Char, Padding, Str};
+    use NumberType::*;
+
+    fn parse(s: &str) -> Result<ValueFormat, String> {
+        s.parse()
+    }
+
+    #[test]
+    fn parses_named_formats() {
+        assert_eq!(parse("hex"), Ok(ValueFormat::Hex));
+        assert_eq!(parse("utf8"), Ok(ValueFormat::Utf8));
+        assert_eq!(parse("text"), Ok(ValueFormat::Utf8));
+        assert_eq!(parse("base64"), Ok(ValueFormat::Base64));
+    }
+
+    #[test]
+    fn parses_number_types() {
+        assert_eq!(parse("u8"), Ok(ValueFormat::Number(U8, Endian::Little)));
+        assert_eq!(parse("i16le"), Ok(ValueFormat::Number(I16, Endian::Little)));
+        assert_eq!(parse("u32be"), Ok(ValueFormat::Number(U32, Endian::Big)));
+        assert_eq!(parse("f64"), Ok(ValueFormat::Number(F64, Endian::Little)));
+        assert!(parse("u24").is_err());
+        assert!(parse("u16xe").is_err());
+    }
+
+    #[test]
+    fn parses_struct_byte_orders() {
+        let fields = vec![Field::Number(U16)];
+        assert_eq!(parse("H"), Ok(ValueFormat::Struct(fields.clone(), Endian::Little)));
+        for s in ["<H", "=H", "@H"] {
+            assert_eq!(parse(s), Ok(ValueFormat::Struct(fields.clone(), Endian::Little)), "{}", s);
+        }
+        for s in [">H", "!H"] {
+            assert_eq!(parse(s), Ok(ValueFormat::Struct(fields.clone(), Endian::Big)), "{}", s);
+        }
+    }
+
+    #[test]
+    fn parses_struct_fields() {
+        assert_eq!(
+            parse("<xc?bBhHiIlLqQfd"),
+            Ok(ValueFormat::Struct(vec![
+                Padding, Char, Bool,
+                Field::Number(I8), Field::Number(U8),
+                Field::Number(I16), Field::Number(U16),
+                Field::Number(I32), Field::Number(U32), Field::Number(I32), Field::Number(U32),
+                Field::Number(I64), Field::Number(U64),
+                Field::Number(F32), Field::Number(F64),
+            ], Endian::Little))
+        );
+    }
+
+    #[test]
+    fn parses_struct_counts() {
+        assert_eq!(
+            parse("2H3x"),
+            Ok(ValueFormat::Struct(vec![Field::Number(U16), Field::Number(U16), Padding, Padding, Padding], Endian::Little))
+        );
+        // The count of a string is its length
+        assert_eq!(parse(">4sB"), Ok(ValueFormat::Struct(vec![Str(4), Field::Number(U8)], Endian::Big)));
+        assert_eq!(parse("s"), Ok(ValueFormat::Struct(vec![Str(1)], Endian::Little)));
+        assert_eq!(parse("10s"), Ok(ValueFormat::Struct(vec![Str(10)], Endian::Little)));
+    }
+
+    #[test]
+    fn rejects_invalid_structs() {
+        for s in ["", "<", "Hz", "H 2", "2H3", "0H", "-1H"] {
+            assert!(parse(s).is_err(), "{}", s);
+        }
+    }
+
+    #[test]
+    fn rejects_structs_longer_than_a_value() {
+        assert!(parse("256H").is_ok());
+        assert!(parse("257H").is_err());
+        assert!(parse("512s").is_ok());
+        assert!(parse("512sB").is_err());
+        assert!(parse("4000000000H").is_err());
+        assert!(parse("18446744073709551615s").is_err());
+        assert!(parse("99999999999999999999999H").is_err());
+    }
+
+    #[test]
+    fn decodes_text_formats() {
+        assert_eq!(ValueFormat::Hex.decode(&[0x01, 0xab]), Ok(Value::Text("01ab".into())));
+        assert_eq!(ValueFormat::Utf8.decode(b"hi"), Ok(Value::Text("hi".into())));
+        assert!(ValueFormat::Utf8.decode(&[0xff]).is_err());
+        assert_eq!(ValueFormat::Base64.decode(b"hi"), Ok(Value::Text("aGk=".into())));
+    }
+
+    #[test]
+    fn formats_hex() {
+        assert_eq!(hex(&[]), "");
+        assert_eq!(hex(&[0x00, 0x0f, 0xab, 0xff]), "000fabff");
+    }
+
+    #[test]
+    fn decodes_numbers() {
+        let decode = |s: &str, data: &[u8]| parse(s).unwrap().decode(data);
+        assert_eq!(decode("u16", &[0x34, 0x12]), Ok(Value::UInt(0x1234)));
+        assert_eq!(decode("u16be", &[0x12, 0x34]), Ok(Value::UInt(0x1234)));
+        assert_eq!(decode("i8", &[0xff]), Ok(Value::Int(-1)));
+        assert_eq!(decode("i16", &[0x29, 0xfb]), Ok(Value::Int(-1239)));
+        assert_eq!(decode("i32be", &[0xff, 0xff, 0xff, 0xfe]), Ok(Value::Int(-2)));
+        assert_eq!(decode("f32", &1.5f32.to_le_bytes()), Ok(Value::Float(1.5)));
+        assert_eq!(decode("f64be", &(-0.25f64).to_be_bytes()), Ok(Value::Float(-0.25)));
+        // Several values make a list
+        assert_eq!(decode("u8", &[1, 2]), Ok(Value::List(vec![Value::UInt(1), Value::UInt(2)])));
+        assert!(decode("u16", &[1, 2, 3]).is_err());
+        assert!(decode("u16", &[]).is_err());
+    }
+
+    #[test]
+    fn decodes_structs() {
+        let format = parse("<Hhx?c3s").unwrap();
+        let data = [0x01, 0x00, 0xfe, 0xff, 0xaa, 0x01, b'A', b'a', b'b', b'c'];
+        assert_eq!(
+            format.decode(&data),
+            Ok(Value::List(vec![
+                Value::UInt(1),
+                Value::Int(-2),
+                Value::Bool(true),
+                Value::Text("A".into()),
+                Value::Text("abc".into()),
+            ]))
+        );
+        assert_eq!(parse(">H").unwrap().decode(&[0x01, 0x00]), Ok(Value::List(vec![Value::UInt(256)])));
+        assert!(format.decode(&data[..9]).is_err());
+    }
+
+    #[test]
+    fn decoding_huge_layouts_fails() {
+        let format = ValueFormat::Struct(vec![Str(usize::MAX), Str(1)], Endian::Little);
+        assert!(format.decode(&[0]).is_err());
+    }
+
+    #[test]
+    fn displays_values() {
+        assert_eq!(Value::List(vec![Value::UInt(1), Value::Text("a".into()), Value::Bool(false)]).to_string(), "1 a false");
+        assert_eq!(Value::Float(23.45).to_string(), "23.45");
+    }
+
+    fn presentation(format: u8, exponent: i8) -> PresentationFormat {
+        PresentationFormat { format, exponent, unit: 0x2700, namespace: 1, description: 0 }
+    }
+
+    fn float(format: u8, data: &[u8]) -> f64 {
+        match presentation(format, 0).decode(data) {
+            Ok(Value::Float(f)) => f,
+            other => panic!("not a float: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn parses_presentation_formats() {
+        let p = PresentationFormat::parse(&[0x0e, 0xfe, 0x2f, 0x27, 0x01, 0x00, 0x00]).unwrap();
+        assert_eq!(p, PresentationFormat { format: 0x0e, exponent: -2, unit: 0x272f, namespace: 1, description: 0 });
+        assert_eq!(p.unit_symbol(), Some("°C"));
+        assert_eq!(PresentationFormat::parse(&[0x0e, 0xfe]), None);
+    }
+
+    #[test]
+    fn decodes_sfloats() {
+        assert_eq!(float(0x16, &[0x6d, 0xf1]), 36.5);
+        assert_eq!(float(0x16, &[0xf1, 0xff]), -1.5);
+        assert_eq!(float(0x16, &[0x0c, 0x20]), 1200.0);
+        assert_eq!(float(0x16, &[0x00, 0x00]), 0.0);
+
+        // NaN, NRes and the reserved value
+        for raw in [0x07ffu16, 0x0800, 0x0801] {
+            assert!(float(0x16, &raw.to_le_bytes()).is_nan(), "{:#06x}", raw);
+        }
+        assert_eq!(float(0x16, &[0xfe, 0x07]), f64::INFINITY);
+        assert_eq!(float(0x16, &[0x02, 0x08]), f64::NEG_INFINITY);
+        assert!(presentation(0x16, 0).decode(&[0x6d]).is_err());
+    }
+
+    #[test]
+    fn decodes_medfloats() {
+        assert_eq!(float(0x17, &[0x29, 0x09, 0x00, 0xfe]), 23.45);
+        assert_eq!(float(0x17, &[0xfb, 0xff, 0xff, 0x03]), -5000.0);
+
+        for raw in [0x007f_ffffu32, 0x0080_0000, 0x0080_0001] {
+            assert!(float(0x17, &raw.to_le_bytes()).is_nan(), "{:#010x}", raw);
+        }
+        assert_eq!(float(0x17, &0x007f_fffeu32.to_le_bytes()), f64::INFINITY);
+        assert_eq!(float(0x17, &0x0080_0002u32.to_le_bytes()), f64::NEG_INFINITY);
+        assert!(presentation(0x17, 0).decode(&[0x29, 0x09, 0x00]).is_err());
+    }
+
+    #[test]
+    fn scales_integers_by_the_exponent() {
+        assert_eq!(presentation(0x0e, -2).decode(&[0x29, 0x09]), Ok(Value::Float(23.45)));
+        assert_eq!(presentation(0x0e, -2).decode(&[0xd7, 0xf6]), Ok(Value::Float(-23.45)));
+        assert_eq!(presentation(0x06, 1).decode(&[0x05, 0x00]), Ok(Value::Float(50.0)));
+        assert_eq!(presentation(0x04, 0).decode(&[0x64]), Ok(Value::UInt(100)));
+        assert_eq!(presentation(0x0c, 0).decode(&[0xff]), Ok(Value::Int(-1)));
+    }
+
+    #[test]
+    fn decodes_presentation_integers() {
+        // Bits beyond the significant ones are ignored, and signed values sign extended from them
+        assert_eq!(presentation(0x05, 0).decode(&[0xff, 0xff]), Ok(Value::UInt(0x0fff)));
+        assert_eq!(presentation(0x0d, 0).decode(&[0xff, 0x0f]), Ok(Value::Int(-1)));
+        assert_eq!(presentation(0x0f, 0).decode(&[0x00, 0x00, 0x80]), Ok(Value::Int(-0x80_0000)));
+        assert_eq!(presentation(0x01, 0).decode(&[0x01]), Ok(Value::Bool(true)));
+        assert!(presentation(0x08, 0).decode(&[0x01, 0x02]).is_err());
+        assert!(presentation(0x1b, 0).decode(&[0x01]).is_err());
+    }
+
+    #[test]
+    fn decodes_presentation_strings() {
+        assert_eq!(presentation(0x19, 0).decode(b"abc"), Ok(Value::Text("abc".into())));
+        assert_eq!(presentation(0x1a, 0).decode(&[b'h', 0, b'i', 0]), Ok(Value::Text("hi".into())));
+        assert!(presentation(0x1a, 0).decode(&[b'h', 0, b'i']).is_err());
+        // An unpaired surrogate
+        assert!(presentation(0x1a, 0).decode(&[0x00, 0xd8]).is_err());
+    }
+}
@@ -0,0 +1,134 @@
+//! Splitting long values into writes that fit the MTU, against a scripted mock peripheral.
+
+use std::time::Duration;
+
+use ble_util::backend::{MockAdapter, MockBackend, MockPeripheral};
+use ble_util::{CharPropFlags, Chunking, Device, Session, Uuid, ValueNotification, WriteType};
+use tokio::time;
+use tokio_stream::StreamExt;
+
+const ADDRESS: &str = "aa:bb:cc:dd:ee:10";
+
+const SERVICE: Uuid = Uuid::from_u128(0x6e400001_b5a3_f393_e0a9_e50e24dcca9e);
+const RX: Uuid = Uuid::from_u128(0x6e400002_b5a3_f393_e0a9_e50e24dcca9e);
+const TX: Uuid = Uuid::from_u128(0x6e400003_b5a3_f393_e0a9_e50e24dcca9e);
+
+const TIMEOUT: Duration = Duration::from_secs(2);
+
+/// A peripheral taking writes on RX and notifying on TX, and a device connected to it.
+async fn connect() -> (MockPeripheral, Device) {
+    let peripheral = MockPeripheral::new([0xaa, 0xbb, 0xcc, 0xdd, 0xee, 0x10].into())
+        .name("chunking")
+        .service(SERVICE)
+        .characteristic(SERVICE, RX, CharPropFlags::WRITE | CharPropFlags::WRITE_WITHOUT_RESPONSE, &[])
+        .characteristic(SERVICE, TX, CharPropFlags::NOTIFY, &[]);
+    let backend = MockBackend::new().with_adapter(MockAdapter::new("mock").with_peripheral(peripheral.clone()));
+
+    let session = Session::with_backend(Box::new(backend)).await.unwrap();
+    let dev = session.connect(&ADDRESS.parse().unwrap(), TIMEOUT).await.unwrap();
+    (peripheral, dev)
+}
+
+fn data(len: usize) -> Vec<u8> {
+    (0..len).map(|i| i as u8).collect()
+}
+
+#[tokio::test]
+async fn splits_values_to_fit_the_mtu() {
+    let (peripheral, dev) = connect().await;
+
+    // 23 byte MTU less the 3 byte ATT header
+    let chunks = dev.write_chunked(RX, &data(50), WriteType::WithoutResponse, &Chunking::default()).await.unwrap();
+    assert_eq!(chunks, 3);
+    let sizes: Vec<usize> = peripheral.writes(RX).iter().map(Vec::len).collect();
+    assert_eq!(sizes, [20, 20, 10]);
+    assert_eq!(peripheral.writes(RX).concat(), data(50));
+}
+
+#[tokio::test]
+async fn sizes_chunks_for_the_given_mtu() {
+    let (peripheral, dev) = connect().await;
+
+    let chunking = Chunking { mtu: Some(30), ..Default::default() };
+    let mut progress = Vec::new();
+    let chunks = dev.write_chunked_with_progress(RX, &data(60), WriteType::WithResponse, &chunking, |n| {
+        progress.push(n)
+    }).await.unwrap();
+    assert_eq!(chunks, 3);
+    assert_eq!(progress, [27, 54, 60]);
+    let sizes: Vec<usize> = peripheral.writes(RX).iter().map(Vec::len).collect();
+    assert_eq!(sizes, [27, 27, 6]);
+}
+
+#[tokio::test]
+async fn writes_empty_values() {
+    let (peripheral, dev) = connect().await;
+
+    let chunks = dev.write_chunked(RX, &[], WriteType::WithResponse, &Chunking::default()).await.unwrap();
+    assert_eq!(chunks, 1);
+    assert_eq!(peripheral.writes(RX), [Vec::<u8>::new()]);
+}
+
+#[tokio::test]
+async fn waits_for_acknowledgements() {
+    let (peripheral, dev) = connect().await;
+
+    let chunking = Chunking { ack: Some(TX), ..Default::default() };
+    let data = data(45);
+    let write = dev.write_chunked(RX, &data, WriteType::WithoutResponse, &chunking);
+
+    // Only acknowledge each chunk once it's there, checking the next one waits for it
+    let acknowledge = async {
+        for expected in 1..=3 {
+            while peripheral.writes(RX).len() < expected {
+                time::sleep(Duration::from_millis(10)).await;
+            }
+            time::sleep(Duration::from_millis(100)).await;
+            assert_eq!(peripheral.writes(RX).len(), expected);
+            peripheral.notify(TX, &[expected as u8]);
+        }
+    };
+
+    let (chunks, _) = tokio::join!(time::timeout(TIMEOUT, write), acknowledge);
+    assert_eq!(chunks.unwrap().unwrap(), 3);
+
+    // Unsubscribed from again once done
+    let mut notifications = dev.notifications().await.unwrap();
+    peripheral.notify(TX, &[0]);
+    assert!(time::timeout(Duration::from_millis(200), notifications.next()).await.is_err());
+}
+
+#[tokio::test]
+async fn unsubscribes_from_acknowledgements_when_writes_fail() {
+    let (peripheral, dev) = connect().await;
+
+    // Too long for a write without response
+    let chunking = Chunking { mtu: Some(100), ack: Some(TX), ..Default::default() };
+    assert!(dev.write_chunked(RX, &data(50), WriteType::WithoutResponse, &chunking).await.is_err());
+
+    let mut notifications = dev.notifications().await.unwrap();
+    peripheral.notify(TX, &[0]);
+    assert!(time::timeout(Duration::from_millis(200), notifications.next()).await.is_err());
+}
+
+#[tokio::test]
+async fn keeps_existing_subscriptions_to_acknowledgements() {
+    let (peripheral, dev) = connect().await;
+    peripheral.clone().on_write(RX, |data| vec![ValueNotification { uuid: TX, value: data.to_vec() }]);
+
+    let mut notifications = dev.notifications().await.unwrap();
+    dev.subscribe(TX).await.unwrap();
+
+    // The device acknowledges by echoing, as a Nordic UART device may
+    let chunking = Chunking { ack: Some(TX), ..Default::default() };
+    for line in [&b"first"[..], b"second"] {
+        dev.write_chunked(RX, line, WriteType::WithoutResponse, &chunking).await.unwrap();
+        let n = time::timeout(TIMEOUT, notifications.next()).await.unwrap().unwrap();
+        assert_eq!(n.value, line);
+    }
+
+    assert!(dev.is_subscribed(TX));
+    peripheral.notify(TX, &[1]);
+    let n = time::timeout(TIMEOUT, notifications.next()).await.unwrap().unwrap();
+    assert_eq!(n.value, [1]);
+}
@@ -0,0 +1,76 @@
+//! Sending files with the command line tool to the devices of the demo mock backend.
+
+use std::fs;
+use std::path::PathBuf;
+use std::process::{Command, Output};
+
+use serde_json::Value;
+
+/// Receives a size header then the file, answering with its CRC32.
+const CONFIG: &str = "aa:bb:cc:dd:ee:03";
+/// Echoes what it receives.
+const UART: &str = "aa:bb:cc:dd:ee:01";
+
+/// Writes a file for a test to send, named after it.
+fn file(test: &str, data: &[u8]) -> PathBuf {
+    let path = std::env::temp_dir().join(format!("ble-util-{}-{}", test, std::process::id()));
+    fs::write(&path, data).unwrap();
+    path
+}
+
+fn send_file(path: &PathBuf, args: &[&str]) -> Output {
+    Command::new(env!("CARGO_BIN_EXE_ble-util"))
+        .args(["--backend", "mock", "--format", "json", "send-file"])
+        .args(args)
+        .arg(path)
+        .output()
+        .unwrap()
+}
+
+fn record(output: &Output) -> Value {
+    serde_json::from_slice(&output.stdout).unwrap()
+}
+
+#[test]
+fn sends_files_and_verifies_the_checksum() {
+    let data: Vec<u8> = (0..1000).map(|i| (i % 251) as u8).collect();
+    let path = file("verified", &data);
+    let output = send_file(&path, &[CONFIG, "--size-header"]);
+    fs::remove_file(&path).ok();
+
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    let record = record(&output);
+    assert_eq!(record["length"], 1000);
+    // 247 byte MTU less the ATT header
+    assert_eq!(record["chunks"], 5);
+    assert_eq!(record["checksum"], "crc32");
+    assert_eq!(record["expected"], format!("{:08x}", crc32fast::hash(&data)));
+    // The device answers in little endian
+    assert_eq!(record["received"], format!("{:08x}", crc32fast::hash(&data).swap_bytes()));
+    assert_eq!(record["verified"], true);
+}
+
+#[test]
+fn sends_files_without_verifying() {
+    let path = file("unverified", b"hello");
+    let output = send_file(&path, &[UART, "--verify", "none"]);
+    fs::remove_file(&path).ok();
+
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    let record = record(&output);
+    assert_eq!(record["checksum"], Value::Null);
+    assert_eq!(record["verified"], Value::Null);
+}
+
+#[test]
+fn wrong_checksums_are_a_mismatch() {
+    // Echoed back as is, which has the length of a CRC32 but not its value
+    let path = file("mismatch", b"abcd");
+    let output = send_file(&path, &[UART]);
+    fs::remove_file(&path).ok();
+
+    assert_eq!(output.status.code(), Some(9));
+    let record = record(&output);
+    assert_eq!(record["received"], "61626364");
+    assert_eq!(record["verified"], false);
+}
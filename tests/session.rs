@@ -0,0 +1,204 @@
+//! Scanning for and talking to the devices of the demo mock backend through a `Session`.
+
+use std::time::Duration;
+
+use ble_util::backend::MockBackend;
+use ble_util::{
+    AdapterSelector, BDAddr, BleUtilError, DeviceFilter, ScanEventKind, Session, Target, Uuid, WriteType,
+};
+use btleplug::api::bleuuid::uuid_from_u16;
+use regex::Regex;
+use tokio::time;
+use tokio_stream::StreamExt;
+
+const UART: &str = "aa:bb:cc:dd:ee:01";
+const SENSOR: &str = "aa:bb:cc:dd:ee:02";
+
+const BATTERY_LEVEL: Uuid = uuid_from_u16(0x2a19);
+const MANUFACTURER: Uuid = uuid_from_u16(0x2a29);
+const SERIAL_NUMBER: Uuid = uuid_from_u16(0x2a25);
+const TEMPERATURE: Uuid = uuid_from_u16(0x2a6e);
+const NUS: Uuid = Uuid::from_u128(0x6e400001_b5a3_f393_e0a9_e50e24dcca9e);
+const NUS_RX: Uuid = Uuid::from_u128(0x6e400002_b5a3_f393_e0a9_e50e24dcca9e);
+const NUS_TX: Uuid = Uuid::from_u128(0x6e400003_b5a3_f393_e0a9_e50e24dcca9e);
+
+const TIMEOUT: Duration = Duration::from_secs(2);
+
+async fn session() -> Session {
+    Session::with_backend(Box::new(MockBackend::demo())).await.unwrap()
+}
+
+fn address(s: &str) -> BDAddr {
+    BDAddr::from_str_delim(s).unwrap()
+}
+
+#[tokio::test]
+async fn selects_adapters() {
+    let second = AdapterSelector::Name("mock1".into());
+    assert!(Session::with_adapter(Box::new(MockBackend::demo()), &second).await.is_ok());
+
+    let missing = AdapterSelector::Index(2);
+    let res = Session::with_adapter(Box::new(MockBackend::demo()), &missing).await;
+    assert!(matches!(res, Err(BleUtilError::NoAdapter)));
+}
+
+#[tokio::test]
+async fn scan_finds_demo_devices() {
+    let session = session().await;
+    let devices = session.scan(Duration::from_secs(1), &DeviceFilter::default()).await.unwrap();
+
+    assert_eq!(devices.len(), 8);
+    let uart = devices.iter().find(|d| d.address == address(UART)).unwrap();
+    assert_eq!(uart.local_name.as_deref(), Some("ble-util mock"));
+    assert_eq!(uart.rssi, Some(-48));
+    assert_eq!(uart.tx_power, Some(-4));
+    assert_eq!(uart.services, vec![NUS]);
+}
+
+#[tokio::test]
+async fn scan_filters_devices() {
+    let session = session().await;
+
+    let by_name = DeviceFilter { name: Some(Regex::new("^ble-util").unwrap()), ..Default::default() };
+    let devices = session.scan(Duration::from_secs(1), &by_name).await.unwrap();
+    assert_eq!(devices.len(), 3);
+
+    let by_service = DeviceFilter { services: vec![NUS], ..Default::default() };
+    let devices = session.scan(Duration::from_secs(1), &by_service).await.unwrap();
+    assert_eq!(devices.len(), 2);
+
+    // Only sent as service data, by the BTHome sensor
+    let by_service_data = DeviceFilter { services: vec![uuid_from_u16(0xfcd2)], ..Default::default() };
+    let devices = session.scan(Duration::from_secs(1), &by_service_data).await.unwrap();
+    assert_eq!(devices.len(), 1);
+    assert_eq!(devices[0].address, address(SENSOR));
+
+    let by_rssi = DeviceFilter { min_rssi: Some(-60), ..Default::default() };
+    let devices = session.scan(Duration::from_secs(1), &by_rssi).await.unwrap();
+    assert!(devices.iter().all(|d| d.rssi >= Some(-60)));
+    assert_eq!(devices.len(), 2);
+
+    let by_company = DeviceFilter { manufacturers: vec![0x004c], ..Default::default() };
+    let devices = session.scan(Duration::from_secs(1), &by_company).await.unwrap();
+    assert_eq!(devices.len(), 1);
+    assert_eq!(devices[0].address, address("aa:bb:cc:dd:ee:04"));
+}
+
+#[tokio::test]
+async fn watch_reports_discoveries_and_updates() {
+    let session = session().await;
+    let mut scanner = session.watch(DeviceFilter::default()).await.unwrap();
+
+    let mut discovered = Vec::new();
+    let mut updated = false;
+    while discovered.len() < 8 || !updated {
+        let event = time::timeout(TIMEOUT, scanner.next()).await.unwrap().unwrap().unwrap();
+        match event.kind {
+            ScanEventKind::Discovered => discovered.push(event.device.address),
+            // The iBeacon keeps advertising
+            ScanEventKind::Updated => updated = true,
+        }
+    }
+    scanner.stop().await.unwrap();
+
+    assert!(discovered.contains(&address(SENSOR)));
+}
+
+#[tokio::test]
+async fn finds_devices_by_address_name_and_service() {
+    let session = session().await;
+
+    let targets = [UART, "name:ble-util m*", "ble-util mo?k", "service:6e400001-b5a3-f393-e0a9-e50e24dcca9e"];
+    for target in targets {
+        let target: Target = target.parse().unwrap();
+        let found = session.find(&target, TIMEOUT).await.unwrap();
+        assert_eq!(found.address(), address(UART), "{}", target);
+    }
+
+    // Shows up after a while
+    let sensor = session.find(&SENSOR.parse().unwrap(), TIMEOUT).await.unwrap();
+    assert_eq!(sensor.address(), address(SENSOR));
+}
+
+#[tokio::test]
+async fn missing_device_is_not_found() {
+    let session = session().await;
+    let target: Target = "name:nothing".parse().unwrap();
+
+    let res = session.find(&target, Duration::from_millis(300)).await;
+    assert!(matches!(res, Err(BleUtilError::DeviceNotFound(t)) if t == target));
+}
+
+#[tokio::test]
+async fn connects_and_discovers_services() {
+    let session = session().await;
+    let dev = session.connect(&UART.parse().unwrap(), TIMEOUT).await.unwrap();
+
+    assert_eq!(dev.address(), address(UART));
+    assert!(dev.peripheral().is_connected().await.unwrap());
+    assert_eq!(dev.mtu(), 23);
+
+    let services: Vec<Uuid> = dev.services().iter().map(|s| s.uuid).collect();
+    assert_eq!(services.len(), 3);
+    assert!(services.contains(&uuid_from_u16(0x180f)));
+    assert!(services.contains(&NUS));
+}
+
+#[tokio::test]
+async fn reads_characteristics() {
+    let session = session().await;
+    let dev = session.connect(&UART.parse().unwrap(), TIMEOUT).await.unwrap();
+
+    assert_eq!(dev.read(BATTERY_LEVEL).await.unwrap(), [100]);
+    assert_eq!(dev.read(MANUFACTURER).await.unwrap(), b"ble-util");
+
+    assert!(matches!(dev.read(SERIAL_NUMBER).await, Err(BleUtilError::Backend(_))));
+    assert!(matches!(dev.read(NUS_RX).await, Err(BleUtilError::NotReadable(u)) if u == NUS_RX));
+    assert!(matches!(dev.read(TEMPERATURE).await, Err(BleUtilError::CharacteristicNotFound(_))));
+}
+
+#[tokio::test]
+async fn writes_and_receives_notifications() {
+    let session = session().await;
+    let dev = session.connect(&UART.parse().unwrap(), TIMEOUT).await.unwrap();
+
+    let mut notifications = dev.notifications().await.unwrap();
+    dev.subscribe(NUS_TX).await.unwrap();
+    dev.write(NUS_RX, b"hello", WriteType::WithoutResponse).await.unwrap();
+
+    let n = time::timeout(TIMEOUT, notifications.next()).await.unwrap().unwrap();
+    assert_eq!(n.uuid, NUS_TX);
+    assert_eq!(n.value, b"hello");
+
+    assert!(matches!(dev.write(BATTERY_LEVEL, &[1], WriteType::WithResponse).await, Err(BleUtilError::NotWritable(_))));
+    assert!(matches!(dev.subscribe(MANUFACTURER).await, Err(BleUtilError::NotSubscribable(_))));
+}
+
+#[tokio::test]
+async fn subscribes_to_periodic_notifications() {
+    let session = session().await;
+    let dev = session.connect(&SENSOR.parse().unwrap(), TIMEOUT).await.unwrap();
+
+    let mut notifications = dev.notifications().await.unwrap();
+    dev.subscribe(TEMPERATURE).await.unwrap();
+    for _ in 0..2 {
+        let n = time::timeout(TIMEOUT, notifications.next()).await.unwrap().unwrap();
+        assert_eq!(n.uuid, TEMPERATURE);
+        assert_eq!(n.value.len(), 2);
+    }
+
+    dev.unsubscribe(TEMPERATURE).await.unwrap();
+    // Drain what was sent before unsubscribing, then nothing more comes
+    while time::timeout(Duration::from_millis(100), notifications.next()).await.is_ok() {}
+    assert!(time::timeout(Duration::from_millis(1500), notifications.next()).await.is_err());
+}
+
+#[tokio::test]
+async fn disconnects() {
+    let session = session().await;
+    let dev = session.connect(&UART.parse().unwrap(), TIMEOUT).await.unwrap();
+
+    dev.disconnect().await.unwrap();
+    assert!(!dev.peripheral().is_connected().await.unwrap());
+    assert!(matches!(dev.read(BATTERY_LEVEL).await, Err(BleUtilError::Backend(_))));
+}